@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("descriptor.bin"))
+        .compile(&["api.proto", "health.proto", "reflection.proto"], &["."])?;
+    Ok(())
+}
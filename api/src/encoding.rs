@@ -0,0 +1,69 @@
+//! astrobase-api encodings of binary keys and values in text.
+
+use std::str::FromStr;
+
+/// Represents the ways of writing bytes as text.
+#[derive(Clone, Copy)]
+pub enum Encoding {
+    /// Text as is; invalid UTF-8 is replaced when printed.
+    Utf8,
+    Hex,
+    Base64,
+}
+
+impl Encoding {
+    pub const NAMES: &'static [&'static str] = &["utf8", "hex", "base64"];
+
+    /// Converts text to bytes.
+    pub fn decode(self, text: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Hex => decode_hex(text).ok_or_else(|| Error::Hex(text.into())),
+            Encoding::Base64 => base64::decode(text).map_err(|_| Error::Base64(text.into())),
+        }
+    }
+
+    /// Converts bytes to text.
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            Encoding::Base64 => base64::encode(bytes),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "utf8" => Ok(Encoding::Utf8),
+            "hex" => Ok(Encoding::Hex),
+            "base64" => Ok(Encoding::Base64),
+            _ => Err(Error::Unknown(s.into())),
+        }
+    }
+}
+
+/// Parses pairs of hex digits.
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Represents errors of decoding text.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid hex '{0}'")]
+    Hex(String),
+    #[error("invalid base64 '{0}'")]
+    Base64(String),
+    #[error("unknown encoding '{0}'")]
+    Unknown(String),
+}
@@ -0,0 +1,327 @@
+//! astrobase-api: the `gRPC` API shared by the server and the client.
+
+#![forbid(unsafe_code)]
+#![deny(warnings)]
+// For the generated code
+#![allow(clippy::derive_partial_eq_without_eq)]
+#![allow(clippy::too_many_lines)]
+#![allow(clippy::wildcard_imports)]
+#![allow(clippy::similar_names)]
+#![allow(clippy::default_trait_access)]
+
+/// The messages and services generated from `api.proto`.
+pub mod proto {
+    tonic::include_proto!("api");
+}
+
+/// The standard `gRPC` health checking service.
+pub mod health {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+/// The standard `gRPC` server reflection service.
+pub mod reflection {
+    tonic::include_proto!("grpc.reflection.v1alpha");
+}
+
+/// The encoded `FileDescriptorSet` of all the protos above, served by reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/descriptor.bin"));
+
+pub mod encoding;
+#[cfg(test)]
+mod tests;
+
+pub use encoding::Encoding;
+
+pub const MAX_KEY_LEN: usize = 1024;
+pub const MAX_VALUE_LEN: usize = 1024 * 1024;
+
+/// The metadata of a write rejected by a follower: the endpoint of the leader.
+pub const LEADER_METADATA: &str = "x-astrobase-leader";
+/// The metadata of a read choosing its `Consistency` in a cluster.
+pub const CONSISTENCY_METADATA: &str = "x-astrobase-consistency";
+
+/// Represents the guarantee of a read in a cluster.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Consistency {
+    /// Sees every write completed before the read; served by the leader only.
+    #[default]
+    Linearizable,
+    /// Served by any member, may miss the latest writes.
+    Stale,
+}
+
+impl Consistency {
+    pub const NAMES: &'static [&'static str] = &["linearizable", "stale"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Consistency::Linearizable => "linearizable",
+            Consistency::Stale => "stale",
+        }
+    }
+}
+
+impl std::str::FromStr for Consistency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "linearizable" => Ok(Consistency::Linearizable),
+            "stale" => Ok(Consistency::Stale),
+            _ => Err(format!("unknown consistency '{}'", s)),
+        }
+    }
+}
+
+/// Represents what a batch does with keys which already exist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conflict {
+    /// Fails the whole batch.
+    InsertOnly,
+    Overwrite,
+    Skip,
+}
+
+impl Conflict {
+    /// Decodes the mode of a request, None if unknown.
+    pub fn from_proto(code: i32) -> Option<Self> {
+        match proto::Conflict::from_i32(code)? {
+            proto::Conflict::InsertOnly => Some(Conflict::InsertOnly),
+            proto::Conflict::Overwrite => Some(Conflict::Overwrite),
+            proto::Conflict::Skip => Some(Conflict::Skip),
+        }
+    }
+}
+
+impl From<Conflict> for proto::Conflict {
+    fn from(conflict: Conflict) -> Self {
+        match conflict {
+            Conflict::InsertOnly => proto::Conflict::InsertOnly,
+            Conflict::Overwrite => proto::Conflict::Overwrite,
+            Conflict::Skip => proto::Conflict::Skip,
+        }
+    }
+}
+
+/// Represents the counts of records written by a batch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Written {
+    pub inserted: u64,
+    pub updated: u64,
+    pub skipped: u64,
+}
+
+/// Represents a value a key had.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    /// None if the key was deleted.
+    pub value: Option<Vec<u8>>,
+    /// The place of the write among the writes of the namespace.
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch, 0 if unknown.
+    pub timestamp: u64,
+}
+
+impl From<proto::Version> for Version {
+    fn from(version: proto::Version) -> Self {
+        Version {
+            value: (!version.deleted).then_some(version.value),
+            seq: version.seq,
+            timestamp: version.timestamp,
+        }
+    }
+}
+
+impl From<Version> for proto::Version {
+    fn from(version: Version) -> Self {
+        proto::Version {
+            deleted: version.value.is_none(),
+            value: version.value.unwrap_or_default(),
+            seq: version.seq,
+            timestamp: version.timestamp,
+        }
+    }
+}
+
+/// Represents the moment a point-in-time read sees a key at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum At {
+    /// Right after the write with the sequence number.
+    Seq(u64),
+    /// At the time, in milliseconds since the Unix epoch.
+    Timestamp(u64),
+}
+
+impl At {
+    /// Returns whether the version was written by the moment.
+    pub fn sees(self, version: &Version) -> bool {
+        match self {
+            At::Seq(seq) => version.seq <= seq,
+            At::Timestamp(timestamp) => version.timestamp <= timestamp,
+        }
+    }
+}
+
+impl From<At> for proto::moment::At {
+    fn from(at: At) -> Self {
+        match at {
+            At::Seq(seq) => proto::moment::At::Seq(seq),
+            At::Timestamp(timestamp) => proto::moment::At::Timestamp(timestamp),
+        }
+    }
+}
+
+impl From<proto::moment::At> for At {
+    fn from(at: proto::moment::At) -> Self {
+        match at {
+            proto::moment::At::Seq(seq) => At::Seq(seq),
+            proto::moment::At::Timestamp(timestamp) => At::Timestamp(timestamp),
+        }
+    }
+}
+
+/// Represents the reasons the server rejects a command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    NotFound,
+    AlreadyExists,
+    /// The new value is the same as the old one.
+    Identical,
+    Invalid,
+    Other,
+}
+
+impl Failure {
+    /// Decodes the failure of a reply, unknown ones are `Other`.
+    pub fn from_proto(code: i32) -> Self {
+        match proto::Failure::from_i32(code) {
+            Some(proto::Failure::NotFound) => Failure::NotFound,
+            Some(proto::Failure::AlreadyExists) => Failure::AlreadyExists,
+            Some(proto::Failure::Identical) => Failure::Identical,
+            Some(proto::Failure::Invalid) => Failure::Invalid,
+            _ => Failure::Other,
+        }
+    }
+}
+
+impl From<Failure> for proto::Failure {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::NotFound => proto::Failure::NotFound,
+            Failure::AlreadyExists => proto::Failure::AlreadyExists,
+            Failure::Identical => proto::Failure::Identical,
+            Failure::Invalid => proto::Failure::Invalid,
+            Failure::Other => proto::Failure::Other,
+        }
+    }
+}
+
+/// Represents a command rejected by the server; `info` explains why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    pub failure: Failure,
+    pub info: String,
+}
+
+impl proto::Output {
+    /// Constructs the reply of an accepted command.
+    pub fn accepted(value: Vec<u8>) -> Self {
+        proto::Output {
+            ok: true,
+            value,
+            ..proto::Output::default()
+        }
+    }
+
+    /// Constructs the reply of a rejected command.
+    pub fn rejected(rejection: Rejection) -> Self {
+        proto::Output {
+            ok: false,
+            info: rejection.info,
+            failure: proto::Failure::from(rejection.failure) as i32,
+            ..proto::Output::default()
+        }
+    }
+
+    /// Returns the value of an accepted command or the rejection.
+    pub fn into_result(self) -> Result<Vec<u8>, Rejection> {
+        if self.ok {
+            return Ok(self.value);
+        }
+        Err(Rejection {
+            failure: Failure::from_proto(self.failure),
+            info: self.info,
+        })
+    }
+}
+
+impl proto::Written {
+    /// Constructs the reply of an accepted batch.
+    pub fn accepted(written: Written) -> Self {
+        proto::Written {
+            ok: true,
+            inserted: written.inserted,
+            updated: written.updated,
+            skipped: written.skipped,
+            ..proto::Written::default()
+        }
+    }
+
+    /// Constructs the reply of a rejected batch.
+    pub fn rejected(rejection: Rejection) -> Self {
+        proto::Written {
+            ok: false,
+            info: rejection.info,
+            failure: proto::Failure::from(rejection.failure) as i32,
+            ..proto::Written::default()
+        }
+    }
+
+    /// Returns the counts of an accepted batch or the rejection.
+    pub fn into_result(self) -> Result<Written, Rejection> {
+        if self.ok {
+            return Ok(Written {
+                inserted: self.inserted,
+                updated: self.updated,
+                skipped: self.skipped,
+            });
+        }
+        Err(Rejection {
+            failure: Failure::from_proto(self.failure),
+            info: self.info,
+        })
+    }
+}
+
+impl proto::Versions {
+    /// Constructs the reply of an accepted history request.
+    pub fn accepted(versions: Vec<Version>) -> Self {
+        proto::Versions {
+            ok: true,
+            versions: versions.into_iter().map(proto::Version::from).collect(),
+            ..proto::Versions::default()
+        }
+    }
+
+    /// Constructs the reply of a rejected history request.
+    pub fn rejected(rejection: Rejection) -> Self {
+        proto::Versions {
+            ok: false,
+            info: rejection.info,
+            failure: proto::Failure::from(rejection.failure) as i32,
+            ..proto::Versions::default()
+        }
+    }
+
+    /// Returns the versions of an accepted request or the rejection.
+    pub fn into_result(self) -> Result<Vec<Version>, Rejection> {
+        if self.ok {
+            return Ok(self.versions.into_iter().map(Version::from).collect());
+        }
+        Err(Rejection {
+            failure: Failure::from_proto(self.failure),
+            info: self.info,
+        })
+    }
+}
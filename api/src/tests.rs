@@ -0,0 +1,379 @@
+//! astrobase-api tests: the generated server and client agree on every message.
+
+use crate::proto::astrobase_client::AstrobaseClient;
+use crate::proto::astrobase_server::{Astrobase, AstrobaseServer};
+use crate::proto::{
+    self, Batch, Chunk, Empty, Key, Moment, Namespace, Namespaces, Output, Pair, Range,
+    Versions,
+};
+use crate::{At, Conflict, Failure, Rejection, Version, Written};
+
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+use tonic::{Request, Response, Status};
+
+type CallResult = Result<Response<Output>, Status>;
+
+/// Answers with the fields of the requests, so every field crosses the wire both ways.
+struct Mirror;
+
+#[tonic::async_trait]
+impl Astrobase for Mirror {
+    async fn get(&self, req: Request<Key>) -> CallResult {
+        let key = req.into_inner();
+        Ok(Response::new(Output {
+            ok: true,
+            info: key.namespace,
+            failure: proto::Failure::None as i32,
+            value: key.key,
+        }))
+    }
+
+    async fn insert(&self, req: Request<Pair>) -> CallResult {
+        let pair = req.into_inner();
+        Ok(Response::new(Output {
+            ok: false,
+            info: pair.namespace,
+            failure: proto::Failure::AlreadyExists as i32,
+            value: [pair.key, pair.value].concat(),
+        }))
+    }
+
+    async fn delete(&self, req: Request<Key>) -> CallResult {
+        let key = req.into_inner();
+        Ok(Response::new(Output::rejected(Rejection {
+            failure: Failure::NotFound,
+            info: key.namespace,
+        })))
+    }
+
+    async fn update(&self, req: Request<Pair>) -> CallResult {
+        let pair = req.into_inner();
+        Ok(Response::new(Output::accepted([pair.value, pair.key].concat())))
+    }
+
+    async fn reload(&self, _: Request<Empty>) -> CallResult {
+        Ok(Response::new(Output::rejected(Rejection {
+            failure: Failure::Invalid,
+            info: "reload".into(),
+        })))
+    }
+
+    async fn create_namespace(&self, req: Request<Namespace>) -> CallResult {
+        Ok(Response::new(Output::accepted(req.into_inner().name.into())))
+    }
+
+    async fn drop_namespace(&self, req: Request<Namespace>) -> CallResult {
+        Ok(Response::new(Output::rejected(Rejection {
+            failure: Failure::Other,
+            info: req.into_inner().name,
+        })))
+    }
+
+    async fn list_namespaces(&self, _: Request<Empty>) -> Result<Response<Namespaces>, Status> {
+        Ok(Response::new(Namespaces {
+            names: vec!["default".into(), "team".into()],
+        }))
+    }
+
+    type ScanStream = tokio_stream::Iter<std::vec::IntoIter<Result<Pair, Status>>>;
+
+    async fn scan(&self, req: Request<Range>) -> Result<Response<Self::ScanStream>, Status> {
+        let range = req.into_inner();
+        let pairs: Vec<Result<Pair, Status>> = (0..3u8)
+            .map(|i| Pair {
+                key: [range.prefix.as_slice(), &[i]].concat(),
+                value: vec![i; i as usize],
+                namespace: range.namespace.clone(),
+            })
+            .map(Ok)
+            .collect();
+        Ok(Response::new(tokio_stream::iter(pairs)))
+    }
+
+    async fn write_batch(
+        &self,
+        req: Request<Batch>,
+    ) -> Result<Response<proto::Written>, Status> {
+        let batch = req.into_inner();
+        Ok(Response::new(proto::Written {
+            ok: true,
+            info: batch.namespace,
+            inserted: batch.pairs.len() as u64,
+            updated: batch.conflict as u64,
+            skipped: batch.pairs.iter().map(|pair| pair.value.len() as u64).sum(),
+            failure: proto::Failure::None as i32,
+        }))
+    }
+
+    type BackupStream = tokio_stream::Iter<std::vec::IntoIter<Result<Chunk, Status>>>;
+
+    async fn backup(&self, _: Request<Empty>) -> Result<Response<Self::BackupStream>, Status> {
+        let chunks: Vec<Result<Chunk, Status>> = vec![binary(), Vec::new(), b"end".to_vec()]
+            .into_iter()
+            .map(|data| Chunk { data })
+            .map(Ok)
+            .collect();
+        Ok(Response::new(tokio_stream::iter(chunks)))
+    }
+
+    async fn history(&self, req: Request<Key>) -> Result<Response<Versions>, Status> {
+        let key = req.into_inner();
+        Ok(Response::new(Versions::accepted(vec![
+            Version {
+                value: Some(key.key),
+                seq: 1,
+                timestamp: key.namespace.len() as u64,
+            },
+            Version {
+                value: None,
+                seq: 2,
+                timestamp: u64::MAX,
+            },
+        ])))
+    }
+
+    async fn get_at(&self, req: Request<Moment>) -> CallResult {
+        let moment = req.into_inner();
+        let (info, at) = match moment.at.map(At::from) {
+            Some(At::Seq(seq)) => ("seq", seq),
+            Some(At::Timestamp(timestamp)) => ("timestamp", timestamp),
+            None => ("none", 0),
+        };
+        Ok(Response::new(Output {
+            ok: true,
+            info: format!("{} {}", moment.namespace, info),
+            failure: proto::Failure::None as i32,
+            value: [moment.key, at.to_be_bytes().to_vec()].concat(),
+        }))
+    }
+}
+
+/// Starts the mirror on a free local port and connects to it.
+async fn connect() -> AstrobaseClient<Channel> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(AstrobaseServer::new(Mirror))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    AstrobaseClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap()
+}
+
+/// Returns bytes which are not valid UTF-8.
+fn binary() -> Vec<u8> {
+    (0..=255).collect()
+}
+
+#[tokio::test]
+async fn test_key_and_output() {
+    let mut client = connect().await;
+    let key = Key {
+        key: binary(),
+        namespace: "team".into(),
+    };
+
+    let output = client.get(key.clone()).await.unwrap().into_inner();
+    let expected = Output {
+        ok: true,
+        info: "team".into(),
+        failure: proto::Failure::None as i32,
+        value: binary(),
+    };
+    assert_eq!(output, expected);
+
+    let output = client.delete(key).await.unwrap().into_inner();
+    let expected = Rejection {
+        failure: Failure::NotFound,
+        info: "team".into(),
+    };
+    assert_eq!(output.into_result(), Err(expected));
+}
+
+#[tokio::test]
+async fn test_pair() {
+    let mut client = connect().await;
+    let pair = Pair {
+        key: b"key".to_vec(),
+        value: binary(),
+        namespace: "team".into(),
+    };
+
+    let output = client.insert(pair.clone()).await.unwrap().into_inner();
+    let expected = Output {
+        ok: false,
+        info: "team".into(),
+        failure: proto::Failure::AlreadyExists as i32,
+        value: [b"key".to_vec(), binary()].concat(),
+    };
+    assert_eq!(output, expected);
+
+    let output = client.update(pair).await.unwrap().into_inner();
+    assert_eq!(output.into_result(), Ok([binary(), b"key".to_vec()].concat()));
+}
+
+#[tokio::test]
+async fn test_admin() {
+    let mut client = connect().await;
+
+    let output = client.reload(Empty {}).await.unwrap().into_inner();
+    let expected = Rejection {
+        failure: Failure::Invalid,
+        info: "reload".into(),
+    };
+    assert_eq!(output.into_result(), Err(expected));
+
+    let namespace = Namespace {
+        name: "team".into(),
+    };
+    let output = client.create_namespace(namespace.clone()).await.unwrap();
+    assert_eq!(output.into_inner().into_result(), Ok(b"team".to_vec()));
+    let output = client.drop_namespace(namespace).await.unwrap().into_inner();
+    assert_eq!(output.into_result().unwrap_err().failure, Failure::Other);
+
+    let namespaces = client.list_namespaces(Empty {}).await.unwrap();
+    assert_eq!(namespaces.into_inner().names, ["default", "team"]);
+}
+
+#[tokio::test]
+async fn test_scan() {
+    let mut client = connect().await;
+    let range = Range {
+        namespace: "team".into(),
+        prefix: vec![0xff],
+        after: Vec::new(),
+    };
+
+    let mut stream = client.scan(range).await.unwrap().into_inner();
+    let mut pairs = Vec::new();
+    while let Some(pair) = stream.message().await.unwrap() {
+        pairs.push(pair);
+    }
+    let expected: Vec<Pair> = (0..3u8)
+        .map(|i| Pair {
+            key: vec![0xff, i],
+            value: vec![i; i as usize],
+            namespace: "team".into(),
+        })
+        .collect();
+    assert_eq!(pairs, expected);
+}
+
+#[tokio::test]
+async fn test_write_batch() {
+    let mut client = connect().await;
+    let batch = Batch {
+        namespace: "team".into(),
+        conflict: proto::Conflict::from(Conflict::Skip) as i32,
+        pairs: vec![
+            Pair {
+                key: b"a".to_vec(),
+                value: binary(),
+                namespace: String::default(),
+            },
+            Pair {
+                key: b"b".to_vec(),
+                value: b"b".to_vec(),
+                namespace: String::default(),
+            },
+        ],
+    };
+
+    let written = client.write_batch(batch).await.unwrap().into_inner();
+    assert_eq!(written.info, "team");
+    let expected = Written {
+        inserted: 2,
+        updated: proto::Conflict::Skip as u64,
+        skipped: 257,
+    };
+    assert_eq!(written.into_result(), Ok(expected));
+}
+
+#[tokio::test]
+async fn test_backup() {
+    let mut client = connect().await;
+
+    let mut stream = client.backup(Empty {}).await.unwrap().into_inner();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = stream.message().await.unwrap() {
+        chunks.push(chunk.data);
+    }
+    assert_eq!(chunks, vec![binary(), Vec::new(), b"end".to_vec()]);
+}
+
+#[tokio::test]
+async fn test_history() {
+    let mut client = connect().await;
+    let key = Key {
+        key: binary(),
+        namespace: "team".into(),
+    };
+
+    let history = client.history(key).await.unwrap().into_inner();
+    assert_eq!(history.versions[1].value, b"");
+    assert!(history.versions[1].deleted);
+    let expected = vec![
+        Version {
+            value: Some(binary()),
+            seq: 1,
+            timestamp: 4,
+        },
+        Version {
+            value: None,
+            seq: 2,
+            timestamp: u64::MAX,
+        },
+    ];
+    assert_eq!(history.into_result(), Ok(expected));
+}
+
+#[tokio::test]
+async fn test_get_at() {
+    let mut client = connect().await;
+
+    for (at, info) in [(At::Seq(7), "team seq"), (At::Timestamp(u64::MAX), "team timestamp")] {
+        let moment = Moment {
+            key: binary(),
+            namespace: "team".into(),
+            at: Some(at.into()),
+        };
+        let output = client.get_at(moment).await.unwrap().into_inner();
+        assert_eq!(output.info, info);
+        let number = match at {
+            At::Seq(n) | At::Timestamp(n) => n,
+        };
+        let expected = [binary(), number.to_be_bytes().to_vec()].concat();
+        assert_eq!(output.into_result(), Ok(expected));
+    }
+}
+
+#[test]
+fn test_conversions() {
+    for conflict in [Conflict::InsertOnly, Conflict::Overwrite, Conflict::Skip] {
+        let code = proto::Conflict::from(conflict) as i32;
+        assert_eq!(Conflict::from_proto(code), Some(conflict));
+    }
+    assert_eq!(Conflict::from_proto(-1), None);
+
+    for failure in [
+        Failure::NotFound,
+        Failure::AlreadyExists,
+        Failure::Identical,
+        Failure::Invalid,
+        Failure::Other,
+    ] {
+        let code = proto::Failure::from(failure) as i32;
+        assert_eq!(Failure::from_proto(code), failure);
+    }
+    assert_eq!(Failure::from_proto(-1), Failure::Other);
+
+    let written = Written {
+        inserted: 1,
+        updated: 2,
+        skipped: 3,
+    };
+    assert_eq!(proto::Written::accepted(written).into_result(), Ok(written));
+}
@@ -0,0 +1,460 @@
+//! astrobase-client bulk export and import of records.
+
+use crate::output::{Failure, Reply};
+
+use astrobase_api::Encoding;
+use astrobase_client::ShardedClient;
+
+use anyhow::{anyhow, Context as _};
+use serde::de::{Deserializer as _, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use tokio::sync::mpsc;
+
+/// Represents the file formats of export and import.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    Ndjson,
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &["ndjson", "csv", "json"];
+
+    /// Guesses the format by the file extension, NDJSON if unknown.
+    fn guess(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => Format::Csv,
+            Some("json") => Format::Json,
+            _ => Format::Ndjson,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(anyhow!("unknown format '{}'", s)),
+        }
+    }
+}
+
+/// Represents what import does with keys which already exist.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Conflict {
+    #[default]
+    InsertOnly,
+    Overwrite,
+    Skip,
+}
+
+impl Conflict {
+    pub const NAMES: &'static [&'static str] = &["insert-only", "overwrite", "skip"];
+}
+
+impl FromStr for Conflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "insert-only" => Ok(Conflict::InsertOnly),
+            "overwrite" => Ok(Conflict::Overwrite),
+            "skip" => Ok(Conflict::Skip),
+            _ => Err(anyhow!("unknown conflict mode '{}'", s)),
+        }
+    }
+}
+
+impl From<Conflict> for astrobase_client::Conflict {
+    fn from(conflict: Conflict) -> Self {
+        match conflict {
+            Conflict::InsertOnly => astrobase_client::Conflict::InsertOnly,
+            Conflict::Overwrite => astrobase_client::Conflict::Overwrite,
+            Conflict::Skip => astrobase_client::Conflict::Skip,
+        }
+    }
+}
+
+/// Returns the default number of records sent in one request.
+pub fn default_batch_size() -> usize {
+    1000
+}
+
+/// Options of the import.
+pub struct Options {
+    pub format: Option<Format>,
+    pub encoding: Encoding,
+    pub conflict: Conflict,
+    pub batch_size: usize,
+}
+
+/// Represents a record in NDJSON and JSON files,
+/// the key and the value are in the chosen encoding.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    key: String,
+    value: String,
+}
+
+/// Streams the records with keys starting with the prefix to a file
+/// (`-` for stdout).
+pub async fn export(
+    client: &ShardedClient,
+    output: &Path,
+    format: Option<Format>,
+    encoding: Encoding,
+    prefix: Vec<u8>,
+) -> anyhow::Result<Reply> {
+    let format = format.unwrap_or_else(|| Format::guess(output));
+    let writer: Box<dyn Write + Send> = if output == Path::new("-") {
+        Box::new(std::io::stdout())
+    } else {
+        let file = std::fs::File::create(output)
+            .with_context(|| format!("cannot create '{}'", output.display()))?;
+        Box::new(file)
+    };
+
+    let mut exporter = Exporter::new(writer, format)?;
+    let mut scan = client.scan(prefix).await?;
+    while let Some((key, value)) = scan.next().await? {
+        exporter.write(Record {
+            key: encoding.encode(&key),
+            value: encoding.encode(&value),
+        })?;
+    }
+    let count = exporter.count;
+    exporter.finish()?;
+
+    Ok(Reply {
+        exported: Some(count),
+        silent: output == Path::new("-"),
+        ..Reply::ok(format!("exported: {}", count))
+    })
+}
+
+/// Loads the records of a file (`-` for stdin) sending them in batches
+/// as they are read, so the file is never held in memory.
+pub async fn import(
+    client: &ShardedClient,
+    input: &Path,
+    options: Options,
+) -> anyhow::Result<Reply> {
+    let format = options.format.unwrap_or_else(|| Format::guess(input));
+    let reader: Box<dyn BufRead + Send> = if input == Path::new("-") {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        let file = std::fs::File::open(input)
+            .with_context(|| format!("cannot read '{}'", input.display()))?;
+        Box::new(BufReader::new(file))
+    };
+
+    // The file is parsed on a blocking thread at most a batch ahead of the writes.
+    let batch_size = options.batch_size.max(1);
+    let (tx, mut rx) = mpsc::channel(batch_size);
+    let reading = tokio::task::spawn_blocking(move || {
+        read(reader, format, |record| tx.blocking_send(record).is_ok())
+    });
+
+    let (mut inserted, mut updated, mut skipped) = (0, 0, 0);
+    let mut rejected = None;
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut count = 0;
+    loop {
+        let record = rx.recv().await;
+        let done = record.is_none();
+        if let Some(record) = record {
+            count += 1;
+            let pair = record
+                .and_then(|record| decode(&record, options.encoding))
+                .with_context(|| {
+                    format!(
+                        "record {} (written before it: inserted: {}, updated: {}, skipped: {})",
+                        count, inserted, updated, skipped
+                    )
+                })?;
+            batch.push(pair);
+        }
+        if batch.len() == batch_size || (done && !batch.is_empty()) {
+            let pairs = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+            match client.write_batch(pairs, options.conflict.into()).await {
+                Ok(written) => {
+                    inserted += written.inserted;
+                    updated += written.updated;
+                    skipped += written.skipped;
+                }
+                Err(err) => match Failure::of(&err) {
+                    Some(failure) => {
+                        rejected = Some((failure, err));
+                        break;
+                    }
+                    None => return Err(err.into()),
+                },
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    drop(rx);
+    reading.await?;
+
+    let summary = format!(
+        "inserted: {}, updated: {}, skipped: {}",
+        inserted, updated, skipped
+    );
+    let reply = match rejected {
+        None => Reply::ok(summary),
+        Some((failure, err)) => Reply::failed(failure, format!("{} ({})", err, summary)),
+    };
+    Ok(Reply {
+        inserted: Some(inserted),
+        updated: Some(updated),
+        skipped: Some(skipped),
+        ..reply
+    })
+}
+
+/// Decodes and checks the key and the value of a record.
+fn decode(record: &Record, encoding: Encoding) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    let key = encoding.decode(&record.key)?;
+    let value = encoding.decode(&record.value)?;
+    astrobase_client::ensure_key_valid(&key)?;
+    astrobase_client::ensure_value_valid(&value)?;
+    Ok((key, value))
+}
+
+/// Writes records in the chosen format one by one.
+struct Exporter {
+    writer: std::io::BufWriter<Box<dyn Write + Send>>,
+    format: Format,
+    count: usize,
+}
+
+impl Exporter {
+    /// Starts the output writing the header if the format has one.
+    fn new(writer: Box<dyn Write + Send>, format: Format) -> anyhow::Result<Self> {
+        let mut writer = std::io::BufWriter::new(writer);
+        match format {
+            Format::Ndjson => {}
+            Format::Csv => writeln!(writer, "key,value")?,
+            Format::Json => write!(writer, "[")?,
+        }
+        Ok(Exporter {
+            writer,
+            format,
+            count: 0,
+        })
+    }
+
+    /// Writes a record.
+    fn write(&mut self, record: Record) -> anyhow::Result<()> {
+        match self.format {
+            Format::Ndjson => {
+                serde_json::to_writer(&mut self.writer, &record)?;
+                writeln!(self.writer)?;
+            }
+            Format::Csv => writeln!(
+                self.writer,
+                "{},{}",
+                csv_field(&record.key),
+                csv_field(&record.value)
+            )?,
+            Format::Json => {
+                if self.count > 0 {
+                    write!(self.writer, ",")?;
+                }
+                write!(self.writer, "\n  ")?;
+                serde_json::to_writer(&mut self.writer, &record)?;
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Completes the output.
+    fn finish(mut self) -> anyhow::Result<()> {
+        if let Format::Json = self.format {
+            if self.count > 0 {
+                writeln!(self.writer)?;
+            }
+            writeln!(self.writer, "]")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Quotes a CSV field if it contains separators, quotes or line breaks.
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Parses the records of a file passing them one by one until the
+/// consumer stops; an unparsable record is passed as the last one.
+fn read(
+    reader: impl BufRead,
+    format: Format,
+    mut emit: impl FnMut(anyhow::Result<Record>) -> bool,
+) {
+    match format {
+        Format::Ndjson => {
+            for (i, line) in reader.lines().enumerate() {
+                let record = match line {
+                    Ok(line) if line.trim().is_empty() => continue,
+                    Ok(line) => serde_json::from_str(&line).map_err(anyhow::Error::from),
+                    Err(err) => Err(err.into()),
+                };
+                let record = record.with_context(|| format!("line {}", i + 1));
+                let failed = record.is_err();
+                if !emit(record) || failed {
+                    return;
+                }
+            }
+        }
+        Format::Json => {
+            let mut de = serde_json::Deserializer::from_reader(reader);
+            let stopped = match de.deserialize_seq(Records(&mut emit)) {
+                Ok(stopped) => stopped,
+                Err(err) => {
+                    emit(Err(err.into()));
+                    return;
+                }
+            };
+            if !stopped {
+                if let Err(err) = de.end() {
+                    emit(Err(err.into()));
+                }
+            }
+        }
+        Format::Csv => {
+            let mut rows = CsvRows::new(reader).peekable();
+            if let Some(Ok(row)) = rows.peek() {
+                if row == &["key", "value"] {
+                    rows.next();
+                }
+            }
+            for (i, row) in rows.enumerate() {
+                let record = row.and_then(|row| match <[String; 2]>::try_from(row) {
+                    Ok([key, value]) => Ok(Record { key, value }),
+                    Err(_) => Err(anyhow!("record {}: expected 2 fields", i + 1)),
+                });
+                let failed = record.is_err();
+                if !emit(record) || failed {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Passes the records of a JSON array one by one,
+/// the value is whether the consumer stopped.
+struct Records<F>(F);
+
+impl<'de, F: FnMut(anyhow::Result<Record>) -> bool> Visitor<'de> for Records<F> {
+    type Value = bool;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array of records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<bool, A::Error> {
+        while let Some(record) = seq.next_element()? {
+            if !(self.0)(Ok(record)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Splits CSV text into rows of fields as it is read; quoted fields may
+/// contain separators, doubled quotes and line breaks.
+struct CsvRows<R> {
+    reader: R,
+    row: Vec<String>,
+    field: String,
+    started: bool,
+    quoted: bool,
+}
+
+impl<R: BufRead> CsvRows<R> {
+    fn new(reader: R) -> Self {
+        CsvRows {
+            reader,
+            row: Vec::new(),
+            field: String::new(),
+            started: false,
+            quoted: false,
+        }
+    }
+
+    /// Completes the current row.
+    fn take_row(&mut self) -> Vec<String> {
+        self.started = false;
+        self.row.push(std::mem::take(&mut self.field));
+        std::mem::take(&mut self.row)
+    }
+}
+
+impl<R: BufRead> Iterator for CsvRows<R> {
+    type Item = anyhow::Result<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Err(err) => return Some(Err(err.into())),
+                Ok(0) if self.quoted => {
+                    self.quoted = false;
+                    return Some(Err(anyhow!("unterminated quote in CSV")));
+                }
+                Ok(0) if self.started => return Some(Ok(self.take_row())),
+                Ok(0) => return None,
+                Ok(_) => {}
+            }
+
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                if self.quoted {
+                    if c != '"' {
+                        self.field.push(c);
+                    } else if chars.peek() == Some(&'"') {
+                        chars.next();
+                        self.field.push('"');
+                    } else {
+                        self.quoted = false;
+                    }
+                    continue;
+                }
+
+                match c {
+                    '"' => self.quoted = true,
+                    ',' => self.row.push(std::mem::take(&mut self.field)),
+                    '\r' if chars.peek() == Some(&'\n') => continue,
+                    // The line ends here, a blank one makes no row.
+                    '\n' if self.started => return Some(Ok(self.take_row())),
+                    '\n' => continue,
+                    c => self.field.push(c),
+                }
+                self.started = true;
+            }
+        }
+    }
+}
@@ -1,6 +1,10 @@
 //! astrobase-client options parser.
 
-use crate::config;
+use crate::{bulk, config, output};
+use astrobase_api::encoding;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -11,27 +15,316 @@ pub struct Application {
         short,
         long,
         default_value = &config::DEFAULT_ENDPOINT,
-        help = "The service endpoint"
+        help = "The service endpoint, or unix:///path of a Unix domain socket; \
+                comma-separated endpoints of several servers spread the keys over them"
     )]
     pub endpoint: String,
 
+    #[structopt(
+        parse(from_os_str),
+        long,
+        help = "CA certificate to verify the server (enables TLS)"
+    )]
+    pub ca: Option<PathBuf>,
+
+    #[structopt(
+        parse(from_os_str),
+        long,
+        requires_all = &["ca", "key"],
+        help = "Client certificate for mutual TLS"
+    )]
+    pub cert: Option<PathBuf>,
+
+    #[structopt(
+        parse(from_os_str),
+        long,
+        requires = "cert",
+        help = "Client private key for mutual TLS"
+    )]
+    pub key: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        requires = "ca",
+        help = "Server name to verify instead of the endpoint host"
+    )]
+    pub domain: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ASTROBASE_TOKEN",
+        hide_env_values = true,
+        help = "Bearer token to authenticate with"
+    )]
+    pub token: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "30000",
+        help = "Deadline of every request in milliseconds (0 for none)"
+    )]
+    pub timeout: u64,
+
+    #[structopt(
+        long,
+        default_value = "5000",
+        help = "Limit of connecting in milliseconds (0 for none)"
+    )]
+    pub connect_timeout: u64,
+
+    #[structopt(
+        long,
+        default_value = "2",
+        help = "How many times a request is repeated while the server is unavailable"
+    )]
+    pub retries: u32,
+
+    #[structopt(
+        long,
+        default_value = "unsent",
+        possible_values = astrobase_client::WriteRetry::NAMES,
+        help = "Which failed writes are repeated: never or only unsent ones"
+    )]
+    pub write_retry: astrobase_client::WriteRetry,
+
+    #[structopt(
+        long,
+        default_value = "linearizable",
+        possible_values = astrobase_client::Consistency::NAMES,
+        help = "What reads from a cluster see: linearizable (the leader only) or stale"
+    )]
+    pub consistency: astrobase_client::Consistency,
+
+    #[structopt(
+        short,
+        long,
+        default_value = "",
+        hide_default_value = true,
+        help = "The namespace of keys (the default one if omitted)"
+    )]
+    pub namespace: String,
+
+    #[structopt(
+        short,
+        long,
+        default_value = "text",
+        possible_values = output::Format::NAMES,
+        help = "How to print results: messages, JSON objects or bare values"
+    )]
+    pub output: output::Format,
+
+    #[structopt(
+        long,
+        global = true,
+        default_value = "utf8",
+        possible_values = encoding::Encoding::NAMES,
+        help = "How keys and values are written in arguments, files and results"
+    )]
+    pub encoding: encoding::Encoding,
+
     #[structopt(subcommand)]
     pub cmd: Command,
 }
 
-#[derive(StructOpt)]
+#[derive(StructOpt, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
 pub enum Command {
     #[structopt(about = "Get value by key")]
-    Get { key: String },
+    Get {
+        key: String,
+
+        #[structopt(parse(from_os_str), long, help = "Write the value to the file")]
+        out: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            conflicts_with = "at-time",
+            help = "Read the value as it was right after the write with the sequence number"
+        )]
+        at_seq: Option<u64>,
+
+        #[structopt(
+            long,
+            help = "Read the value as it was at the time, in milliseconds since the Unix epoch"
+        )]
+        at_time: Option<u64>,
+    },
+
+    #[structopt(about = "Show the versions of a key the server keeps, the oldest first")]
+    History { key: String },
+
+    #[structopt(about = "Get values of several keys, from all servers at once")]
+    Mget {
+        #[structopt(required = true)]
+        keys: Vec<String>,
+    },
 
     #[structopt(about = "Insert new record")]
-    Insert { key: String, value: String },
+    Insert {
+        key: String,
+
+        #[structopt(required_unless = "value-file")]
+        value: Option<String>,
+
+        #[structopt(
+            parse(from_os_str),
+            long,
+            conflicts_with = "value",
+            help = "Read the value from the file, '-' for stdin"
+        )]
+        value_file: Option<PathBuf>,
+    },
 
     #[structopt(about = "Delete record by key")]
     Delete { key: String },
 
     #[structopt(about = "Update value by key")]
-    Update { key: String, value: String },
+    Update {
+        key: String,
+
+        #[structopt(required_unless = "value-file")]
+        value: Option<String>,
+
+        #[structopt(
+            parse(from_os_str),
+            long,
+            conflicts_with = "value",
+            help = "Read the value from the file, '-' for stdin"
+        )]
+        value_file: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Make the server reload its config")]
+    Reload,
+
+    #[structopt(about = "Create new namespace")]
+    CreateNamespace { name: String },
+
+    #[structopt(about = "Drop namespace with all its records")]
+    DropNamespace { name: String },
+
+    #[structopt(about = "List namespaces")]
+    ListNamespaces,
+
+    #[structopt(
+        about = "Move records to the servers owning them after servers are added or removed"
+    )]
+    Rebalance {
+        #[structopt(
+            long,
+            help = "Comma-separated endpoints of all servers after the change"
+        )]
+        to: String,
+    },
+
+    #[structopt(about = "Run commands interactively over one connection")]
+    Shell,
+
+    #[structopt(about = "Run commands or JSON operations from a file line by line")]
+    Exec {
+        #[structopt(parse(from_os_str), help = "File with commands, '-' for stdin")]
+        input: PathBuf,
+
+        #[structopt(
+            short = "j",
+            long,
+            default_value = "1",
+            help = "Number of lines executed concurrently"
+        )]
+        concurrency: usize,
+
+        #[structopt(long, help = "Run all lines instead of stopping at the first failure")]
+        continue_on_error: bool,
+    },
+
+    #[structopt(about = "Export records to NDJSON, CSV or JSON")]
+    Export {
+        #[structopt(
+            parse(from_os_str),
+            default_value = "-",
+            help = "Output file, '-' for stdout"
+        )]
+        output: PathBuf,
+
+        #[structopt(
+            short,
+            long,
+            possible_values = bulk::Format::NAMES,
+            help = "File format (guessed by the extension, ndjson by default)"
+        )]
+        format: Option<bulk::Format>,
+
+        #[structopt(
+            long,
+            default_value = "",
+            hide_default_value = true,
+            help = "Export only keys starting with the prefix"
+        )]
+        #[serde(default)]
+        prefix: String,
+    },
+
+    #[structopt(about = "Import records from NDJSON, CSV or JSON")]
+    Import {
+        #[structopt(parse(from_os_str), help = "Input file, '-' for stdin")]
+        input: PathBuf,
+
+        #[structopt(
+            short,
+            long,
+            possible_values = bulk::Format::NAMES,
+            help = "File format (guessed by the extension, ndjson by default)"
+        )]
+        format: Option<bulk::Format>,
+
+        #[structopt(
+            long,
+            default_value = "insert-only",
+            possible_values = bulk::Conflict::NAMES,
+            help = "What to do with keys which already exist"
+        )]
+        #[serde(default)]
+        conflict: bulk::Conflict,
+
+        #[structopt(long, default_value = "1000", help = "Number of records sent at once")]
+        #[serde(default = "bulk::default_batch_size")]
+        batch_size: usize,
+    },
+}
+
+impl Application {
+    /// Collects the connection options of the servers of `--endpoint`.
+    pub fn targets(&self) -> Vec<astrobase_client::Target> {
+        self.targets_of(&self.endpoint)
+    }
+
+    /// Collects the connection options of comma-separated endpoints.
+    pub fn targets_of(&self, endpoints: &str) -> Vec<astrobase_client::Target> {
+        endpoints
+            .split(',')
+            .map(|endpoint| astrobase_client::Target {
+                endpoint: endpoint.trim().into(),
+                ca: self.ca.clone(),
+                cert: self.cert.clone(),
+                key: self.key.clone(),
+                domain: self.domain.clone(),
+                token: self.token.clone(),
+            })
+            .collect()
+    }
+
+    /// Collects the timeouts and retries.
+    pub fn options(&self) -> astrobase_client::Options {
+        let millis = |ms| Some(Duration::from_millis(ms)).filter(|_| ms > 0);
+        astrobase_client::Options {
+            timeout: millis(self.timeout),
+            connect_timeout: millis(self.connect_timeout),
+            retries: self.retries,
+            write_retry: self.write_retry,
+            consistency: self.consistency,
+            ..astrobase_client::Options::default()
+        }
+    }
 }
 
 /// Constructs an instance of the Application.
@@ -0,0 +1,118 @@
+//! astrobase-client command line unit tests.
+
+use crate::cli::{Application, Command};
+use crate::command::load_value;
+use crate::config;
+use crate::exec::parse;
+use crate::output::{exit_code, Failure, Reply};
+use crate::shell::split;
+
+use anyhow::Context as _;
+use astrobase_api::Encoding;
+use astrobase_client::Error;
+use structopt::StructOpt as _;
+use tonic::{Code, Status};
+
+#[test]
+fn test_split_groups_quoted_words() {
+    let words = split(r#"insert  "a key" 'it\'s' x\ y "" "#).unwrap();
+    assert_eq!(words, ["insert", "a key", "it's", "x y", ""]);
+    let words = split(r##"get "say 'hi'"'!' \""##).unwrap();
+    assert_eq!(words, ["get", "say 'hi'!", "\""]);
+    assert!(split("").unwrap().is_empty());
+
+    for line in ["get \"key", "get 'key", "get key\\"] {
+        assert!(split(line).is_err(), "{}", line);
+    }
+}
+
+#[test]
+fn test_exec_parses_lines() {
+    assert!(parse("  ", "team").unwrap().is_none());
+    assert!(parse("# insert k v", "team").unwrap().is_none());
+
+    let (namespace, cmd) = parse("insert k 'a value'", "team").unwrap().unwrap();
+    assert_eq!(namespace, "team");
+    assert!(
+        matches!(cmd, Command::Insert { key, value: Some(value), .. }
+        if key == "k" && value == "a value")
+    );
+
+    // A JSON operation names its namespace or runs in the one of the batch.
+    let line = r#"{"op": "delete", "key": "k", "namespace": "other"}"#;
+    let (namespace, cmd) = parse(line, "team").unwrap().unwrap();
+    assert_eq!(namespace, "other");
+    assert!(matches!(cmd, Command::Delete { key } if key == "k"));
+    let (namespace, _) = parse(r#"{"op": "get", "key": "k"}"#, "team")
+        .unwrap()
+        .unwrap();
+    assert_eq!(namespace, "team");
+
+    for line in [
+        "shell",
+        "exec commands.txt",
+        r#"{"op": "shell"}"#,
+        "fly",
+        "{",
+    ] {
+        assert!(parse(line, "team").is_err(), "{}", line);
+    }
+}
+
+#[test]
+fn test_exit_codes() {
+    assert_eq!(Reply::ok(String::new()).exit_code(), config::SUCCESS);
+    let reply = Reply::failed(Failure::AlreadyExists, String::new());
+    assert_eq!(reply.exit_code(), config::ALREADY_EXISTS);
+
+    let status = |code| Error::Status(Box::new(Status::new(code, "")));
+    let cases = [
+        (Error::NotFound("k".into()), config::NOT_FOUND),
+        (Error::Identical("k".into()), config::IDENTICAL),
+        (Error::Rejected("k".into()), config::FAILURE),
+        (Error::ValueTooLong(1), config::INVALID),
+        (status(Code::OutOfRange), config::INVALID),
+        (status(Code::Unavailable), config::CONNECTION),
+        (status(Code::DeadlineExceeded), config::TIMEOUT),
+        (status(Code::Internal), config::FAILURE),
+    ];
+    for (err, code) in cases {
+        let message = err.to_string();
+        // The cause decides, however deep in the chain it is.
+        let err = Err::<(), _>(err).context("Cannot run").unwrap_err();
+        assert_eq!(exit_code(&err), code, "{}", message);
+    }
+    let err = Encoding::Hex.decode("xyz").unwrap_err();
+    assert_eq!(exit_code(&err.into()), config::INVALID);
+    assert_eq!(exit_code(&anyhow::anyhow!("other")), config::FAILURE);
+}
+
+#[test]
+fn test_value_file() {
+    let app = Application::from_iter_safe(["cli", "insert", "k", "--value-file", "-"]).unwrap();
+    let (value, value_file) = match app.cmd {
+        Command::Insert {
+            value, value_file, ..
+        } => (value, value_file),
+        _ => panic!("not an insert"),
+    };
+    assert_eq!(value, None);
+    // A value from stdin is taken as it is, whatever the encoding.
+    let stdin = &b"\xff line\n"[..];
+    let bytes = load_value(value, value_file, Encoding::Hex, stdin).unwrap();
+    assert_eq!(bytes, b"\xff line\n");
+
+    let file = std::env::temp_dir().join("astrobase-value-file");
+    std::fs::write(&file, b"from file").unwrap();
+    let bytes = load_value(None, Some(file.clone()), Encoding::Utf8, &b""[..]);
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(bytes.unwrap(), b"from file");
+    let bytes = load_value(Some("6869".into()), None, Encoding::Hex, &b"x"[..]);
+    assert_eq!(bytes.unwrap(), b"hi");
+
+    let long = vec![b'v'; astrobase_api::MAX_VALUE_LEN + 1];
+    let err = load_value(None, Some("-".into()), Encoding::Utf8, &long[..]).unwrap_err();
+    assert_eq!(exit_code(&err), config::INVALID);
+    assert!(load_value(None, Some(file), Encoding::Utf8, &b""[..]).is_err());
+    assert!(Application::from_iter_safe(["cli", "insert", "k"]).is_err());
+}
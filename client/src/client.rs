@@ -0,0 +1,586 @@
+//! astrobase-client library connection and `gRPC` API calls.
+
+use crate::error::{Error, Result};
+
+use astrobase_api::proto::{
+    self, astrobase_client, Batch, Empty, Key, Moment, Namespace, Pair, Range,
+};
+use astrobase_api::{
+    At, Conflict, Consistency, Version, Written, CONSISTENCY_METADATA, MAX_KEY_LEN, MAX_VALUE_LEN,
+};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::UnixStream;
+use tonic::codegen::http::Uri;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+use tonic::{Code, Request, Status, Streaming};
+
+/// The scheme of endpoints which are Unix domain socket paths.
+const UNIX_SCHEME: &str = "unix://";
+/// The URI standing for a Unix domain socket, only used in the requests.
+const UNIX_AUTHORITY: &str = "http://localhost";
+
+type Inner = astrobase_client::AstrobaseClient<InterceptedService<Channel, Auth>>;
+
+/// Represents the server to connect to.
+pub struct Target {
+    pub endpoint: String,
+    pub ca: Option<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub domain: Option<String>,
+    pub token: Option<String>,
+}
+
+impl Target {
+    /// Constructs a plain text target without a token.
+    pub fn new(endpoint: &str) -> Self {
+        Target {
+            endpoint: endpoint.into(),
+            ca: None,
+            cert: None,
+            key: None,
+            domain: None,
+            token: None,
+        }
+    }
+
+    /// Constructs the TLS config, None for a plain text target.
+    /// The client certificate, its key and the domain need the CA certificate,
+    /// the certificate and the key go together.
+    fn tls_config(&self) -> Result<Option<ClientTlsConfig>> {
+        let ca = match &self.ca {
+            Some(ca) => ca,
+            None if self.cert.is_some() || self.key.is_some() => {
+                return Err(Error::TlsInvalid(
+                    "the client certificate needs a CA certificate",
+                ))
+            }
+            None if self.domain.is_some() => {
+                return Err(Error::TlsInvalid("the domain needs a CA certificate"))
+            }
+            None => return Ok(None),
+        };
+        let mut tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(read_pem(ca)?));
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                tls = tls.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+            }
+            (Some(_), None) => {
+                return Err(Error::TlsInvalid("the client certificate needs its key"))
+            }
+            (None, Some(_)) => {
+                return Err(Error::TlsInvalid("the key needs its client certificate"))
+            }
+            (None, None) => {}
+        }
+        if let Some(domain) = &self.domain {
+            tls = tls.domain_name(domain.clone());
+        }
+        Ok(Some(tls))
+    }
+}
+
+/// Represents timeouts and retries of the calls.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The deadline of every call, sent to the server as the `gRPC` timeout;
+    /// `scan` is limited only until the stream starts. No limit if omitted.
+    pub timeout: Option<Duration>,
+    /// Limits establishing the connection, no limit if omitted.
+    pub connect_timeout: Option<Duration>,
+    /// How many times a call is repeated while the server is unavailable.
+    pub retries: u32,
+    /// The delay before the first repetition, doubled for every next one
+    /// up to `max_backoff`; a random part of it is cut off (jitter).
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Which failed writes are repeated; reads are repeated on any transient error.
+    pub write_retry: WriteRetry,
+    /// What the reads from a cluster see, the leader serves the linearizable ones.
+    pub consistency: Consistency,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            timeout: None,
+            connect_timeout: Some(Duration::from_secs(5)),
+            retries: 2,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            write_retry: WriteRetry::default(),
+            consistency: Consistency::default(),
+        }
+    }
+}
+
+impl Options {
+    /// Checks whether a failed call may be repeated.
+    pub(crate) fn retryable(&self, kind: Call, status: &Status) -> bool {
+        match (kind, self.write_retry) {
+            (Call::Read, _) => is_transient(status),
+            (Call::Write, WriteRetry::Unsent) => is_unsent(status),
+            (Call::Write, WriteRetry::Never) => false,
+        }
+    }
+
+    /// Returns the delay before a repetition: exponential with jitter.
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        use rand::Rng as _;
+
+        let delay = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// Represents the policy of repeating writes, which are not idempotent:
+/// a repeated insert may fail with `AlreadyExists` if the first one was applied.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum WriteRetry {
+    Never,
+    /// Only if the request has not been sent, for example the connection was refused.
+    #[default]
+    Unsent,
+}
+
+impl WriteRetry {
+    pub const NAMES: &'static [&'static str] = &["never", "unsent"];
+}
+
+impl std::str::FromStr for WriteRetry {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "never" => Ok(WriteRetry::Never),
+            "unsent" => Ok(WriteRetry::Unsent),
+            _ => Err(format!("unknown write retry policy '{}'", s)),
+        }
+    }
+}
+
+/// Represents the kinds of calls which are repeated differently.
+#[derive(Clone, Copy)]
+pub(crate) enum Call {
+    Read,
+    Write,
+}
+
+/// Represents a connection to the server working with one namespace.
+/// Clones share the connection.
+#[derive(Clone)]
+pub struct AstrobaseClient {
+    inner: Inner,
+    namespace: String,
+    options: Options,
+}
+
+impl AstrobaseClient {
+    /// Connects to the server, over TLS if a CA certificate is given,
+    /// or to the Unix domain socket of a `unix:///path` endpoint.
+    /// The client works with the default namespace.
+    pub async fn connect(target: Target, options: Options) -> Result<Self> {
+        // The URI of a Unix socket only names the channel, the connector opens the path.
+        let uri = match unix_path(&target.endpoint) {
+            Some("") => return Err(Error::EndpointInvalid(target.endpoint.clone())),
+            Some(_) => UNIX_AUTHORITY.to_owned(),
+            None => target.endpoint.clone(),
+        };
+        let mut endpoint = Channel::from_shared(uri)
+            .map_err(|_| Error::EndpointInvalid(target.endpoint.clone()))?;
+        if let Some(timeout) = options.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(tls) = target.tls_config()? {
+            endpoint = endpoint.tls_config(tls)?;
+        }
+        let channel = match unix_path(&target.endpoint) {
+            Some(path) => {
+                let path = path.to_owned();
+                let connector = tower::service_fn(move |_: Uri| UnixStream::connect(path.clone()));
+                endpoint.connect_with_connector(connector).await?
+            }
+            None => endpoint.connect().await?,
+        };
+
+        let bearer = match target.token {
+            None => None,
+            Some(token) => Some(
+                format!("Bearer {}", token)
+                    .parse()
+                    .map_err(|_| Error::TokenInvalid)?,
+            ),
+        };
+        Ok(AstrobaseClient {
+            inner: astrobase_client::AstrobaseClient::with_interceptor(channel, Auth { bearer }),
+            namespace: String::new(),
+            options,
+        })
+    }
+
+    /// Returns a client sharing the connection which works with another namespace
+    /// (the default one if empty).
+    pub fn with_namespace(&self, namespace: &str) -> Self {
+        AstrobaseClient {
+            namespace: namespace.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the namespace the client works with.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Returns the value of a key or None if there is no such record.
+    pub async fn get(&self, key: impl Into<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+        let key = key.into();
+        ensure_key_valid(&key)?;
+
+        let output = self
+            .call(Call::Read, |mut inner| {
+                let req = self.request(self.key(&key));
+                async move { inner.get(req).await }
+            })
+            .await?;
+        match output.into_result().map_err(Error::from) {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the value a key had at the moment or None if there was no such record.
+    pub async fn get_at(&self, key: impl Into<Vec<u8>>, at: At) -> Result<Option<Vec<u8>>> {
+        let key = key.into();
+        ensure_key_valid(&key)?;
+
+        let moment = Moment {
+            key,
+            namespace: self.namespace.clone(),
+            at: Some(at.into()),
+        };
+        let output = self
+            .call(Call::Read, |mut inner| {
+                let req = self.request(moment.clone());
+                async move { inner.get_at(req).await }
+            })
+            .await?;
+        match output.into_result().map_err(Error::from) {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the versions of a key, the oldest first; the server keeps them
+    /// within its retention window.
+    pub async fn history(&self, key: impl Into<Vec<u8>>) -> Result<Vec<Version>> {
+        let key = key.into();
+        ensure_key_valid(&key)?;
+
+        let versions = self
+            .call(Call::Read, |mut inner| {
+                let req = self.request(self.key(&key));
+                async move { inner.history(req).await }
+            })
+            .await?;
+        versions.into_result().map_err(Error::from)
+    }
+
+    /// Inserts a new record, fails if the key exists.
+    pub async fn insert(&self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Result<()> {
+        let pair = self.pair(key.into(), value.into())?;
+        let output = self
+            .call(Call::Write, |mut inner| {
+                let req = self.request(pair.clone());
+                async move { inner.insert(req).await }
+            })
+            .await?;
+        output.into_result().map(drop).map_err(Error::from)
+    }
+
+    /// Changes the value of an existing record.
+    pub async fn update(&self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Result<()> {
+        let pair = self.pair(key.into(), value.into())?;
+        let output = self
+            .call(Call::Write, |mut inner| {
+                let req = self.request(pair.clone());
+                async move { inner.update(req).await }
+            })
+            .await?;
+        output.into_result().map(drop).map_err(Error::from)
+    }
+
+    /// Deletes a record returning its value.
+    pub async fn delete(&self, key: impl Into<Vec<u8>>) -> Result<Vec<u8>> {
+        let key = key.into();
+        ensure_key_valid(&key)?;
+
+        let output = self
+            .call(Call::Write, |mut inner| {
+                let req = self.request(self.key(&key));
+                async move { inner.delete(req).await }
+            })
+            .await?;
+        output.into_result().map_err(Error::from)
+    }
+
+    /// Streams the records with keys starting with the prefix, sorted by key.
+    pub async fn scan(&self, prefix: impl Into<Vec<u8>>) -> Result<Scan> {
+        let prefix = prefix.into();
+        let stream = self
+            .call(Call::Read, |mut inner| {
+                let req = self.stream_request(Range {
+                    namespace: self.namespace.clone(),
+                    prefix: prefix.clone(),
+                    after: Vec::new(),
+                });
+                async move { inner.scan(req).await }
+            })
+            .await?;
+        Ok(Scan { stream })
+    }
+
+    /// Writes the pairs in one request.
+    pub async fn write_batch(
+        &self,
+        pairs: Vec<(Vec<u8>, Vec<u8>)>,
+        conflict: Conflict,
+    ) -> Result<Written> {
+        let pairs = pairs
+            .into_iter()
+            .map(|(key, value)| Pair {
+                key,
+                value,
+                namespace: String::default(),
+            })
+            .collect();
+        let batch = Batch {
+            namespace: self.namespace.clone(),
+            conflict: proto::Conflict::from(conflict) as i32,
+            pairs,
+        };
+
+        let written = self
+            .call(Call::Write, |mut inner| {
+                let req = self.request(batch.clone());
+                async move { inner.write_batch(req).await }
+            })
+            .await?;
+        written.into_result().map_err(Error::from)
+    }
+
+    /// Makes the server reload its config.
+    pub async fn reload(&self) -> Result<()> {
+        let output = self
+            .call(Call::Write, |mut inner| {
+                let req = self.request(Empty {});
+                async move { inner.reload(req).await }
+            })
+            .await?;
+        output.into_result().map(drop).map_err(Error::from)
+    }
+
+    /// Creates a namespace.
+    pub async fn create_namespace(&self, name: &str) -> Result<()> {
+        let output = self
+            .call(Call::Write, |mut inner| {
+                let req = self.request(Namespace { name: name.into() });
+                async move { inner.create_namespace(req).await }
+            })
+            .await?;
+        output.into_result().map(drop).map_err(Error::from)
+    }
+
+    /// Drops a namespace with all its records.
+    pub async fn drop_namespace(&self, name: &str) -> Result<()> {
+        let output = self
+            .call(Call::Write, |mut inner| {
+                let req = self.request(Namespace { name: name.into() });
+                async move { inner.drop_namespace(req).await }
+            })
+            .await?;
+        output.into_result().map(drop).map_err(Error::from)
+    }
+
+    /// Returns the names of the namespaces.
+    pub async fn list_namespaces(&self) -> Result<Vec<String>> {
+        let namespaces = self
+            .call(Call::Read, |mut inner| {
+                let req = self.request(Empty {});
+                async move { inner.list_namespaces(req).await }
+            })
+            .await?;
+        Ok(namespaces.names)
+    }
+
+    /// Makes a call within the deadline repeating it while the server
+    /// is unavailable, see `Options`.
+    async fn call<T, F, Fut>(&self, kind: Call, mut call: F) -> Result<T>
+    where
+        F: FnMut(Inner) -> Fut,
+        Fut: Future<Output = std::result::Result<tonic::Response<T>, Status>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let r = match self.options.timeout {
+                Some(timeout) => {
+                    match tokio::time::timeout(timeout, call(self.inner.clone())).await {
+                        Ok(r) => r,
+                        Err(_) => Err(Status::deadline_exceeded("request timed out")),
+                    }
+                }
+                None => call(self.inner.clone()).await,
+            };
+            match r.map_err(deadline) {
+                Ok(resp) => return Ok(resp.into_inner()),
+                Err(status)
+                    if attempt < self.options.retries && self.options.retryable(kind, &status) =>
+                {
+                    let delay = self.options.backoff(attempt);
+                    attempt += 1;
+                    tracing::debug!(
+                        "retrying ({}) in {:?}: {}",
+                        attempt,
+                        delay,
+                        status.message()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(status) => return Err(status.into()),
+            }
+        }
+    }
+
+    /// Constructs a request carrying the deadline.
+    fn request<M>(&self, message: M) -> Request<M> {
+        let mut req = self.stream_request(message);
+        if let Some(timeout) = self.options.timeout {
+            req.set_timeout(timeout);
+        }
+        req
+    }
+
+    /// Constructs the request of a streamed reply: without the deadline,
+    /// which would limit the whole stream.
+    fn stream_request<M>(&self, message: M) -> Request<M> {
+        let mut req = Request::new(message);
+        let consistency = MetadataValue::from_static(self.options.consistency.name());
+        req.metadata_mut().insert(CONSISTENCY_METADATA, consistency);
+        req
+    }
+
+    fn key(&self, key: &[u8]) -> Key {
+        Key {
+            key: key.to_vec(),
+            namespace: self.namespace.clone(),
+        }
+    }
+
+    fn pair(&self, key: Vec<u8>, value: Vec<u8>) -> Result<Pair> {
+        ensure_key_valid(&key)?;
+        ensure_value_valid(&value)?;
+        Ok(Pair {
+            key,
+            value,
+            namespace: self.namespace.clone(),
+        })
+    }
+}
+
+/// Adds the bearer token, if any, to every request.
+#[derive(Clone)]
+struct Auth {
+    bearer: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for Auth {
+    fn call(&mut self, mut req: Request<()>) -> std::result::Result<Request<()>, Status> {
+        if let Some(bearer) = &self.bearer {
+            req.metadata_mut().insert("authorization", bearer.clone());
+        }
+        Ok(req)
+    }
+}
+
+/// Represents the records streamed by `scan`.
+pub struct Scan {
+    stream: Streaming<Pair>,
+}
+
+impl Scan {
+    /// Returns the next record or None at the end.
+    pub async fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let pair = self.stream.message().await?;
+        Ok(pair.map(|pair| (pair.key, pair.value)))
+    }
+}
+
+/// Reports the expired deadline as such, tonic cancels the call with a message.
+fn deadline(status: Status) -> Status {
+    if status.code() == Code::Cancelled && status.message() == "Timeout expired" {
+        return Status::deadline_exceeded("request timed out");
+    }
+    status
+}
+
+/// Checks whether the request has certainly not reached the server:
+/// the connection was refused.
+fn is_unsent(status: &Status) -> bool {
+    let mut cause = std::error::Error::source(status);
+    while let Some(err) = cause {
+        if let Some(err) = err.downcast_ref::<std::io::Error>() {
+            // NotFound: the Unix socket does not exist
+            return matches!(
+                err.kind(),
+                std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::NotFound
+            );
+        }
+        cause = err.source();
+    }
+    false
+}
+
+/// Returns the socket path of a `unix:///path` endpoint.
+fn unix_path(endpoint: &str) -> Option<&str> {
+    endpoint.strip_prefix(UNIX_SCHEME)
+}
+
+/// Checks whether the failure may pass if the call is repeated.
+fn is_transient(status: &Status) -> bool {
+    match status.code() {
+        Code::Unavailable => true,
+        Code::Unknown => status.message().starts_with("transport error"),
+        _ => false,
+    }
+}
+
+/// Checks the length of a key is below the limit.
+pub fn ensure_key_valid(key: &[u8]) -> Result<()> {
+    if key.len() > MAX_KEY_LEN {
+        return Err(Error::KeyTooLong(key.len()));
+    }
+    Ok(())
+}
+
+/// Checks the length of a value is below the limit.
+pub fn ensure_value_valid(value: &[u8]) -> Result<()> {
+    if value.len() > MAX_VALUE_LEN {
+        return Err(Error::ValueTooLong(value.len()));
+    }
+    Ok(())
+}
+
+/// Reads a PEM file.
+fn read_pem(filename: &Path) -> Result<Vec<u8>> {
+    std::fs::read(filename).map_err(|err| Error::Pem(filename.into(), err))
+}
@@ -1,99 +1,272 @@
-//! astrobase-client `gRPC` API calls.
+//! astrobase-client commands of the command line.
 
-mod api {
-    tonic::include_proto!("api");
+use astrobase_client::{At, Error, ShardedClient};
+use std::path::{Path, PathBuf};
+
+use crate::bulk;
+use crate::cli::Command;
+use crate::output::{Failure, Reply};
+
+use anyhow::anyhow;
+use astrobase_api::Encoding;
+
+/// Executes a command over the connection.
+/// Keys and values of the command line are decoded from the encoding.
+pub async fn execute(
+    client: &ShardedClient,
+    namespace: &str,
+    encoding: Encoding,
+    cmd: Command,
+) -> anyhow::Result<Reply> {
+    let client = &client.with_namespace(namespace);
+    match cmd {
+        Command::Get {
+            key,
+            out,
+            at_seq,
+            at_time,
+        } => {
+            let at = at_seq.map(At::Seq).or(at_time.map(At::Timestamp));
+            let reply = get(client, encoding.decode(&key)?, at).await?;
+            match out {
+                Some(out) => save_value(reply, &out),
+                None => Ok(reply),
+            }
+        }
+        Command::History { key } => history(client, encoding.decode(&key)?).await,
+        Command::Mget { keys } => {
+            let keys = keys
+                .iter()
+                .map(|key| encoding.decode(key))
+                .collect::<Result<_, _>>()?;
+            mget(client, keys).await
+        }
+        Command::Insert {
+            key,
+            value,
+            value_file,
+        } => {
+            let value = load_value(value, value_file, encoding, std::io::stdin())?;
+            insert(client, encoding.decode(&key)?, value).await
+        }
+        Command::Delete { key } => delete(client, encoding.decode(&key)?).await,
+        Command::Update {
+            key,
+            value,
+            value_file,
+        } => {
+            let value = load_value(value, value_file, encoding, std::io::stdin())?;
+            update(client, encoding.decode(&key)?, value).await
+        }
+        Command::Reload => reload(client).await,
+        Command::CreateNamespace { name } => create_namespace(client, name).await,
+        Command::DropNamespace { name } => drop_namespace(client, name).await,
+        Command::ListNamespaces => list_namespaces(client).await,
+        Command::Export {
+            output,
+            format,
+            prefix,
+        } => {
+            let prefix = encoding.decode(&prefix)?;
+            bulk::export(client, &output, format, encoding, prefix).await
+        }
+        Command::Import {
+            input,
+            format,
+            conflict,
+            batch_size,
+        } => {
+            let options = bulk::Options {
+                format,
+                encoding,
+                conflict,
+                batch_size,
+            };
+            bulk::import(client, &input, options).await
+        }
+        Command::Shell => Err(anyhow!("shell cannot be nested")),
+        Command::Exec { .. } => Err(anyhow!("exec cannot be nested")),
+        Command::Rebalance { .. } => Err(anyhow!("rebalance cannot be nested")),
+    }
 }
 
-use api::{astrobase_client, Key, Pair};
-use tonic::Request;
-use tracing::{info, warn};
+/// Decodes the value given in the command line or reads it as is from a file
+/// (`-` for stdin).
+pub fn load_value(
+    value: Option<String>,
+    value_file: Option<PathBuf>,
+    encoding: Encoding,
+    mut stdin: impl std::io::Read,
+) -> anyhow::Result<Vec<u8>> {
+    use anyhow::Context as _;
+    use std::io::Read as _;
 
-/// Calls RPC-method `Get`.
-pub async fn get(endpoint: String, key: String) -> anyhow::Result<()> {
-    ensure_key_valid(&key)?;
+    let filename = match (value, value_file) {
+        (Some(value), _) => return Ok(encoding.decode(&value)?),
+        (None, Some(filename)) => filename,
+        (None, None) => return Err(anyhow!("value is missing")),
+    };
 
-    let mut caller = astrobase_client::AstrobaseClient::connect(endpoint).await?;
-    let req = Request::new(Key { key: key.clone() });
-    let resp = caller.get(req).await?.into_inner();
-    if resp.ok {
-        info!("key: '{}', value: '{}'", key, resp.info);
+    let mut bytes = Vec::new();
+    if filename == Path::new("-") {
+        stdin.read_to_end(&mut bytes)
     } else {
-        warn!("{}", resp.info);
+        std::fs::File::open(&filename).and_then(|mut file| file.read_to_end(&mut bytes))
     }
+    .with_context(|| format!("Cannot read value from '{}'", filename.display()))?;
 
-    Ok(())
+    astrobase_client::ensure_value_valid(&bytes)
+        .with_context(|| format!("Cannot read value from '{}'", filename.display()))?;
+    Ok(bytes)
 }
 
-/// Calls RPC-method `Insert`.
-pub async fn insert(endpoint: String, key: String, value: String) -> anyhow::Result<()> {
-    ensure_key_valid(&key)?;
-    ensure_value_valid(&value)?;
-
-    let mut caller = astrobase_client::AstrobaseClient::connect(endpoint).await?;
-    let req = Request::new(Pair {
-        key: key.clone(),
-        value: value.clone(),
-    });
-    let resp = caller.insert(req).await?.into_inner();
-    if resp.ok {
-        info!("key: '{}', value: '{}'", key, value);
-    } else {
-        warn!("{}", resp.info);
-    }
+/// Writes the value of a successful `get` to a file instead of printing it.
+fn save_value(reply: Reply, out: &Path) -> anyhow::Result<Reply> {
+    use anyhow::Context as _;
+
+    let value = match (reply.ok, &reply.value) {
+        (true, Some(value)) => value,
+        _ => return Ok(reply),
+    };
+    std::fs::write(out, value)
+        .with_context(|| format!("Cannot write value to '{}'", out.display()))?;
 
-    Ok(())
+    Ok(Reply {
+        key: reply.key,
+        ..Reply::ok(format!("value written to '{}'", out.display()))
+    })
 }
 
-/// Calls RPC-method `Delete`.
-pub async fn delete(endpoint: String, key: String) -> anyhow::Result<()> {
-    ensure_key_valid(&key)?;
-
-    let mut caller = astrobase_client::AstrobaseClient::connect(endpoint).await?;
-    let req = Request::new(Key { key: key.clone() });
-    let resp = caller.delete(req).await?.into_inner();
-    if resp.ok {
-        info!("key: '{}', value: '{}'", key, resp.info);
-    } else {
-        warn!("{}", resp.info);
+/// Calls RPC-method `Get`, or `GetAt` if the moment is given.
+pub async fn get(client: &ShardedClient, key: Vec<u8>, at: Option<At>) -> anyhow::Result<Reply> {
+    let value = match at {
+        Some(at) => client.get_at(key.clone(), at).await?,
+        None => client.get(key.clone()).await?,
+    };
+    match value {
+        Some(value) => Ok(Reply {
+            key: Some(key),
+            value: Some(value),
+            ..Reply::ok(String::default())
+        }),
+        None => {
+            let message = format!("Record '{}' is missing", String::from_utf8_lossy(&key));
+            Ok(Reply {
+                key: Some(key),
+                ..Reply::failed(Failure::NotFound, message)
+            })
+        }
+    }
+}
+
+/// Calls RPC-method `History`.
+pub async fn history(client: &ShardedClient, key: Vec<u8>) -> anyhow::Result<Reply> {
+    match client.history(key.clone()).await {
+        Ok(versions) => Ok(Reply {
+            key: Some(key),
+            message: format!("{} versions", versions.len()),
+            versions: Some(versions),
+            ..Reply::ok(String::default())
+        }),
+        Err(err) => rejected(err),
     }
+}
+
+/// Calls RPC-method `Get` for every key on the servers owning them.
+pub async fn mget(client: &ShardedClient, keys: Vec<Vec<u8>>) -> anyhow::Result<Reply> {
+    let values = client.get_many(keys.clone()).await?;
+    let missing = values.iter().filter(|value| value.is_none()).count();
+    Ok(Reply {
+        records: Some(keys.into_iter().zip(values).collect()),
+        ..Reply::ok(format!("{} of the records are missing", missing))
+    })
+}
 
-    Ok(())
+/// Moves the records of the namespace to the servers owning them in `to`.
+pub async fn rebalance(client: &ShardedClient, to: &ShardedClient) -> anyhow::Result<Reply> {
+    let rebalanced = client.rebalance(to).await?;
+    Ok(Reply {
+        scanned: Some(rebalanced.scanned),
+        moved: Some(rebalanced.moved),
+        ..Reply::ok(format!(
+            "{} records scanned, {} moved",
+            rebalanced.scanned, rebalanced.moved
+        ))
+    })
+}
+
+/// Calls RPC-method `Insert`.
+pub async fn insert(client: &ShardedClient, key: Vec<u8>, value: Vec<u8>) -> anyhow::Result<Reply> {
+    let r = client.insert(key.clone(), value.clone()).await;
+    reply(r.map(|()| Some(value)), key)
+}
+
+/// Calls RPC-method `Delete`.
+pub async fn delete(client: &ShardedClient, key: Vec<u8>) -> anyhow::Result<Reply> {
+    let r = client.delete(key.clone()).await;
+    reply(r.map(Some), key)
 }
 
 /// Calls RPC-method `Update`.
-pub async fn update(endpoint: String, key: String, value: String) -> anyhow::Result<()> {
-    ensure_key_valid(&key)?;
-    ensure_value_valid(&value)?;
-
-    let mut caller = astrobase_client::AstrobaseClient::connect(endpoint).await?;
-    let req = Request::new(Pair {
-        key: key.clone(),
-        value: value.clone(),
-    });
-    let resp = caller.update(req).await?.into_inner();
-    if resp.ok {
-        info!("key: '{}', value: '{}'", key, value);
-    } else {
-        warn!("{}", resp.info);
+pub async fn update(client: &ShardedClient, key: Vec<u8>, value: Vec<u8>) -> anyhow::Result<Reply> {
+    let r = client.update(key.clone(), value.clone()).await;
+    reply(r.map(|()| Some(value)), key)
+}
+
+/// Calls admin RPC-method `Reload`.
+pub async fn reload(client: &ShardedClient) -> anyhow::Result<Reply> {
+    match client.reload().await {
+        Ok(()) => Ok(Reply::ok("config reloaded".into())),
+        Err(err) => rejected(err),
     }
+}
 
-    Ok(())
+/// Calls admin RPC-method `CreateNamespace`.
+pub async fn create_namespace(client: &ShardedClient, name: String) -> anyhow::Result<Reply> {
+    match client.create_namespace(&name).await {
+        Ok(()) => Ok(Reply::ok(format!("namespace '{}' created", name))),
+        Err(err) => rejected(err),
+    }
 }
 
-use anyhow::anyhow;
+/// Calls admin RPC-method `DropNamespace`.
+pub async fn drop_namespace(client: &ShardedClient, name: String) -> anyhow::Result<Reply> {
+    match client.drop_namespace(&name).await {
+        Ok(()) => Ok(Reply::ok(format!("namespace '{}' dropped", name))),
+        Err(err) => rejected(err),
+    }
+}
+
+/// Calls admin RPC-method `ListNamespaces`.
+pub async fn list_namespaces(client: &ShardedClient) -> anyhow::Result<Reply> {
+    let names = client.list_namespaces().await?;
+
+    Ok(Reply {
+        namespaces: Some(names.clone()),
+        ..Reply::ok(format!("namespaces: {}", names.join(", ")))
+    })
+}
 
-/// Checks the length of a key is below the limit.
-fn ensure_key_valid(key: &str) -> anyhow::Result<()> {
-    if key.len() > crate::config::MAX_KEY_LEN {
-        return Err(anyhow!("key is too long: {}", key.len()));
+/// Constructs the reply of a key command.
+fn reply(r: astrobase_client::Result<Option<Vec<u8>>>, key: Vec<u8>) -> anyhow::Result<Reply> {
+    match r {
+        Ok(value) => Ok(Reply {
+            key: Some(key),
+            value,
+            ..Reply::ok(String::default())
+        }),
+        Err(err) => rejected(err).map(|reply| Reply {
+            key: Some(key),
+            ..reply
+        }),
     }
-    Ok(())
 }
 
-/// Checks the length of a value is below the limit.
-fn ensure_value_valid(value: &str) -> anyhow::Result<()> {
-    if value.len() > crate::config::MAX_VALUE_LEN {
-        return Err(anyhow!("value is too long: {}", value.len()));
+/// Constructs the reply of a command rejected by the server,
+/// other errors prevent the command from completing.
+pub fn rejected(err: Error) -> anyhow::Result<Reply> {
+    match Failure::of(&err) {
+        Some(failure) => Ok(Reply::failed(failure, err.to_string())),
+        None => Err(err.into()),
     }
-    Ok(())
 }
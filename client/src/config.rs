@@ -1,8 +1,12 @@
 //! astrobase-client config module.
 
+pub const SUCCESS: i32 = 0;
 pub const FAILURE: i32 = 1;
-
-pub const MAX_KEY_LEN: usize = 1024;
-pub const MAX_VALUE_LEN: usize = 1024 * 1024;
+pub const NOT_FOUND: i32 = 2;
+pub const ALREADY_EXISTS: i32 = 3;
+pub const IDENTICAL: i32 = 4;
+pub const INVALID: i32 = 5;
+pub const CONNECTION: i32 = 6;
+pub const TIMEOUT: i32 = 7;
 
 pub const DEFAULT_ENDPOINT: &str = "http://[::1]:50051";
@@ -0,0 +1,80 @@
+//! astrobase-client library errors.
+
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Represents errors of the client calls.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("key is too long: {0}")]
+    KeyTooLong(usize),
+    #[error("value is too long: {0}")]
+    ValueTooLong(usize),
+
+    // The command is rejected by the server, the message explains why.
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    AlreadyExists(String),
+    #[error("{0}")]
+    Identical(String),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Rejected(String),
+
+    #[error("the server is a follower, send the request to the leader at {0}")]
+    Follower(String),
+
+    #[error("invalid endpoint '{0}'")]
+    EndpointInvalid(String),
+    #[error("invalid TLS settings: {0}")]
+    TlsInvalid(&'static str),
+    #[error("invalid token")]
+    TokenInvalid,
+    #[error("cannot read '{0}': {1}")]
+    Pem(PathBuf, std::io::Error),
+    #[error(transparent)]
+    Transport(#[from] tonic::transport::Error),
+    #[error(transparent)]
+    Status(Box<tonic::Status>),
+}
+
+impl Error {
+    /// Checks whether the server has rejected the command,
+    /// so the connection and the request themselves are fine.
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            Error::NotFound(_)
+                | Error::AlreadyExists(_)
+                | Error::Identical(_)
+                | Error::Invalid(_)
+                | Error::Rejected(_)
+        )
+    }
+}
+
+impl From<astrobase_api::Rejection> for Error {
+    fn from(rejection: astrobase_api::Rejection) -> Self {
+        use astrobase_api::Failure;
+        match rejection.failure {
+            Failure::NotFound => Error::NotFound(rejection.info),
+            Failure::AlreadyExists => Error::AlreadyExists(rejection.info),
+            Failure::Identical => Error::Identical(rejection.info),
+            Failure::Invalid => Error::Invalid(rejection.info),
+            Failure::Other => Error::Rejected(rejection.info),
+        }
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        let leader = status.metadata().get(astrobase_api::LEADER_METADATA);
+        if let Some(leader) = leader.and_then(|leader| leader.to_str().ok()) {
+            return Error::Follower(leader.into());
+        }
+        Error::Status(Box::new(status))
+    }
+}
@@ -0,0 +1,148 @@
+//! astrobase-client batch execution of commands from a file.
+
+use crate::cli::Command;
+use crate::command;
+use crate::output;
+use crate::shell;
+
+use anyhow::anyhow;
+use astrobase_api::Encoding;
+use astrobase_client::ShardedClient;
+use serde::Deserialize;
+use std::io::BufRead as _;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use structopt::StructOpt;
+use tokio::sync::Semaphore;
+use tracing::{error, info, Instrument as _};
+
+/// Represents a JSONL operation, for example
+/// `{"op": "insert", "key": "k", "value": "v", "namespace": "team"}`.
+#[derive(Deserialize)]
+struct Operation {
+    #[serde(flatten)]
+    cmd: Command,
+    #[serde(default)]
+    namespace: Option<String>,
+}
+
+/// Options of the batch execution.
+pub struct Options {
+    pub concurrency: usize,
+    pub continue_on_error: bool,
+    pub output: output::Format,
+    pub encoding: Encoding,
+}
+
+/// Runs the commands of a file (`-` for stdin) line by line;
+/// up to `concurrency` lines are in flight over the same connection.
+/// Fails if any line failed.
+pub async fn run(
+    client: &ShardedClient,
+    namespace: &str,
+    input: &Path,
+    options: Options,
+) -> anyhow::Result<()> {
+    let reader: Box<dyn std::io::BufRead> = if input == Path::new("-") {
+        Box::new(std::io::BufReader::new(std::io::stdin()))
+    } else {
+        let file = std::fs::File::open(input)
+            .map_err(|e| anyhow!("cannot open '{}': {}", input.display(), e))?;
+        Box::new(std::io::BufReader::new(file))
+    };
+
+    let permits = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let failed = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut tasks = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let number = i + 1;
+        let line = line?;
+        let (namespace, cmd) = match parse(&line, namespace) {
+            Ok(None) => continue,
+            Ok(Some(parsed)) => parsed,
+            Err(err) => {
+                error!("line {}: {:#}", number, err);
+                failed.fetch_add(1, Ordering::SeqCst);
+                if !options.continue_on_error {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        // the permit is released when the previous line is done, so check after
+        let permit = permits.clone().acquire_owned().await?;
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let client = client.clone();
+        let failed = failed.clone();
+        let stop = stop.clone();
+        let continue_on_error = options.continue_on_error;
+        let format = options.output;
+        let encoding = options.encoding;
+        let span = tracing::info_span!("line", n = number);
+        tasks.push(tokio::spawn(
+            async move {
+                let ok = match command::execute(&client, &namespace, encoding, cmd).await {
+                    Ok(reply) => {
+                        reply.print(format, encoding);
+                        reply.ok
+                    }
+                    Err(err) => {
+                        error!("{:#}", err);
+                        false
+                    }
+                };
+                if !ok {
+                    failed.fetch_add(1, Ordering::SeqCst);
+                    if !continue_on_error {
+                        stop.store(true, Ordering::SeqCst);
+                    }
+                }
+                drop(permit);
+            }
+            .instrument(span),
+        ));
+    }
+
+    let total = tasks.len();
+    for task in tasks {
+        task.await?;
+    }
+
+    let failed = failed.load(Ordering::SeqCst);
+    info!("executed: {}, failed: {}", total, failed);
+    if failed > 0 {
+        return Err(anyhow!("{} line(s) failed", failed));
+    }
+    Ok(())
+}
+
+/// Parses a line as a JSON operation or as a command like in the shell.
+/// Returns None for blank lines and comments.
+pub fn parse(line: &str, namespace: &str) -> anyhow::Result<Option<(String, Command)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (namespace, cmd) = if line.starts_with('{') {
+        let op: Operation = serde_json::from_str(line)?;
+        (op.namespace.unwrap_or_else(|| namespace.into()), op.cmd)
+    } else {
+        let words = shell::split(line)?;
+        let args = std::iter::once("exec").chain(words.iter().map(String::as_str));
+        let cmd = Command::from_iter_safe(args).map_err(|e| anyhow!(e.message))?;
+        (namespace.into(), cmd)
+    };
+
+    if let Command::Shell | Command::Exec { .. } = cmd {
+        return Err(anyhow!("command is not allowed in batch"));
+    }
+    Ok(Some((namespace, cmd)))
+}
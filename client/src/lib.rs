@@ -0,0 +1,32 @@
+//! astrobase-client library: typed async access to the Astrobase server.
+//!
+//! ```no_run
+//! # async fn run() -> astrobase_client::Result<()> {
+//! use astrobase_client::{AstrobaseClient, Options, Target};
+//!
+//! let client = AstrobaseClient::connect(Target::new("http://[::1]:50051"), Options::default())
+//!     .await?
+//!     .with_namespace("team");
+//! client.insert("key", "value").await?;
+//! assert_eq!(client.get("key").await?, Some(b"value".to_vec()));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `ShardedClient` spreads the keys over several servers by consistent hashing.
+
+#![forbid(unsafe_code)]
+#![deny(warnings)]
+
+mod client;
+mod error;
+mod shard;
+#[cfg(test)]
+mod tests;
+
+pub use astrobase_api::{At, Conflict, Consistency, Version, Written, MAX_KEY_LEN, MAX_VALUE_LEN};
+pub use client::{
+    ensure_key_valid, ensure_value_valid, AstrobaseClient, Options, Scan, Target, WriteRetry,
+};
+pub use error::{Error, Result};
+pub use shard::{MergedScan, Rebalanced, Ring, ShardedClient, VIRTUAL_NODES};
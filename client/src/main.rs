@@ -3,21 +3,30 @@
 #![forbid(unsafe_code)]
 #![deny(warnings)]
 
-// For the generated code
-#![allow(clippy::derive_partial_eq_without_eq)]
-#![allow(clippy::too_many_lines)]
-#![allow(clippy::wildcard_imports)]
-#![allow(clippy::similar_names)]
-
+mod bulk;
 mod cli;
 mod command;
 mod config;
+mod exec;
+mod output;
+mod shell;
+
+#[cfg(test)]
+mod cli_tests;
+
+use astrobase_client::ShardedClient;
 
 fn main() {
     init_logger();
-    if let Err(err) = execute(cli::application()) {
-        eprintln!("Error: {:#}", err);
-        std::process::exit(config::FAILURE);
+    let code = match execute(cli::application()) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            output::exit_code(&err)
+        }
+    };
+    if code != config::SUCCESS {
+        std::process::exit(code);
     }
 }
 
@@ -26,27 +35,52 @@ fn init_logger() {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info");
     }
-    tracing_subscriber::fmt::init();
+    // stderr keeps stdout clean for exported records
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
 }
 
 /// Dispatches CLI commands.
-fn execute(app: cli::Application) -> anyhow::Result<()> {
+/// Returns the exit code.
+fn execute(app: cli::Application) -> anyhow::Result<i32> {
     let rt = tokio::runtime::Runtime::new()?;
+    let targets = app.targets();
 
-    match app.cmd {
-        cli::Command::Get { key } => {
-            rt.block_on(command::get(app.endpoint, key))?;
-        }
-        cli::Command::Insert { key, value } => {
-            rt.block_on(command::insert(app.endpoint, key, value))?;
+    rt.block_on(async {
+        let client = ShardedClient::connect(targets, app.options()).await?;
+        match app.cmd {
+            cli::Command::Shell => shell::run(&client, app.namespace, app.output, app.encoding)
+                .await
+                .map(|()| config::SUCCESS),
+            cli::Command::Exec {
+                input,
+                concurrency,
+                continue_on_error,
+            } => {
+                let options = exec::Options {
+                    concurrency,
+                    continue_on_error,
+                    output: app.output,
+                    encoding: app.encoding,
+                };
+                exec::run(&client, &app.namespace, &input, options)
+                    .await
+                    .map(|()| config::SUCCESS)
+            }
+            cli::Command::Rebalance { ref to } => {
+                let to = ShardedClient::connect(app.targets_of(to), app.options()).await?;
+                let client = client.with_namespace(&app.namespace);
+                let reply = command::rebalance(&client, &to).await?;
+                reply.print(app.output, app.encoding);
+                Ok(reply.exit_code())
+            }
+            cmd => {
+                let reply = command::execute(&client, &app.namespace, app.encoding, cmd).await?;
+                reply.print(app.output, app.encoding);
+                Ok(reply.exit_code())
+            }
         }
-        cli::Command::Delete { key } => {
-            rt.block_on(command::delete(app.endpoint, key))?;
-        }
-        cli::Command::Update { key, value } => {
-            rt.block_on(command::update(app.endpoint, key, value))?;
-        }
-    }
-
-    Ok(())
+    })
 }
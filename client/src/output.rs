@@ -0,0 +1,301 @@
+//! astrobase-client output of command results and exit codes.
+
+use crate::config;
+
+use anyhow::anyhow;
+use astrobase_api::encoding::{self, Encoding};
+use astrobase_api::Version;
+use serde::Serialize;
+use std::io::{IsTerminal as _, Write as _};
+use std::str::FromStr;
+
+/// Represents the ways of printing results.
+#[derive(Clone, Copy)]
+pub enum Format {
+    /// Human-readable messages.
+    Text,
+    /// One JSON object per command.
+    Json,
+    /// Just the values, for example the value of `get`.
+    Raw,
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &["text", "json", "raw"];
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "raw" => Ok(Format::Raw),
+            _ => Err(anyhow!("unknown output format '{}'", s)),
+        }
+    }
+}
+
+/// Represents the reasons the server rejects a command.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Failure {
+    NotFound,
+    AlreadyExists,
+    Identical,
+    Invalid,
+    Other,
+}
+
+impl Failure {
+    /// Classifies an error of the server rejecting a command.
+    pub fn of(err: &astrobase_client::Error) -> Option<Self> {
+        use astrobase_client::Error;
+        match err {
+            Error::NotFound(_) => Some(Failure::NotFound),
+            Error::AlreadyExists(_) => Some(Failure::AlreadyExists),
+            Error::Identical(_) => Some(Failure::Identical),
+            Error::Invalid(_) => Some(Failure::Invalid),
+            Error::Rejected(_) => Some(Failure::Other),
+            _ => None,
+        }
+    }
+
+    /// Returns the exit code of the process.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Failure::NotFound => config::NOT_FOUND,
+            Failure::AlreadyExists => config::ALREADY_EXISTS,
+            Failure::Identical => config::IDENTICAL,
+            Failure::Invalid => config::INVALID,
+            Failure::Other => config::FAILURE,
+        }
+    }
+}
+
+/// The keys with their values, None for the missing ones.
+pub type Found = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+/// Represents the result of a command.
+#[derive(Default, Serialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(rename = "error", skip_serializing_if = "Option::is_none")]
+    pub failure: Option<Failure>,
+    /// Printed in the chosen encoding.
+    #[serde(skip)]
+    pub key: Option<Vec<u8>>,
+    /// Printed in the chosen encoding.
+    #[serde(skip)]
+    pub value: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<Vec<String>>,
+    /// The keys and the values of `mget`, printed in the chosen encoding.
+    #[serde(skip)]
+    pub records: Option<Found>,
+    /// The versions of a key, the values printed in the chosen encoding.
+    #[serde(skip)]
+    pub versions: Option<Vec<Version>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exported: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inserted: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scanned: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved: Option<u64>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub message: String,
+    /// The command has written its data to stdout itself,
+    /// so only failures are printed (to stderr).
+    #[serde(skip)]
+    pub silent: bool,
+}
+
+impl Reply {
+    /// Constructs a successful reply.
+    pub fn ok(message: String) -> Self {
+        Reply {
+            ok: true,
+            message,
+            ..Reply::default()
+        }
+    }
+
+    /// Constructs a reply of a rejected command.
+    pub fn failed(failure: Failure, message: String) -> Self {
+        Reply {
+            ok: false,
+            failure: Some(failure),
+            message,
+            ..Reply::default()
+        }
+    }
+
+    /// Returns the exit code of the process.
+    pub fn exit_code(&self) -> i32 {
+        match (self.ok, self.failure) {
+            (true, _) => config::SUCCESS,
+            (false, Some(failure)) => failure.exit_code(),
+            (false, None) => config::FAILURE,
+        }
+    }
+
+    /// Prints the reply: results to stdout, failures to stderr
+    /// (in JSON both go to stdout unless the command is silent).
+    pub fn print(&self, format: Format, encoding: Encoding) {
+        if self.silent && self.ok {
+            return;
+        }
+        match format {
+            Format::Json => {
+                let json = self.json(encoding);
+                if self.silent {
+                    eprintln!("{}", json);
+                } else {
+                    println!("{}", json);
+                }
+            }
+            _ if !self.ok => eprintln!("{}", self.message),
+            Format::Text => {
+                for (key, value) in self.records.iter().flatten() {
+                    match value {
+                        Some(value) => println!(
+                            "key: '{}', value: '{}'",
+                            encoding.encode(key),
+                            encoding.encode(value)
+                        ),
+                        None => println!("key: '{}' is missing", encoding.encode(key)),
+                    }
+                }
+                for version in self.versions.iter().flatten() {
+                    match &version.value {
+                        Some(value) => println!(
+                            "seq: {}, time: {}, value: '{}'",
+                            version.seq,
+                            version.timestamp,
+                            encoding.encode(value)
+                        ),
+                        None => {
+                            println!("seq: {}, time: {}, deleted", version.seq, version.timestamp)
+                        }
+                    }
+                }
+                let mut parts = Vec::new();
+                if let Some(key) = &self.key {
+                    parts.push(format!("key: '{}'", encoding.encode(key)));
+                }
+                if let Some(value) = &self.value {
+                    parts.push(format!("value: '{}'", encoding.encode(value)));
+                }
+                if !self.message.is_empty() {
+                    parts.push(self.message.clone());
+                }
+                if !parts.is_empty() {
+                    println!("{}", parts.join(", "));
+                }
+            }
+            Format::Raw => {
+                let mut stdout = std::io::stdout();
+                if let Some(value) = &self.value {
+                    // exact bytes for pipes, line break for the terminal
+                    match encoding {
+                        Encoding::Utf8 => stdout.write_all(value).ok(),
+                        _ => write!(stdout, "{}", encoding.encode(value)).ok(),
+                    };
+                    if stdout.is_terminal() {
+                        writeln!(stdout).ok();
+                    }
+                }
+                // one line per key, empty for a missing one
+                for (_, value) in self.records.iter().flatten() {
+                    let value = value.as_deref().unwrap_or_default();
+                    writeln!(stdout, "{}", encoding.encode(value)).ok();
+                }
+                // one line per version, empty for a delete
+                for version in self.versions.iter().flatten() {
+                    let value = version.value.as_deref().unwrap_or_default();
+                    writeln!(stdout, "{}", encoding.encode(value)).ok();
+                }
+                for name in self.namespaces.iter().flatten() {
+                    writeln!(stdout, "{}", name).ok();
+                }
+                stdout.flush().ok();
+            }
+        }
+    }
+
+    /// Formats the reply as a JSON object.
+    fn json(&self, encoding: Encoding) -> String {
+        let mut json = serde_json::to_value(self).unwrap_or_default();
+        if let Some(object) = json.as_object_mut() {
+            if let Some(key) = &self.key {
+                object.insert("key".into(), encoding.encode(key).into());
+            }
+            if let Some(value) = &self.value {
+                object.insert("value".into(), encoding.encode(value).into());
+            }
+            if let Some(records) = &self.records {
+                let records: Vec<serde_json::Value> = records
+                    .iter()
+                    .map(|(key, value)| {
+                        serde_json::json!({
+                            "key": encoding.encode(key),
+                            "value": value.as_ref().map(|value| encoding.encode(value)),
+                        })
+                    })
+                    .collect();
+                object.insert("records".into(), records.into());
+            }
+            if let Some(versions) = &self.versions {
+                let versions: Vec<serde_json::Value> = versions
+                    .iter()
+                    .map(|version| {
+                        serde_json::json!({
+                            "seq": version.seq,
+                            "timestamp": version.timestamp,
+                            "value": version.value.as_ref().map(|value| encoding.encode(value)),
+                        })
+                    })
+                    .collect();
+                object.insert("versions".into(), versions.into());
+            }
+        }
+        json.to_string()
+    }
+}
+
+/// Returns the exit code for an error which prevented a command from completing.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    use astrobase_client::Error;
+
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<Error>() {
+            if let Some(failure) = Failure::of(err) {
+                return failure.exit_code();
+            }
+            return match err {
+                Error::Status(status) => match status.code() {
+                    tonic::Code::NotFound => config::NOT_FOUND,
+                    tonic::Code::AlreadyExists => config::ALREADY_EXISTS,
+                    tonic::Code::InvalidArgument | tonic::Code::OutOfRange => config::INVALID,
+                    tonic::Code::Unavailable => config::CONNECTION,
+                    tonic::Code::DeadlineExceeded => config::TIMEOUT,
+                    _ => config::FAILURE,
+                },
+                Error::Transport(_) => config::CONNECTION,
+                Error::KeyTooLong(_) | Error::ValueTooLong(_) => config::INVALID,
+                _ => config::FAILURE,
+            };
+        }
+        if cause.is::<encoding::Error>() {
+            return config::INVALID;
+        }
+    }
+    config::FAILURE
+}
@@ -0,0 +1,364 @@
+//! astrobase-client sharding: the keys are spread over several servers
+//! by consistent hashing with virtual nodes.
+
+use crate::client::{AstrobaseClient, Options, Scan, Target};
+use crate::error::{Error, Result};
+
+use astrobase_api::{At, Conflict, Version, Written};
+use std::future::Future;
+use std::sync::Arc;
+
+/// How many points every server has on the ring; more points spread the keys more evenly.
+pub const VIRTUAL_NODES: usize = 128;
+
+/// Represents the hash ring: a key belongs to the server of the first point
+/// at or after the hash of the key.
+#[derive(Clone, Debug)]
+pub struct Ring {
+    points: Vec<(u64, usize)>, // sorted hashes and the servers they belong to
+}
+
+impl Ring {
+    /// Places the points of every server by hashing its name (the endpoint),
+    /// so adding or removing a server moves only the keys it owns.
+    pub fn new(names: &[impl AsRef<str>]) -> Self {
+        let mut points = Vec::with_capacity(names.len() * VIRTUAL_NODES);
+        for (i, name) in names.iter().enumerate() {
+            for point in 0..VIRTUAL_NODES {
+                let label = format!("{}#{}", name.as_ref(), point);
+                points.push((hash(label.as_bytes()), i));
+            }
+        }
+        points.sort_unstable();
+        Ring { points }
+    }
+
+    /// Returns the index of the server owning the key.
+    pub fn owner(&self, key: &[u8]) -> usize {
+        let hash = hash(key);
+        let i = self.points.partition_point(|&(point, _)| point < hash);
+        self.points[i % self.points.len()].1
+    }
+}
+
+/// Hashes the bytes the same way in every version and on every platform:
+/// FNV-1a finished with the mixing step of MurmurHash3.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Represents the counts of a rebalancing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rebalanced {
+    pub scanned: u64,
+    pub moved: u64,
+}
+
+/// Represents connections to several servers each keeping a part of the keys
+/// (a shard), working with one namespace. Clones share the connections.
+///
+/// Every key goes to one server, so the calls on one key behave as on a single
+/// server. The calls on many keys are split between the servers and run at once;
+/// they are not atomic as a whole.
+#[derive(Clone)]
+pub struct ShardedClient {
+    endpoints: Arc<[String]>,
+    shards: Vec<AstrobaseClient>,
+    ring: Arc<Ring>,
+}
+
+impl ShardedClient {
+    /// Connects to every server; they must be listed in the same way by every
+    /// client, the endpoints place the servers on the ring.
+    pub async fn connect(targets: Vec<Target>, options: Options) -> Result<Self> {
+        if targets.is_empty() {
+            return Err(Error::EndpointInvalid(String::new()));
+        }
+        let mut endpoints = Vec::with_capacity(targets.len());
+        let mut shards = Vec::with_capacity(targets.len());
+        for target in targets {
+            if endpoints.contains(&target.endpoint) {
+                return Err(Error::EndpointInvalid(target.endpoint));
+            }
+            endpoints.push(target.endpoint.clone());
+            shards.push(AstrobaseClient::connect(target, options.clone()).await?);
+        }
+        Ok(ShardedClient {
+            ring: Arc::new(Ring::new(&endpoints)),
+            endpoints: endpoints.into(),
+            shards,
+        })
+    }
+
+    /// Returns a client sharing the connections which works with another namespace
+    /// (the default one if empty).
+    pub fn with_namespace(&self, namespace: &str) -> Self {
+        ShardedClient {
+            shards: self
+                .shards
+                .iter()
+                .map(|shard| shard.with_namespace(namespace))
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the namespace the client works with.
+    pub fn namespace(&self) -> &str {
+        self.shards[0].namespace()
+    }
+
+    /// Returns the endpoints of the servers.
+    pub fn endpoints(&self) -> &[String] {
+        &self.endpoints
+    }
+
+    /// Returns the client of the server owning the key.
+    pub fn shard(&self, key: &[u8]) -> &AstrobaseClient {
+        &self.shards[self.ring.owner(key)]
+    }
+
+    /// Returns the value of a key or None if there is no such record.
+    pub async fn get(&self, key: impl Into<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+        let key = key.into();
+        self.shard(&key).get(key).await
+    }
+
+    /// Returns the values of the keys in the same order, None for the missing ones.
+    pub async fn get_many(&self, keys: Vec<Vec<u8>>) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut groups = vec![Vec::new(); self.shards.len()];
+        for (i, key) in keys.into_iter().enumerate() {
+            groups[self.ring.owner(&key)].push((i, key));
+        }
+        let count = groups.iter().map(Vec::len).sum();
+        let found = self
+            .fan_out(groups, |shard, keys| async move {
+                let mut found = Vec::with_capacity(keys.len());
+                for (i, key) in keys {
+                    found.push((i, shard.get(key).await?));
+                }
+                Ok(found)
+            })
+            .await?;
+
+        let mut values = vec![None; count];
+        for (i, value) in found.into_iter().flatten() {
+            values[i] = value;
+        }
+        Ok(values)
+    }
+
+    /// Returns the value a key had at the moment or None if there was no such record.
+    /// The sequence numbers are those of the server owning the key.
+    pub async fn get_at(&self, key: impl Into<Vec<u8>>, at: At) -> Result<Option<Vec<u8>>> {
+        let key = key.into();
+        self.shard(&key).get_at(key, at).await
+    }
+
+    /// Returns the versions of a key, the oldest first.
+    pub async fn history(&self, key: impl Into<Vec<u8>>) -> Result<Vec<Version>> {
+        let key = key.into();
+        self.shard(&key).history(key).await
+    }
+
+    /// Inserts a new record, fails if the key exists.
+    pub async fn insert(&self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Result<()> {
+        let key = key.into();
+        self.shard(&key).insert(key, value).await
+    }
+
+    /// Changes the value of an existing record.
+    pub async fn update(&self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Result<()> {
+        let key = key.into();
+        self.shard(&key).update(key, value).await
+    }
+
+    /// Deletes a record returning its value.
+    pub async fn delete(&self, key: impl Into<Vec<u8>>) -> Result<Vec<u8>> {
+        let key = key.into();
+        self.shard(&key).delete(key).await
+    }
+
+    /// Streams the records of all servers with keys starting with the prefix, sorted by key.
+    pub async fn scan(&self, prefix: impl Into<Vec<u8>>) -> Result<MergedScan> {
+        let prefix = prefix.into();
+        let groups = vec![(); self.shards.len()];
+        let scans = self
+            .fan_out(groups, |shard, ()| {
+                let prefix = prefix.clone();
+                async move { shard.scan(prefix).await }
+            })
+            .await?;
+        Ok(MergedScan {
+            heads: Vec::with_capacity(scans.len()),
+            scans,
+        })
+    }
+
+    /// Writes the pairs, one request to every server owning some of them.
+    /// An insert-only batch may fail on one server and be written on the others.
+    pub async fn write_batch(
+        &self,
+        pairs: Vec<(Vec<u8>, Vec<u8>)>,
+        conflict: Conflict,
+    ) -> Result<Written> {
+        let mut groups = vec![Vec::new(); self.shards.len()];
+        for (key, value) in pairs {
+            groups[self.ring.owner(&key)].push((key, value));
+        }
+        let written = self
+            .fan_out(groups, |shard, pairs| async move {
+                if pairs.is_empty() {
+                    return Ok(Written::default());
+                }
+                shard.write_batch(pairs, conflict).await
+            })
+            .await?;
+        Ok(written
+            .into_iter()
+            .fold(Written::default(), |sum, written| Written {
+                inserted: sum.inserted + written.inserted,
+                updated: sum.updated + written.updated,
+                skipped: sum.skipped + written.skipped,
+            }))
+    }
+
+    /// Makes every server reload its config.
+    pub async fn reload(&self) -> Result<()> {
+        let groups = vec![(); self.shards.len()];
+        self.fan_out(groups, |shard, ()| async move { shard.reload().await })
+            .await
+            .map(drop)
+    }
+
+    /// Creates a namespace on every server.
+    pub async fn create_namespace(&self, name: &str) -> Result<()> {
+        let groups = vec![name.to_owned(); self.shards.len()];
+        self.fan_out(groups, |shard, name| async move {
+            shard.create_namespace(&name).await
+        })
+        .await
+        .map(drop)
+    }
+
+    /// Drops a namespace with all its records on every server.
+    pub async fn drop_namespace(&self, name: &str) -> Result<()> {
+        let groups = vec![name.to_owned(); self.shards.len()];
+        self.fan_out(groups, |shard, name| async move {
+            shard.drop_namespace(&name).await
+        })
+        .await
+        .map(drop)
+    }
+
+    /// Returns the names of the namespaces found on any server.
+    pub async fn list_namespaces(&self) -> Result<Vec<String>> {
+        let groups = vec![(); self.shards.len()];
+        let lists = self
+            .fan_out(
+                groups,
+                |shard, ()| async move { shard.list_namespaces().await },
+            )
+            .await?;
+        let mut names: Vec<String> = lists.into_iter().flatten().collect();
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Moves the records of the namespace to the servers owning them in `to`,
+    /// the servers after some are added or removed. Every moved record is
+    /// inserted into its new server and then deleted from the old one,
+    /// so an interrupted rebalancing can be run again.
+    pub async fn rebalance(&self, to: &ShardedClient) -> Result<Rebalanced> {
+        let to = to.with_namespace(self.namespace());
+        let mut rebalanced = Rebalanced::default();
+        for (endpoint, shard) in self.endpoints.iter().zip(&self.shards) {
+            let mut scan = shard.scan(Vec::new()).await?;
+            while let Some((key, value)) = scan.next().await? {
+                rebalanced.scanned += 1;
+                let owner = to.ring.owner(&key);
+                if &to.endpoints[owner] == endpoint {
+                    continue;
+                }
+                let target = &to.shards[owner];
+                match target.insert(key.clone(), value.clone()).await {
+                    Ok(()) | Err(Error::Identical(_)) => {}
+                    // left by an interrupted run, the old server has the current value
+                    Err(Error::AlreadyExists(_)) => match target.update(key.clone(), value).await {
+                        Ok(()) | Err(Error::Identical(_)) => {}
+                        Err(err) => return Err(err),
+                    },
+                    Err(err) => return Err(err),
+                }
+                shard.delete(key).await?;
+                rebalanced.moved += 1;
+            }
+        }
+        Ok(rebalanced)
+    }
+
+    /// Runs a call on every server at once with its part of the work,
+    /// returning the results in the order of the servers.
+    async fn fan_out<W, T, F, Fut>(&self, work: Vec<W>, call: F) -> Result<Vec<T>>
+    where
+        F: Fn(AstrobaseClient, W) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let tasks: Vec<_> = self
+            .shards
+            .iter()
+            .zip(work)
+            .map(|(shard, work)| tokio::spawn(call(shard.clone(), work)))
+            .collect();
+        let mut results = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let r = task
+                .await
+                .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+            results.push(r?);
+        }
+        Ok(results)
+    }
+}
+
+/// Represents the records streamed by `scan` from all servers, merged by key.
+pub struct MergedScan {
+    scans: Vec<Scan>,
+    heads: Vec<Option<(Vec<u8>, Vec<u8>)>>, // the next record of every server
+}
+
+impl MergedScan {
+    /// Returns the next record or None at the end.
+    pub async fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if self.heads.is_empty() {
+            for scan in &mut self.scans {
+                self.heads.push(scan.next().await?);
+            }
+        }
+        let next = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|(key, _)| (key, i)))
+            .min()
+            .map(|(_, i)| i);
+        match next {
+            Some(i) => {
+                let head = self.scans[i].next().await?;
+                Ok(std::mem::replace(&mut self.heads[i], head))
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! astrobase-client interactive shell.
+
+use crate::cli::Command;
+use crate::command;
+use crate::exec;
+use crate::output;
+
+use astrobase_api::Encoding;
+use astrobase_client::ShardedClient;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+
+const HISTORY_FILE: &str = ".astrobase_history";
+
+/// Commands handled by the shell itself.
+const BUILTINS: &[&str] = &["exit", "help", "quit", "use"];
+
+/// Reads commands from the terminal and executes them one by one.
+pub async fn run(
+    client: &ShardedClient,
+    mut namespace: String,
+    format: output::Format,
+    encoding: Encoding,
+) -> anyhow::Result<()> {
+    let mut editor = Editor::<ShellHelper>::new()?;
+    editor.set_helper(Some(ShellHelper::new()));
+    let history = history_file();
+    if let Some(history) = &history {
+        editor.load_history(history).ok();
+    }
+
+    loop {
+        let prompt = format!("astrobase:{}> ", shown(&namespace));
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        let words = match split(&line) {
+            Ok(words) => words,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                continue;
+            }
+        };
+        if words.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str());
+
+        match words[0].as_str() {
+            "exit" | "quit" => break,
+            "use" => match words.get(1) {
+                Some(name) => namespace = name.clone(),
+                None => namespace.clear(),
+            },
+            _ => {
+                let args = std::iter::once("shell").chain(words.iter().map(String::as_str));
+                match Command::from_iter_safe(args) {
+                    Ok(cmd) => {
+                        let started = Instant::now();
+                        let r = match cmd {
+                            Command::Exec {
+                                input,
+                                concurrency,
+                                continue_on_error,
+                            } => {
+                                let options = exec::Options {
+                                    concurrency,
+                                    continue_on_error,
+                                    output: format,
+                                    encoding,
+                                };
+                                exec::run(client, &namespace, &input, options).await
+                            }
+                            cmd => command::execute(client, &namespace, encoding, cmd)
+                                .await
+                                .map(|reply| reply.print(format, encoding)),
+                        };
+                        if let Err(err) = r {
+                            eprintln!("Error: {:#}", err);
+                        }
+                        println!("Time: {:.3?}", started.elapsed());
+                    }
+                    Err(err) => eprintln!("{}", err.message),
+                }
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history).ok();
+    }
+    Ok(())
+}
+
+/// Returns the namespace as shown in the prompt.
+fn shown(namespace: &str) -> &str {
+    if namespace.is_empty() {
+        "default"
+    } else {
+        namespace
+    }
+}
+
+/// Returns the history file in the home directory.
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Splits a line into words; single and double quotes group words,
+/// backslash escapes the next character.
+pub fn split(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (_, '\\') => {
+                let next = chars
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("trailing backslash"))?;
+                word.get_or_insert_with(String::new).push(next);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(anyhow::anyhow!("unterminated quote"));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Represents the line editor helper completing command names.
+struct ShellHelper {
+    commands: Vec<String>,
+}
+
+impl ShellHelper {
+    fn new() -> Self {
+        // The subcommands are taken from the parser, so new commands are completed too.
+        let mut commands: Vec<String> = Command::clap()
+            .p
+            .subcommands
+            .iter()
+            .map(|app| app.get_name().to_owned())
+            .filter(|name| name != "shell")
+            .chain(BUILTINS.iter().map(|&name| name.to_owned()))
+            .collect();
+        commands.sort();
+        ShellHelper { commands }
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let head = &line[..pos];
+        let word = head.trim_start();
+        if word.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+
+        let candidates = self
+            .commands
+            .iter()
+            .filter(|name| name.starts_with(word))
+            .cloned()
+            .collect();
+        Ok((pos - word.len(), candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
@@ -0,0 +1,126 @@
+use crate::client::Call;
+use crate::{AstrobaseClient, Error, Options, Ring, Target, WriteRetry};
+
+use std::path::PathBuf;
+use std::time::Duration;
+use tonic::Status;
+
+fn keys() -> impl Iterator<Item = Vec<u8>> {
+    (0..10_000).map(|i| format!("key-{}", i).into_bytes())
+}
+
+#[test]
+fn test_ring_spreads_keys() {
+    let ring = Ring::new(&["a:1", "b:2", "c:3"]);
+    let mut counts = [0; 3];
+    for key in keys() {
+        counts[ring.owner(&key)] += 1;
+    }
+    for count in counts {
+        assert!(count > 2_500 && count < 4_200, "{:?}", counts);
+    }
+}
+
+#[test]
+fn test_ring_moves_keys_only_to_added_server() {
+    let before = Ring::new(&["a:1", "b:2", "c:3"]);
+    let after = Ring::new(&["a:1", "b:2", "c:3", "d:4"]);
+    let mut moved = 0;
+    for key in keys() {
+        let owner = after.owner(&key);
+        if owner != before.owner(&key) {
+            assert_eq!(owner, 3);
+            moved += 1;
+        }
+    }
+    assert!(moved > 1_500 && moved < 3_500, "{}", moved);
+}
+
+#[test]
+fn test_ring_does_not_depend_on_order() {
+    let ring = Ring::new(&["a:1", "b:2"]);
+    let reversed = Ring::new(&["b:2", "a:1"]);
+    for key in keys() {
+        assert_eq!(ring.owner(&key), 1 - reversed.owner(&key));
+    }
+}
+
+fn tls_error(target: Target) -> String {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let connected = runtime.block_on(AstrobaseClient::connect(target, Options::default()));
+    match connected {
+        Err(Error::TlsInvalid(reason)) => reason.to_owned(),
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("connected"),
+    }
+}
+
+#[test]
+fn test_connect_rejects_incomplete_tls() {
+    let target = |ca: Option<&str>, cert: Option<&str>, key: Option<&str>| Target {
+        ca: ca.map(PathBuf::from),
+        cert: cert.map(PathBuf::from),
+        key: key.map(PathBuf::from),
+        ..Target::new("https://[::1]:1")
+    };
+    assert_eq!(
+        tls_error(target(None, Some("client.pem"), Some("client.key"))),
+        "the client certificate needs a CA certificate"
+    );
+    assert_eq!(
+        tls_error(Target {
+            domain: Some("localhost".into()),
+            ..target(None, None, None)
+        }),
+        "the domain needs a CA certificate"
+    );
+    let ca = "/tmp/astrobase-client-ca.pem";
+    std::fs::write(ca, "").unwrap();
+    let cert_only = tls_error(target(Some(ca), Some("client.pem"), None));
+    let key_only = tls_error(target(Some(ca), None, Some("client.key")));
+    std::fs::remove_file(ca).unwrap();
+    assert_eq!(cert_only, "the client certificate needs its key");
+    assert_eq!(key_only, "the key needs its client certificate");
+}
+
+#[test]
+fn test_retries_reads_and_unsent_writes() {
+    let options = Options::default();
+    let unavailable = Status::unavailable("down");
+    let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+    let refused = Status::from_error(Box::new(refused));
+    let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+    let reset = Status::from_error(Box::new(reset));
+
+    assert!(options.retryable(Call::Read, &unavailable));
+    assert!(!options.retryable(Call::Read, &Status::not_found("k")));
+    // A write may have been applied unless the connection was refused.
+    assert!(options.retryable(Call::Write, &refused));
+    assert!(!options.retryable(Call::Write, &reset));
+    assert!(!options.retryable(Call::Write, &unavailable));
+    let never = Options {
+        write_retry: WriteRetry::Never,
+        ..Options::default()
+    };
+    assert!(!never.retryable(Call::Write, &refused));
+    assert!(never.retryable(Call::Read, &unavailable));
+}
+
+#[test]
+fn test_backoff_doubles_up_to_the_limit() {
+    let options = Options {
+        backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_secs(1),
+        ..Options::default()
+    };
+    let within = |attempt, millis: u64| {
+        let delay = options.backoff(attempt);
+        let max = Duration::from_millis(millis);
+        assert!(delay >= max / 2 && delay <= max, "{}: {:?}", attempt, delay);
+    };
+    within(0, 100);
+    within(1, 200);
+    within(3, 800);
+    within(4, 1000);
+    within(u32::MAX, 1000);
+}
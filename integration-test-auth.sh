@@ -0,0 +1,189 @@
+#!/usr/bin/env bash
+# Integration testing for astrobase-server with bearer token authentication.
+# Requires Rust installed.
+
+srv="astrobase-server"
+cli="cli"
+bin="./target/release"
+cfg="/tmp/astrobase-integration-testing.json"
+out="/tmp/astrobase-server.out"
+
+function check_exit {
+    result=$?
+    #echo "Result: $result"
+    if [ $result -ne 0 ]; then
+	killall $srv
+	echo "FAIL"
+	exit $result
+    fi
+}
+
+function check_failure {
+    result=$?
+    if [ $result -eq 0 ]; then
+	killall $srv
+	echo "FAIL: request should be refused"
+	exit 1
+    fi
+}
+
+function check_substring {
+    haystack=$1
+    needle=$2
+    if [[ $haystack != *$needle* ]]; then
+	echo "FAIL: $1"
+	echo "SHOULD CONTAIN: $2"
+	killall $srv
+	exit
+    fi
+}
+
+function check_output {
+    counter=$1
+    sleep 1s
+
+    # ensure full line is dumped
+    i=0
+    stats=""
+    stats_ensure="some"
+    while [[ $i<10 && $stats != $stats_ensure ]]
+    do
+        sleep 0.1s
+	stats=$(tail -1 $out)
+	stats_ensure=$(tail -1 $out)
+	((i=i+1))
+    done
+
+    if [[ $i>=10 ]]
+    then
+	echo "Wrong output: $stats"
+	killall $srv
+	exit
+    fi
+
+    check_substring "$stats" "$counter"
+}
+
+function build {
+    echo "Building..."
+    cargo build --quiet --release --no-default-features --features inmemory
+    check_exit
+}
+
+function start_server {
+    echo
+    echo "Starting server..."
+    cat << EOF > $cfg
+{
+    "environment": "integration-testing",
+    "server": {
+	"endpoint": "[::1]:50051"
+    },
+    "auth": {
+	"users": [
+	    {
+		"name": "root",
+		"token": "root-secret",
+		"grants": [ { "prefix": "", "access": "admin" } ]
+	    },
+	    {
+		"name": "alice",
+		"token": "alice-secret",
+		"grants": [
+		    { "prefix": "alice/", "access": "write" },
+		    { "prefix": "public/", "access": "read" }
+		]
+	    }
+	]
+    },
+    "memcached": {
+	"endpoint": "127.0.0.1:50078"
+    },
+    "monitoring": {
+	"interval": 1
+    }
+}
+EOF
+    $bin/$srv --config $cfg run 2>$out &
+    sleep 1s
+}
+
+function stop_server {
+    echo
+    echo "Stopping server..."
+    killall $srv
+}
+
+function test_no_token {
+    echo
+    echo "test_no_token"
+    $bin/$cli get alice/smoke
+    check_failure
+    $bin/$cli --token garbage get alice/smoke
+    check_failure
+}
+
+function test_own_prefix {
+    echo
+    echo "test_own_prefix"
+    $bin/$cli --token alice-secret insert alice/smoke test
+    check_exit
+    check_output "INSERT(ok/fail):(1, 0)"
+    $bin/$cli --token alice-secret get alice/smoke
+    check_exit
+    check_output "GET(ok/fail):(1, 0)"
+}
+
+function test_read_only_prefix {
+    echo
+    echo "test_read_only_prefix"
+    $bin/$cli --token root-secret insert public/smoke test
+    check_exit
+    $bin/$cli --token alice-secret get public/smoke
+    check_exit
+    $bin/$cli --token alice-secret update public/smoke garbage
+    check_failure
+    check_output "DENIED(get/insert/delete/update):(0, 0, 0, 1)"
+}
+
+function test_foreign_prefix {
+    echo
+    echo "test_foreign_prefix"
+    $bin/$cli --token alice-secret get bob/smoke
+    check_failure
+    check_output "DENIED(get/insert/delete/update):(1, 0, 0, 1)"
+}
+
+function test_admin_only_reload {
+    echo
+    echo "test_admin_only_reload"
+    $bin/$cli --token alice-secret reload
+    check_failure
+    $bin/$cli --token root-secret reload
+    check_exit
+}
+
+function test_memcached {
+    echo
+    echo "test_memcached"
+    exec 3<>/dev/tcp/127.0.0.1/50078
+    printf 'get alice/smoke\r\nauth garbage\r\nauth alice-secret\r\nget alice/smoke\r\nquit\r\n' >&3
+    replies=$(cat <&3)
+    exec 3<&-
+    check_substring "$replies" $'CLIENT_ERROR Bearer token is missing\r\nCLIENT_ERROR Bearer token is invalid\r\nOK\r\n'
+    check_substring "$replies" $'VALUE alice/smoke 0 4\r\ntest\r\nEND'
+}
+
+build
+start_server
+
+test_no_token
+test_own_prefix
+test_read_only_prefix
+test_foreign_prefix
+test_admin_only_reload
+test_memcached
+
+stop_server
+
+echo "OK"
@@ -0,0 +1,206 @@
+#!/usr/bin/env bash
+# Integration testing for astrobase-server in cluster mode:
+# three in-memory members on localhost, some of them killed on the way.
+# Requires Rust installed.
+
+srv="astrobase-server"
+cli="cli"
+bin="./target/release"
+prefix="/tmp/astrobase-cluster"
+ports=(50061 50062 50063)
+pids=("" "" "")
+
+function check_exit {
+    result=$?
+    if [ $result -ne 0 ]; then
+	killall $srv
+	echo "FAIL"
+	exit $result
+    fi
+}
+
+function check_failed {
+    result=$?
+    if [ $result -eq 0 ]; then
+	killall $srv
+	echo "FAIL: the command should fail"
+	exit 1
+    fi
+}
+
+function check_substring {
+    haystack=$1
+    needle=$2
+    if [[ $haystack != *"$needle"* ]]; then
+	echo "FAIL: $1"
+	echo "SHOULD CONTAIN: $2"
+	killall $srv
+	exit 1
+    fi
+}
+
+function build {
+    echo "Building..."
+    cargo build --quiet --release
+    check_exit
+}
+
+function endpoint {
+    echo "http://[::1]:${ports[$1]}"
+}
+
+function start_node {
+    node=$1
+    cat << EOF > $prefix-$node.json
+{
+    "environment": "integration-testing",
+    "server": {
+	"endpoint": "[::1]:${ports[$node]}"
+    },
+    "database": {
+	"path": "$prefix-$node.db"
+    },
+    "cluster": {
+	"node": "n$node",
+	"members": [
+	    {"id": "n0", "endpoint": "$(endpoint 0)"},
+	    {"id": "n1", "endpoint": "$(endpoint 1)"},
+	    {"id": "n2", "endpoint": "$(endpoint 2)"}
+	],
+	"election_timeout": 500,
+	"heartbeat": 50
+    },
+    "monitoring": {
+	"interval": 60
+    }
+}
+EOF
+    $bin/$srv --config $prefix-$node.json run >>$prefix-$node.out 2>&1 &
+    pids[$node]=$!
+}
+
+function kill_node {
+    echo "Killing n$1..."
+    kill -9 ${pids[$1]}
+    pids[$1]=""
+}
+
+# Sets $leader to the member accepting writes, waiting for an election.
+function find_leader {
+    leader=""
+    for attempt in $(seq 1 50); do
+	for node in 0 1 2; do
+	    if [[ -n ${pids[$node]} ]] && $bin/$cli --endpoint $(endpoint $node) --timeout 1000 insert probe-$attempt-$node x >/dev/null 2>&1; then
+		leader=$node
+		echo "The leader is n$leader"
+		return
+	    fi
+	done
+	sleep 0.2s
+    done
+    killall $srv
+    echo "FAIL: no leader elected"
+    exit 1
+}
+
+function follower_of {
+    echo $(( ($1 + 1) % 3 ))
+}
+
+function test_election {
+    echo
+    echo "test_election"
+    rm -f $prefix-*
+    for node in 0 1 2; do
+	start_node $node
+    done
+    find_leader
+}
+
+function test_reads {
+    echo
+    echo "test_reads"
+    $bin/$cli --endpoint $(endpoint $leader) insert planet mars
+    check_exit
+    value=$($bin/$cli --endpoint $(endpoint $leader) --output raw get planet)
+    check_exit
+    check_substring "value=$value;" "value=mars;"
+
+    follower=$(follower_of $leader)
+    sleep 0.5s
+    value=$($bin/$cli --endpoint $(endpoint $follower) --consistency stale --output raw get planet)
+    check_exit
+    check_substring "value=$value;" "value=mars;"
+    exported=$($bin/$cli --endpoint $(endpoint $follower) --consistency stale export)
+    check_exit
+    check_substring "$exported" "mars"
+    message=$($bin/$cli --endpoint $(endpoint $follower) get planet 2>&1)
+    check_failed
+    check_substring "$message" "leader at $(endpoint $leader)"
+    $bin/$cli --endpoint $(endpoint $follower) insert moon europa 2>/dev/null
+    check_failed
+}
+
+function test_leader_killed {
+    echo
+    echo "test_leader_killed"
+    killed=$leader
+    kill_node $killed
+    find_leader
+    if [[ $leader == $killed ]]; then
+	killall $srv
+	echo "FAIL: the killed member is the leader"
+	exit 1
+    fi
+    value=$($bin/$cli --endpoint $(endpoint $leader) --output raw get planet)
+    check_exit
+    check_substring "value=$value;" "value=mars;"
+    $bin/$cli --endpoint $(endpoint $leader) update planet venus
+    check_exit
+    # The new leader counts the records it applied as a follower.
+    $bin/$cli --endpoint $(endpoint $leader) delete planet
+    check_exit
+    $bin/$cli --endpoint $(endpoint $leader) insert planet venus
+    check_exit
+}
+
+function test_restart {
+    echo
+    echo "test_restart"
+    start_node $killed
+    sleep 2s
+    value=$($bin/$cli --endpoint $(endpoint $killed) --consistency stale --output raw get planet)
+    check_exit
+    check_substring "value=$value;" "value=venus;"
+}
+
+function test_no_majority {
+    echo
+    echo "test_no_majority"
+    survivor=$(follower_of $leader)
+    for node in 0 1 2; do
+	if [[ $node != $survivor ]]; then
+	    kill_node $node
+	fi
+    done
+    sleep 1s
+    $bin/$cli --endpoint $(endpoint $survivor) --timeout 2000 --retries 0 insert moon io 2>/dev/null
+    check_failed
+    value=$($bin/$cli --endpoint $(endpoint $survivor) --consistency stale --output raw get planet)
+    check_exit
+    check_substring "value=$value;" "value=venus;"
+}
+
+build
+
+test_election
+test_reads
+test_leader_killed
+test_restart
+test_no_majority
+
+echo
+echo "Stopping cluster..."
+killall $srv
+
+echo "OK"
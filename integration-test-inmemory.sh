@@ -1,12 +1,13 @@
 #!/usr/bin/env bash
 # Integration testing for astrobase-server with in-memory database.
-# Requires Rust installed.
+# Requires Rust and curl installed.
 
 srv="astrobase-server"
 cli="cli"
 bin="./target/release"
 cfg="/tmp/astrobase-integration-testing.json"
 out="/tmp/astrobase-server.out"
+sock="/tmp/astrobase-integration-testing.sock"
 
 function check_exit {
     result=$?
@@ -18,6 +19,16 @@ function check_exit {
     fi
 }
 
+function check_exit_code {
+    result=$?
+    expected=$1
+    if [ $result -ne $expected ]; then
+	killall $srv
+	echo "FAIL: exit code $result instead of $expected"
+	exit 1
+    fi
+}
+
 function check_substring {
     haystack=$1
     needle=$2
@@ -71,7 +82,20 @@ function start_server {
 {
     "environment": "integration-testing",
     "server": {
-	"endpoint": "[::1]:50051"
+	"endpoint": "[::1]:50051",
+	"unix": {
+	    "path": "$sock",
+	    "mode": "600"
+	}
+    },
+    "gateway": {
+	"endpoint": "127.0.0.1:50080"
+    },
+    "resp": {
+	"endpoint": "127.0.0.1:50079"
+    },
+    "memcached": {
+	"endpoint": "127.0.0.1:50078"
     },
     "monitoring": {
 	"interval": 1
@@ -100,15 +124,16 @@ function test_failing_insert {
     echo
     echo "test_failing_insert"
     $bin/$cli insert smoke test
-    check_exit
+    check_exit_code 3
     check_output "NR:1" "INSERT(ok/fail):(1, 1)"
 }
 
 function test_successful_get {
     echo
     echo "test_successful_get"
-    $bin/$cli get smoke
+    value=$($bin/$cli --output raw get smoke)
     check_exit
+    check_substring "value=$value;" "value=test;"
     check_output "NR:1" "GET(ok/fail):(1, 0)"
 }
 
@@ -116,7 +141,7 @@ function test_failing_get {
     echo
     echo "test_failing_get"
     $bin/$cli get garbage
-    check_exit
+    check_exit_code 2
     check_output "NR:1" "GET(ok/fail):(1, 1)"
 }
 
@@ -132,10 +157,10 @@ function test_failing_update {
     echo
     echo "test_failing_update"
     $bin/$cli update garbage garbage
-    check_exit
+    check_exit_code 2
     check_output "NR:1" "UPDATE(ok/fail):(1, 1)"
     $bin/$cli update smoke "on the water"
-    check_exit
+    check_exit_code 4
     check_output "NR:1" "UPDATE(ok/fail):(1, 2)"
 }
 
@@ -154,11 +179,186 @@ function test_failing_delete {
     echo
     echo "test_failing_delete"
     $bin/$cli delete garbage
-    check_exit
+    check_exit_code 2
     check_output "NR:1" "DELETE(ok/fail):(1, 1)"
 }
 
+function test_namespaces {
+    echo
+    echo "test_namespaces"
+    $bin/$cli create-namespace team
+    check_exit
+    $bin/$cli --namespace team insert brick "another brick"
+    check_exit
+    check_output "NR:2" "INSERT(ok/fail):(3, 1)"
+    $bin/$cli --namespace team get brick
+    check_exit
+    check_output "NR:2" "GET(ok/fail):(2, 1)"
+    $bin/$cli drop-namespace team
+    check_exit
+    check_output "NR:1" "GET(ok/fail):(1, 1)"
+}
+
+function test_bulk {
+    echo
+    echo "test_bulk"
+    bulk="/tmp/astrobase-bulk"
+    printf '%s\n' '{"key":"bulk-lines","value":"one\ntwo ∑"}' '{"key":"bulk-tab","value":"a\tb"}' > $bulk.ndjson
+    $bin/$cli import $bulk.ndjson
+    check_exit
+    check_output "NR:3" "INSERT(ok/fail):(4, 1)"
+    $bin/$cli export --prefix bulk- $bulk.csv
+    check_exit
+    $bin/$cli import --conflict overwrite $bulk.csv
+    check_exit
+    check_output "NR:3" "INSERT(ok/fail):(4, 1)"
+    $bin/$cli export --prefix bulk- 2>/dev/null | cmp - $bulk.ndjson
+    check_exit
+}
+
+function test_binary {
+    echo
+    echo "test_binary"
+    binary="/tmp/astrobase-binary"
+    printf '\x00\xff\n\x01' > $binary
+    $bin/$cli --encoding hex insert 00ff 00ff0a01
+    check_exit
+    check_output "NR:4" "INSERT(ok/fail):(5, 1)"
+    $bin/$cli --encoding base64 get AP8= --out $binary.out
+    check_exit
+    cmp $binary $binary.out
+    check_exit
+}
+
 build
+function test_gateway {
+    echo
+    echo "test_gateway"
+    url="http://127.0.0.1:50080/v1/keys"
+    code=$(curl -s -o /dev/null -w '%{http_code}' -X POST -d '{"value":"web"}' $url/gateway)
+    check_substring "code=$code;" "code=201;"
+    check_output "NR:5" "INSERT(ok/fail):(6, 1)"
+    value=$($bin/$cli --output raw get gateway)
+    check_exit
+    check_substring "value=$value;" "value=web;"
+    body=$(curl -s -X POST -d '{"value":"web"}' $url/gateway)
+    check_substring "$body" '"kind":"already_exists"'
+    check_output "NR:5" "INSERT(ok/fail):(6, 2)"
+    body=$(curl -s "$url/6761746577617900?encoding=hex")
+    check_substring "$body" '"kind":"not_found"'
+    code=$(head -c 7000000 /dev/zero | curl -s -o /dev/null -w '%{http_code}' -X POST --data-binary @- $url/huge)
+    check_substring "code=$code;" "code=413;"
+}
+
+function test_resp {
+    echo
+    echo "test_resp"
+    exec 3<>/dev/tcp/127.0.0.1/50079
+    printf 'PING\r\nSET redis value NX\r\nSET redis value NX\r\nGET redis\r\nEXISTS redis none\r\nSCAN 0 MATCH red*\r\nQUIT\r\n' >&3
+    replies=$(cat <&3)
+    exec 3<&-
+    check_substring "$replies" "+PONG"
+    check_substring "$replies" "-EXISTS Record 'redis' already exists"
+    check_substring "$replies" $'$5\r\nvalue'
+    check_substring "$replies" ":1"
+    check_substring "$replies" $'*2\r\n$1\r\n0\r\n*1\r\n$5\r\nredis'
+    check_output "NR:6" "INSERT(ok/fail):(7, 3)"
+}
+
+function test_memcached {
+    echo
+    echo "test_memcached"
+    exec 3<>/dev/tcp/127.0.0.1/50078
+    # The data block of a refused command is skipped, not run as a command.
+    printf 'add memcached 0 0 5\r\nvalue\r\nadd memcached 0 0 5\r\nvalue\r\nset memcached x 0 18\r\ndelete memcached\r\n\r\nget memcached\r\nquit\r\n' >&3
+    replies=$(cat <&3)
+    exec 3<&-
+    check_substring "$replies" $'STORED\r\nNOT_STORED'
+    check_substring "$replies" $'NOT_STORED\r\nCLIENT_ERROR bad command line format\r\nVALUE'
+    check_substring "$replies" $'VALUE memcached 0 5\r\nvalue\r\nEND'
+    check_output "NR:7" "INSERT(ok/fail):(8, 4)"
+}
+
+function test_unix {
+    echo
+    echo "test_unix"
+    mode=$(stat -c %a $sock)
+    check_substring "mode=$mode;" "mode=600;"
+    $bin/$cli --endpoint unix://$sock insert unix socket
+    check_exit
+    check_output "NR:8" "INSERT(ok/fail):(9, 4)"
+    value=$($bin/$cli --endpoint unix://$sock --output raw get unix)
+    check_exit
+    check_substring "value=$value;" "value=socket;"
+}
+
+function start_shard {
+    port=$1
+    cat << EOF > /tmp/astrobase-shard-$port.json
+{
+    "environment": "integration-testing",
+    "server": {
+	"endpoint": "[::1]:$port"
+    },
+    "monitoring": {
+	"interval": 60
+    }
+}
+EOF
+    $bin/$srv --config /tmp/astrobase-shard-$port.json run >/dev/null 2>&1 &
+}
+
+function test_sharding {
+    echo
+    echo "test_sharding"
+    start_shard 50071
+    start_shard 50072
+    sleep 1s
+    two="http://[::1]:50051,http://[::1]:50071"
+    three="$two,http://[::1]:50072"
+    $bin/$cli --endpoint $three create-namespace shards
+    check_exit
+    for i in $(seq 1 100); do echo "{\"key\":\"k$i\",\"value\":\"v$i\"}"; done > /tmp/astrobase-shards.ndjson
+    $bin/$cli --endpoint $two -n shards import /tmp/astrobase-shards.ndjson
+    check_exit
+    count=$($bin/$cli --endpoint http://[::1]:50071 -n shards export 2>/dev/null | wc -l)
+    if [[ $count -eq 0 || $count -eq 100 ]]; then
+	echo "FAIL: $count of 100 records on the second shard"
+	killall $srv
+	exit 1
+    fi
+    values=$($bin/$cli --endpoint $two -n shards --output raw mget k1 k2 k99 | tr '\n' ' ')
+    check_exit
+    check_substring "values=$values;" "values=v1 v2 v99 ;"
+
+    message=$($bin/$cli --endpoint $two -n shards rebalance --to $three)
+    check_exit
+    check_substring "$message" "100 records scanned"
+    count=$($bin/$cli --endpoint http://[::1]:50072 -n shards export 2>/dev/null | wc -l)
+    if [[ $count -eq 0 ]]; then
+	echo "FAIL: no records moved to the new shard"
+	killall $srv
+	exit 1
+    fi
+    message=$($bin/$cli --endpoint $three -n shards rebalance --to $three)
+    check_exit
+    check_substring "$message" "0 moved"
+    # the merged export is sorted by key as a single server's one
+    LC_ALL=C sort /tmp/astrobase-shards.ndjson > /tmp/astrobase-shards.sorted
+    $bin/$cli --endpoint $three -n shards export 2>/dev/null | cmp - /tmp/astrobase-shards.sorted
+    check_exit
+}
+
+function test_unix_removed {
+    echo
+    echo "test_unix_removed"
+    sleep 2s
+    if [ -e $sock ]; then
+	echo "FAIL: $sock is left after stop"
+	exit 1
+    fi
+}
+
 start_server
 
 test_successful_insert
@@ -173,6 +373,24 @@ test_failing_update
 test_successful_delete
 test_failing_delete
 
+test_namespaces
+
+test_bulk
+
+test_binary
+
+test_gateway
+
+test_resp
+
+test_memcached
+
+test_unix
+
+test_sharding
+
 stop_server
 
+test_unix_removed
+
 echo "OK"
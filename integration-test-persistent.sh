@@ -8,6 +8,14 @@ bin="./target/release"
 cfg="/tmp/astrobase-integration-testing.json"
 db="/tmp/astrobase.db"
 out="/tmp/astrobase-server.out"
+follower="http://[::1]:50052"
+follower_cfg="/tmp/astrobase-integration-follower.json"
+follower_db="/tmp/astrobase-follower.db"
+follower_out="/tmp/astrobase-follower.out"
+archive="/tmp/astrobase-backup.bin"
+restored="http://[::1]:50053"
+restored_cfg="/tmp/astrobase-integration-restored.json"
+restored_db="/tmp/astrobase-restored.db"
 
 function check_exit {
     result=$?
@@ -19,6 +27,16 @@ function check_exit {
     fi
 }
 
+function check_exit_code {
+    result=$?
+    expected=$1
+    if [ $result -ne $expected ]; then
+	killall $srv
+	echo "FAIL: exit code $result instead of $expected"
+	exit 1
+    fi
+}
+
 function check_substring {
     haystack=$1
     needle=$2
@@ -68,12 +86,22 @@ function build {
 function start_server {
     echo
     echo "Starting server..."
+    # The server indexes the files when it starts, so they are removed before.
+    rm -f $db $db.* /tmp/astrobase.*.db /tmp/astrobase.*.db.*
     cat << EOF > $cfg
 {
     "environment": "integration-testing",
     "server": {
 	"endpoint": "[::1]:50051"
     },
+    "database": {
+	"path": "$db",
+	"segment_size": 64,
+	"merge_interval": 1
+    },
+    "replication": {
+	"journal": 100
+    },
     "monitoring": {
 	"interval": 1
     }
@@ -89,12 +117,36 @@ function stop_server {
     killall $srv
 }
 
+function start_follower {
+    echo
+    echo "Starting follower..."
+    cat << EOF > $follower_cfg
+{
+    "environment": "integration-testing",
+    "server": {
+	"endpoint": "[::1]:50052"
+    },
+    "database": {
+	"path": "$follower_db"
+    },
+    "replication": {
+	"leader": "http://[::1]:50051"
+    },
+    "monitoring": {
+	"interval": 60
+    }
+}
+EOF
+    $bin/$srv --config $follower_cfg run >>$follower_out 2>&1 &
+    follower_pid=$!
+    sleep 1s
+}
+
 function test_no_db {
     echo
     echo "test_no_db"
-    rm -f $db
     $bin/$cli get test
-    check_exit
+    check_exit_code 2
     check_output "NR:0" "GET(ok/fail):(0, 1)"
 }
 
@@ -110,15 +162,16 @@ function test_failing_insert {
     echo
     echo "test_failing_insert"
     $bin/$cli insert smoke test
-    check_exit
+    check_exit_code 3
     check_output "NR:1" "INSERT(ok/fail):(1, 1)"
 }
 
 function test_successful_get {
     echo
     echo "test_successful_get"
-    $bin/$cli get smoke
+    value=$($bin/$cli --output raw get smoke)
     check_exit
+    check_substring "value=$value;" "value=test;"
     check_output "NR:1" "GET(ok/fail):(1, 1)"
 }
 
@@ -126,7 +179,7 @@ function test_failing_get {
     echo
     echo "test_failing_get"
     $bin/$cli get garbage
-    check_exit
+    check_exit_code 2
     check_output "NR:1" "GET(ok/fail):(1, 2)"
 }
 
@@ -142,10 +195,10 @@ function test_failing_update {
     echo
     echo "test_failing_update"
     $bin/$cli update garbage garbage
-    check_exit
+    check_exit_code 2
     check_output "NR:1" "UPDATE(ok/fail):(1, 1)"
     $bin/$cli update smoke "on the water"
-    check_exit
+    check_exit_code 4
     check_output "NR:1" "UPDATE(ok/fail):(1, 2)"
 }
 
@@ -164,10 +217,148 @@ function test_failing_delete {
     echo
     echo "test_failing_delete"
     $bin/$cli delete garbage
-    check_exit
+    check_exit_code 2
     check_output "NR:1" "DELETE(ok/fail):(1, 1)"
 }
 
+function test_bulk {
+    echo
+    echo "test_bulk"
+    bulk="/tmp/astrobase-bulk"
+    printf '%s\n' '{"key":"bulk-lines","value":"one\ntwo ∑"}' '{"key":"bulk-tab","value":"a\tb"}' > $bulk.ndjson
+    $bin/$cli import $bulk.ndjson
+    check_exit
+    check_output "NR:3" "INSERT(ok/fail):(4, 1)"
+    $bin/$cli export --prefix bulk- $bulk.csv
+    check_exit
+    $bin/$cli import --conflict overwrite $bulk.csv
+    check_exit
+    check_output "NR:3" "INSERT(ok/fail):(4, 1)"
+    $bin/$cli export --prefix bulk- 2>/dev/null | cmp - $bulk.ndjson
+    check_exit
+}
+
+function test_replication {
+    echo
+    echo "test_replication"
+    rm -f /tmp/astrobase-follower.* $follower_out
+    start_follower
+    $bin/$cli --endpoint $follower export --prefix bulk- 2>/dev/null | cmp - /tmp/astrobase-bulk.ndjson
+    check_exit
+    $bin/$cli --endpoint $follower insert replica one
+    check_exit_code 1
+    $bin/$cli insert replica one
+    check_exit
+    sleep 0.5s
+    value=$($bin/$cli --endpoint $follower --output raw get replica)
+    check_exit
+    check_substring "value=$value;" "value=one;"
+
+    echo "Restarting follower..."
+    kill $follower_pid
+    sleep 2s
+    $bin/$cli update replica two
+    check_exit
+    start_follower
+    value=$($bin/$cli --endpoint $follower --output raw get replica)
+    check_exit
+    check_substring "value=$value;" "value=two;"
+    snapshots=$(grep -c "Synced with the leader" $follower_out)
+    check_substring "snapshots=$snapshots;" "snapshots=1;"
+}
+
+function test_history {
+    echo
+    echo "test_history"
+    history=$($bin/$cli --output json history replica)
+    check_exit
+    check_substring "$history" '"message":"2 versions"'
+    seq=$(echo "$history" | grep -o '"seq":[0-9]*' | head -1 | cut -d: -f2)
+    value=$($bin/$cli --output raw get replica --at-seq $seq)
+    check_exit
+    check_substring "value=$value;" "value=one;"
+    $bin/$cli get replica --at-time 1
+    check_exit_code 2
+}
+
+function test_backup {
+    echo
+    echo "test_backup"
+    rm -f /tmp/astrobase-restored.* $archive
+    $bin/$cli create-namespace archived
+    check_exit
+    $bin/$cli --namespace archived insert planet mars
+    check_exit
+    $bin/$srv --config $cfg backup $archive
+    check_exit
+    cat << EOF > $restored_cfg
+{
+    "environment": "integration-testing",
+    "server": {
+	"endpoint": "[::1]:50053"
+    },
+    "database": {
+	"path": "$restored_db"
+    },
+    "monitoring": {
+	"interval": 60
+    }
+}
+EOF
+    head -c -1 $archive > $archive.cut
+    $bin/$srv --config $restored_cfg restore $archive.cut 2>/dev/null
+    check_exit_code 1
+    $bin/$srv --config $restored_cfg restore $archive
+    check_exit
+    $bin/$srv --config $restored_cfg restore $archive 2>/dev/null
+    check_exit_code 1
+
+    $bin/$srv --config $restored_cfg run >/dev/null 2>&1 &
+    restored_pid=$!
+    sleep 1s
+    $bin/$cli export /tmp/astrobase-leader.ndjson
+    check_exit
+    $bin/$cli --endpoint $restored export 2>/dev/null | cmp - /tmp/astrobase-leader.ndjson
+    check_exit
+    value=$($bin/$cli --endpoint $restored --namespace archived --output raw get planet)
+    check_exit
+    check_substring "value=$value;" "value=mars;"
+    kill $restored_pid
+}
+
+function test_segments {
+    echo
+    echo "test_segments"
+    for i in 1 2 3 4 5 6; do
+	$bin/$cli insert segment-$i value-$i
+	check_exit
+    done
+    sleep 2s
+    report=$($bin/$srv inspect $db)
+    check_exit
+    check_substring "$report" "Segments: 2"
+    value=$($bin/$cli --output raw get segment-3)
+    check_exit
+    check_substring "value=$value;" "value=value-3;"
+}
+
+function test_inspect {
+    echo
+    echo "test_inspect"
+    report=$($bin/$srv inspect $db --key replica --dump)
+    check_exit
+    check_substring "$report" "Invalid lines: 0"
+    check_substring "$report" "History of 'replica': 2 lines"
+    check_substring "$report" "put 'two'"
+    check_substring "$report" "brick	wall"
+    cp $db $db.broken
+    printf 'garbage' >> $db.broken
+    report=$($bin/$srv inspect $db.broken)
+    check_exit_code 1
+    check_substring "$report" "Invalid lines: 1"
+    rm -f $db.broken
+}
+
 build
 start_server
 
@@ -185,6 +376,18 @@ test_failing_update
 test_successful_delete
 test_failing_delete
 
+test_bulk
+
+test_replication
+
+test_history
+
+test_backup
+
+test_segments
+
+test_inspect
+
 stop_server
 
 echo "OK"
@@ -0,0 +1,206 @@
+#!/usr/bin/env bash
+# Integration testing for astrobase-server with TLS and mutual TLS.
+# Requires Rust and openssl installed.
+
+srv="astrobase-server"
+cli="cli"
+bin="./target/release"
+cfg="/tmp/astrobase-integration-testing.json"
+pki="/tmp/astrobase-pki"
+out="/tmp/astrobase-server.out"
+endpoint="https://[::1]:50051"
+follower_cfg="/tmp/astrobase-integration-follower.json"
+follower_out="/tmp/astrobase-follower.out"
+
+function check_exit {
+    result=$?
+    #echo "Result: $result"
+    if [ $result -ne 0 ]; then
+	killall $srv
+	echo "FAIL"
+	exit $result
+    fi
+}
+
+function check_failure {
+    result=$?
+    if [ $result -eq 0 ]; then
+	killall $srv
+	echo "FAIL: connection should be refused"
+	exit 1
+    fi
+}
+
+function build {
+    echo "Building..."
+    cargo build --quiet --release --no-default-features --features inmemory
+    check_exit
+}
+
+function generate_certs {
+    echo
+    echo "Generating self-signed certificates..."
+    rm -rf $pki
+    mkdir -p $pki
+    openssl req -x509 -newkey rsa:2048 -nodes -days 1 -subj "/CN=astrobase-ca" \
+	    -keyout $pki/ca.key -out $pki/ca.pem 2>/dev/null
+    check_exit
+    for name in server client; do
+	openssl req -newkey rsa:2048 -nodes -subj "/CN=localhost" \
+		-keyout $pki/$name.key -out $pki/$name.csr 2>/dev/null
+	check_exit
+	printf "subjectAltName=DNS:localhost,IP:::1\n" > $pki/$name.ext
+	openssl x509 -req -in $pki/$name.csr -CA $pki/ca.pem -CAkey $pki/ca.key \
+		-CAcreateserial -days 1 -extfile $pki/$name.ext -out $pki/$name.pem 2>/dev/null
+	check_exit
+    done
+}
+
+function start_server {
+    echo
+    echo "Starting server..."
+    cat << EOF > $cfg
+{
+    "environment": "integration-testing",
+    "server": {
+	"endpoint": "[::1]:50051"
+    },
+    "tls": {
+	"cert": "$pki/server.pem",
+	"key": "$pki/server.key",
+	"client_ca": "$pki/ca.pem"
+    },
+    "replication": {
+	"journal": 100
+    },
+    "monitoring": {
+	"interval": 1
+    }
+}
+EOF
+    $bin/$srv --config $cfg run 2>$out &
+    sleep 1s
+}
+
+function stop_server {
+    echo
+    echo "Stopping server..."
+    killall $srv
+}
+
+function test_plaintext_refused {
+    echo
+    echo "test_plaintext_refused"
+    $bin/$cli --endpoint "http://[::1]:50051" insert smoke test
+    check_failure
+}
+
+function test_no_client_cert_refused {
+    echo
+    echo "test_no_client_cert_refused"
+    $bin/$cli --endpoint $endpoint --ca $pki/ca.pem --domain localhost insert smoke test
+    check_failure
+}
+
+function test_mutual_tls {
+    echo
+    echo "test_mutual_tls"
+    $bin/$cli --endpoint $endpoint --ca $pki/ca.pem --domain localhost \
+	      --cert $pki/client.pem --key $pki/client.key insert smoke test
+    check_exit
+    $bin/$cli --endpoint $endpoint --ca $pki/ca.pem --domain localhost \
+	      --cert $pki/client.pem --key $pki/client.key get smoke
+    check_exit
+}
+
+function test_backup {
+    echo
+    echo "test_backup"
+    rm -f /tmp/astrobase-tls-backup.bin
+    $bin/$srv --config $cfg backup /tmp/astrobase-tls-backup.bin --endpoint "http://[::1]:50051"
+    check_failure
+    $bin/$srv --config $cfg backup /tmp/astrobase-tls-backup.bin --domain localhost
+    check_exit
+    grep -q smoke /tmp/astrobase-tls-backup.bin
+    check_exit
+    rm -f /tmp/astrobase-tls-backup.bin
+}
+
+function test_gateway_needs_plaintext {
+    echo
+    echo "test_gateway_needs_plaintext"
+    gateway_cfg="/tmp/astrobase-integration-gateway.json"
+    cat << EOF > $gateway_cfg
+{
+    "environment": "integration-testing",
+    "server": {
+	"endpoint": "[::1]:50053"
+    },
+    "tls": {
+	"cert": "$pki/server.pem",
+	"key": "$pki/server.key"
+    },
+    "gateway": {
+	"endpoint": "127.0.0.1:50080"
+    },
+    "monitoring": {
+	"interval": 60
+    }
+}
+EOF
+    error=$(timeout 5 $bin/$srv --config $gateway_cfg run 2>&1)
+    check_failure
+    rm -f $gateway_cfg
+    [[ $error == *"set 'plaintext'"* ]]
+    check_exit
+}
+
+function test_replication {
+    echo
+    echo "test_replication"
+    cat << EOF > $follower_cfg
+{
+    "environment": "integration-testing",
+    "server": {
+	"endpoint": "[::1]:50052"
+    },
+    "tls": {
+	"cert": "$pki/server.pem",
+	"key": "$pki/server.key",
+	"client_ca": "$pki/ca.pem"
+    },
+    "replication": {
+	"leader": "$endpoint",
+	"ca": "$pki/ca.pem",
+	"domain": "localhost"
+    },
+    "monitoring": {
+	"interval": 60
+    }
+}
+EOF
+    $bin/$srv --config $follower_cfg run >$follower_out 2>&1 &
+    follower_pid=$!
+    sleep 1s
+    value=$($bin/$cli --endpoint "https://[::1]:50052" --ca $pki/ca.pem --domain localhost \
+		      --cert $pki/client.pem --key $pki/client.key --output raw get smoke)
+    check_exit
+    [ "$value" = "test" ]
+    check_exit
+    kill $follower_pid
+}
+
+build
+generate_certs
+start_server
+
+test_plaintext_refused
+test_no_client_cert_refused
+test_mutual_tls
+test_backup
+test_gateway_needs_plaintext
+test_replication
+
+stop_server
+
+echo "OK"
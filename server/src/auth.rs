@@ -0,0 +1,113 @@
+//! astrobase-server authentication and authorization.
+
+use crate::config::{self, Access};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tonic::metadata::MetadataValue;
+use tonic::{service::Interceptor, Request, Status};
+
+const BEARER: &str = "Bearer ";
+
+/// Checks bearer tokens and attaches the user to the request.
+/// Without users configured every request passes anonymously.
+#[derive(Clone)]
+pub struct Authenticator {
+    users: Option<Arc<HashMap<String, config::User>>>,
+}
+
+impl Authenticator {
+    pub fn new(auth: Option<&config::Auth>) -> Self {
+        let users = auth.map(|auth| {
+            Arc::new(
+                auth.users
+                    .iter()
+                    .map(|user| (user.token.clone(), user.clone()))
+                    .collect(),
+            )
+        });
+        Authenticator { users }
+    }
+
+    /// Constructs the `gRPC` request of a command received by another protocol
+    /// and authenticates it by the value of its authorization header
+    /// (`Bearer <token>`) like the `gRPC` listener does.
+    #[allow(clippy::result_large_err)] // the status is the one of the interceptor
+    pub fn request<T>(&self, authorization: Option<&[u8]>, msg: T) -> Result<Request<T>, Status> {
+        let mut req = Request::new(());
+        if let Some(authorization) = authorization {
+            let value = MetadataValue::try_from(authorization)
+                .map_err(|_| Status::unauthenticated(Error::TokenInvalid.to_string()))?;
+            req.metadata_mut().insert("authorization", value);
+        }
+        Ok(self.clone().call(req)?.map(|()| msg))
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        let users = match &self.users {
+            None => return Ok(req),
+            Some(users) => users,
+        };
+
+        let token = req
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER))
+            .ok_or_else(|| Status::unauthenticated(Error::TokenMissing.to_string()))?;
+        let user = users
+            .get(token)
+            .ok_or_else(|| Status::unauthenticated(Error::TokenInvalid.to_string()))?;
+
+        req.extensions_mut().insert(user.clone());
+        Ok(req)
+    }
+}
+
+/// Checks the user attached to the request may access the key in the namespace.
+pub fn authorize<T>(req: &Request<T>, ns: &str, key: &[u8], access: Access) -> Result<(), Error> {
+    match req.extensions().get::<config::User>() {
+        None => Ok(()),
+        Some(user) => {
+            let granted = user.grants.iter().any(|grant| {
+                grant.namespace.iter().all(|name| name == ns)
+                    && key.starts_with(grant.prefix.as_bytes())
+                    && grant.access >= access
+            });
+            if granted {
+                Ok(())
+            } else {
+                Err(Error::Denied(
+                    user.name.clone(),
+                    String::from_utf8_lossy(key).into_owned(),
+                ))
+            }
+        }
+    }
+}
+
+/// Checks the user attached to the request is an administrator of all keys
+/// in all namespaces.
+pub fn authorize_admin<T>(req: &Request<T>) -> Result<(), Error> {
+    match req.extensions().get::<config::User>() {
+        None => Ok(()),
+        Some(user) if user.is_admin() => Ok(()),
+        Some(user) => Err(Error::NotAdmin(user.name.clone())),
+    }
+}
+
+/// Represents authentication and authorization errors.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Bearer token is missing")]
+    TokenMissing,
+    #[error("Bearer token is invalid")]
+    TokenInvalid,
+    #[error("User '{0}' has no access to '{1}'")]
+    Denied(String, String),
+    #[error("User '{0}' is not an administrator")]
+    NotAdmin(String),
+}
@@ -0,0 +1,310 @@
+//! astrobase-server backups: an archive of all namespaces at one point.
+//!
+//! The archive is the magic line followed by length-delimited `ArchiveEntry`
+//! messages: batches of records of every namespace (at least one per namespace,
+//! so empty ones are kept) and then the summary with the number of records and
+//! the CRC-32 of everything before it. Nothing may follow the summary.
+
+use crate::config;
+use crate::database::{self, Database, DEFAULT_NAMESPACE};
+use crate::replication::Records;
+use crate::server::read_pem;
+
+use anyhow::Context as _;
+use astrobase_api::proto::archive_entry::Entry;
+use astrobase_api::proto::astrobase_client::AstrobaseClient;
+use astrobase_api::proto::{ArchiveEnd, ArchiveEntry, Batch, Conflict, Empty, Pair};
+use prost::Message;
+use std::io::Write as _;
+use std::path::Path;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tonic::Request;
+
+const MAGIC: &[u8] = b"ASTROBASE-BACKUP 1\n";
+/// The size of the batches of records in the archive.
+const BATCH_SIZE: usize = 1024 * 1024;
+/// The size of the pieces the archive is streamed in.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Represents the reasons an archive is rejected.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Not an astrobase backup archive")]
+    Magic,
+    #[error("The archive is cut short at offset {0}")]
+    Truncated(usize),
+    #[error("The archive is corrupted at offset {0}")]
+    Corrupted(usize),
+    #[error("Checksum mismatch: the archive says {expected:08x}, the content is {actual:08x}")]
+    Checksum { expected: u32, actual: u32 },
+    #[error("The archive says it has {expected} records, it has {actual}")]
+    Count { expected: u64, actual: u64 },
+    #[error("Unexpected data after the end of the archive at offset {0}")]
+    Trailing(usize),
+    #[error("The database is not empty: namespace '{0}' has records or exists")]
+    NotEmpty(String),
+}
+
+/// Builds the archive of the records of the namespaces.
+pub fn archive(namespaces: Vec<(String, Records)>) -> Vec<u8> {
+    let mut archive = Vec::new();
+    encode(namespaces, |piece| {
+        archive.extend_from_slice(piece);
+        Ok::<_, std::convert::Infallible>(())
+    })
+    .unwrap_or_else(|never| match never {});
+    archive
+}
+
+/// Encodes the archive of the records of the namespaces, passing it on in
+/// pieces as they are encoded, so the whole archive is never in memory.
+/// Stops at the first piece `emit` refuses.
+pub fn encode<E>(
+    namespaces: Vec<(String, Records)>,
+    mut emit: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut crc = Crc32::new();
+    let mut put = |piece: &[u8], crc: &mut Crc32| {
+        crc.update(piece);
+        emit(piece)
+    };
+    put(MAGIC, &mut crc)?;
+    let mut count = 0;
+    for (ns, records) in namespaces {
+        count += records.len() as u64;
+        let mut batch = self::batch(&ns);
+        let mut size = 0;
+        let mut pushed = false;
+        for (key, value) in records {
+            size += key.len() + value.len();
+            batch.pairs.push(Pair {
+                key,
+                value,
+                namespace: String::new(),
+            });
+            if size >= BATCH_SIZE {
+                let full = std::mem::replace(&mut batch, self::batch(&ns));
+                put(&entry(Entry::Batch(full)), &mut crc)?;
+                size = 0;
+                pushed = true;
+            }
+        }
+        if !batch.pairs.is_empty() || !pushed {
+            put(&entry(Entry::Batch(batch)), &mut crc)?;
+        }
+    }
+    let end = ArchiveEnd {
+        records: count,
+        crc32: crc.value(),
+    };
+    emit(&entry(Entry::End(end)))
+}
+
+fn batch(ns: &str) -> Batch {
+    Batch {
+        namespace: ns.into(),
+        conflict: Conflict::InsertOnly as i32,
+        pairs: Vec::new(),
+    }
+}
+
+fn entry(entry: Entry) -> Vec<u8> {
+    let entry = ArchiveEntry { entry: Some(entry) };
+    entry.encode_length_delimited_to_vec()
+}
+
+/// Checks the archive and returns its batches.
+pub fn read(archive: &[u8]) -> Result<Vec<Batch>, Error> {
+    let mut buf = archive.strip_prefix(MAGIC).ok_or(Error::Magic)?;
+    let mut batches = Vec::new();
+    let mut count = 0;
+    loop {
+        let offset = archive.len() - buf.len();
+        if buf.is_empty() {
+            return Err(Error::Truncated(offset));
+        }
+        let entry = ArchiveEntry::decode_length_delimited(&mut buf)
+            .map_err(|_| Error::Corrupted(offset))?;
+        match entry.entry {
+            Some(Entry::Batch(batch)) => {
+                count += batch.pairs.len() as u64;
+                batches.push(batch);
+            }
+            Some(Entry::End(end)) => {
+                let actual = crc32(&archive[..offset]);
+                if end.crc32 != actual {
+                    return Err(Error::Checksum {
+                        expected: end.crc32,
+                        actual,
+                    });
+                }
+                if end.records != count {
+                    return Err(Error::Count {
+                        expected: end.records,
+                        actual: count,
+                    });
+                }
+                if !buf.is_empty() {
+                    return Err(Error::Trailing(archive.len() - buf.len()));
+                }
+                return Ok(batches);
+            }
+            None => return Err(Error::Corrupted(offset)),
+        }
+    }
+}
+
+/// Loads the batches of a checked archive into an empty database.
+/// Returns the number of records.
+pub async fn restore<Db: Database>(db: &Db, batches: Vec<Batch>) -> anyhow::Result<u64> {
+    for ns in db.list_namespaces().await? {
+        if ns != DEFAULT_NAMESPACE || !db.scan(&ns, b"", None, 1).await?.is_empty() {
+            return Err(Error::NotEmpty(ns).into());
+        }
+    }
+    let mut count = 0;
+    for batch in batches {
+        let ns = batch.namespace;
+        match db.create_namespace(&ns).await {
+            Ok(()) | Err(database::Error::NamespaceAlreadyExists(_)) => {}
+            Err(err) => return Err(err.into()),
+        }
+        let pairs: Records = batch
+            .pairs
+            .into_iter()
+            .map(|pair| (pair.key, pair.value))
+            .collect();
+        if !pairs.is_empty() {
+            let conflict = database::Conflict::InsertOnly;
+            count += db.write_batch(&ns, &pairs, conflict).await?.inserted;
+        }
+    }
+    Ok(count)
+}
+
+/// Reads and checks an archive file.
+pub fn load(file: &Path) -> anyhow::Result<Vec<Batch>> {
+    let archive =
+        std::fs::read(file).with_context(|| format!("Cannot read '{}'", file.display()))?;
+    read(&archive).with_context(|| format!("Invalid archive '{}'", file.display()))
+}
+
+/// Makes the TLS config to reach the server of the config. The CA defaults to
+/// the client CA of the config; with mutual TLS the server presents its own
+/// certificate unless another one is given.
+pub fn tls_config(
+    cfg: &config::Astrobase,
+    ca: Option<&Path>,
+    identity: Option<(&Path, &Path)>,
+    domain: Option<String>,
+) -> anyhow::Result<ClientTlsConfig> {
+    let tls = cfg.tls.as_ref();
+    let ca = ca
+        .or_else(|| tls.and_then(|tls| tls.client_ca.as_deref()))
+        .context("Pass the CA certificate of the server with --ca")?;
+    let mut config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(read_pem(ca)?));
+    let identity = identity.or_else(|| {
+        tls.filter(|tls| tls.client_ca.is_some())
+            .map(|tls| (tls.cert.as_path(), tls.key.as_path()))
+    });
+    if let Some((cert, key)) = identity {
+        config = config.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+    }
+    if let Some(domain) = domain {
+        config = config.domain_name(domain);
+    }
+    Ok(config)
+}
+
+/// Streams the archive of a running server to a file, checking it before
+/// the file is put in place.
+pub async fn save(
+    endpoint: &str,
+    token: Option<&str>,
+    tls: Option<ClientTlsConfig>,
+    file: &Path,
+) -> anyhow::Result<()> {
+    let channel = match endpoint.strip_prefix("unix://") {
+        Some(path) => {
+            let path = path.to_owned();
+            let connector = tower::service_fn(move |_: tonic::codegen::http::Uri| {
+                tokio::net::UnixStream::connect(path.clone())
+            });
+            Endpoint::from_static("http://localhost")
+                .connect_with_connector(connector)
+                .await?
+        }
+        None => {
+            let mut endpoint = Endpoint::from_shared(endpoint.to_owned())?;
+            if let Some(tls) = tls {
+                endpoint = endpoint.tls_config(tls)?;
+            }
+            endpoint.connect().await?
+        }
+    };
+    let mut req = Request::new(Empty {});
+    if let Some(token) = token {
+        let bearer = format!("Bearer {}", token).parse()?;
+        req.metadata_mut().insert("authorization", bearer);
+    }
+    let mut chunks = AstrobaseClient::new(channel)
+        .backup(req)
+        .await?
+        .into_inner();
+    let mut archive = Vec::new();
+    while let Some(chunk) = chunks.message().await? {
+        archive.extend_from_slice(&chunk.data);
+    }
+    read(&archive).context("The server sent an invalid archive")?;
+
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut out = std::fs::File::create(&tmp)
+        .with_context(|| format!("Cannot create '{}'", Path::new(&tmp).display()))?;
+    out.write_all(&archive)?;
+    out.sync_all()?;
+    std::fs::rename(&tmp, file).with_context(|| format!("Cannot write '{}'", file.display()))?;
+    Ok(())
+}
+
+/// Computes the CRC-32 (IEEE) of the bytes.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.value()
+}
+
+/// Computes the CRC-32 (IEEE) of the bytes given piece by piece.
+struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut crc = i as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+            *entry = crc;
+        }
+        Crc32 { table, crc: !0 }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        let table = &self.table;
+        self.crc = bytes.iter().fold(self.crc, |crc, &byte| {
+            table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+        });
+    }
+
+    fn value(&self) -> u32 {
+        !self.crc
+    }
+}
@@ -23,7 +23,81 @@ pub struct Application {
 #[derive(StructOpt)]
 pub enum Command {
     #[structopt(about = "Starts listening")]
-    Run,
+    Run {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Loads a backup archive into the empty database first"
+        )]
+        restore: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Saves a backup archive of the running server")]
+    Backup {
+        #[structopt(parse(from_os_str), help = "Path to the archive")]
+        archive: PathBuf,
+
+        #[structopt(
+            long,
+            help = "Endpoint of the server, by default the one in the config, e.g. \"unix:///tmp/astrobase.sock\""
+        )]
+        endpoint: Option<String>,
+
+        #[structopt(
+            long,
+            env = "ASTROBASE_TOKEN",
+            hide_env_values = true,
+            help = "Admin token of the server, by default the one of an administrator in the config"
+        )]
+        token: Option<String>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "CA certificate of the server, by default the client CA of the config"
+        )]
+        ca: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            requires = "key",
+            help = "Client certificate for mutual TLS, by default the certificate of the config"
+        )]
+        cert: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            requires = "cert",
+            help = "Client private key"
+        )]
+        key: Option<PathBuf>,
+
+        #[structopt(long, help = "Domain name of the server certificate")]
+        domain: Option<String>,
+    },
+
+    #[structopt(about = "Checks a backup archive and loads it into the empty database")]
+    Restore {
+        #[structopt(parse(from_os_str), help = "Path to the archive")]
+        archive: PathBuf,
+    },
+
+    #[structopt(about = "Reports what a database file holds, without the server")]
+    Inspect {
+        #[structopt(parse(from_os_str), help = "Path to the database file")]
+        db: PathBuf,
+
+        #[structopt(
+            long,
+            help = "Shows every version of the key, escaped as in the file (\\t, \\n, \\\\)"
+        )]
+        key: Option<String>,
+
+        #[structopt(long, help = "Prints the live pairs in the format of the file")]
+        dump: bool,
+    },
 }
 
 /// Constructs instance of Application.
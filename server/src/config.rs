@@ -3,29 +3,287 @@
 pub const FAILURE: i32 = 1;
 pub const DEFAULT_CONFIG: &str = "astrobase.json";
 pub const DEFAULT_DB: &str = "/tmp/astrobase.db";
-//pub const DEFAULT_INDEX: &str = "/tmp/astrobase.idx";
+pub const DEFAULT_JOURNAL: usize = 100_000; // changes a leader keeps for followers
+pub const DEFAULT_ELECTION_TIMEOUT: u64 = 1000; // milliseconds
+pub const DEFAULT_HEARTBEAT: u64 = 100; // milliseconds
+pub const DEFAULT_RETENTION: u64 = 86_400; // seconds of the history kept by compaction
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024; // bytes a log segment grows to before it rolls over
+pub const DEFAULT_MERGE_INTERVAL: u64 = 60; // seconds between merges of the old segments
+pub const SHUTDOWN_DELAY: u64 = 1; // seconds health checks see NOT_SERVING before exit
+
+pub use astrobase_api::{MAX_KEY_LEN, MAX_VALUE_LEN};
 
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Represents the server config.
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Server {
+    #[serde(default)]
+    pub endpoint: Option<String>, // TCP, omitted to listen only on the Unix socket
+    #[serde(default)]
+    pub unix: Option<Unix>,
+}
+
+/// Represents the Unix domain socket listener config.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Unix {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub mode: Option<String>, // octal file permissions, e.g. "660"
+}
+
+impl Unix {
+    /// Returns the file permissions of the socket if they are configured.
+    pub fn mode(&self) -> Result<Option<u32>> {
+        self.mode
+            .as_ref()
+            .map(|mode| match u32::from_str_radix(mode, 8) {
+                Ok(bits) if bits <= 0o7777 => Ok(bits),
+                _ => Err(Error::Mode(mode.clone())),
+            })
+            .transpose()
+    }
+}
+
+/// Represents the standard `gRPC` services served next to the API.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Grpc {
+    pub health: bool,     // grpc.health.v1.Health
+    pub reflection: bool, // grpc.reflection.v1alpha.ServerReflection
+}
+
+/// Represents the HTTP/JSON gateway config.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Gateway {
     pub endpoint: String,
+    #[serde(default)]
+    pub plaintext: bool, // served without TLS next to 'tls'
+}
+
+/// Represents the Redis protocol listener config.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Resp {
+    pub endpoint: String,
+    #[serde(default)]
+    pub plaintext: bool, // served without TLS next to 'tls'
+}
+
+/// Represents the memcached protocol listener config.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Memcached {
+    pub endpoint: String,
+    #[serde(default)]
+    pub plaintext: bool, // served without TLS next to 'tls'
+    #[serde(default)]
+    pub token: Option<String>, // the user of connections without "auth", only on a loopback endpoint
+}
+
+/// Represents the database config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Database {
+    pub path: PathBuf, // the main file of the persistent database
+    #[serde(default = "default_retention")]
+    pub retention: u64, // seconds the replaced versions stay readable by point-in-time reads
+    #[serde(default = "default_segment_size")]
+    pub segment_size: u64, // bytes of the active segment of a log before the next one is started
+    #[serde(default = "default_merge_interval")]
+    pub merge_interval: u64, // seconds between merges of the old segments
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database {
+            path: DEFAULT_DB.into(),
+            retention: DEFAULT_RETENTION,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            merge_interval: DEFAULT_MERGE_INTERVAL,
+        }
+    }
+}
+
+fn default_retention() -> u64 {
+    DEFAULT_RETENTION
+}
+
+fn default_segment_size() -> u64 {
+    DEFAULT_SEGMENT_SIZE
+}
+
+fn default_merge_interval() -> u64 {
+    DEFAULT_MERGE_INTERVAL
+}
+
+/// Represents the replication config: a leader if `leader` is omitted,
+/// otherwise a read-only follower of that leader.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replication {
+    #[serde(default)]
+    pub leader: Option<String>, // the endpoint of the leader
+    #[serde(default)]
+    pub token: Option<String>, // the admin token of a follower if the leader requires auth
+    #[serde(default)]
+    pub ca: Option<PathBuf>, // the CA certificate of a leader reached over "https://"
+    #[serde(default)]
+    pub domain: Option<String>, // the name in the certificate of the leader
+    #[serde(default = "default_journal")]
+    pub journal: usize, // changes a leader keeps so followers can resume
+}
+
+fn default_journal() -> usize {
+    DEFAULT_JOURNAL
+}
+
+/// Represents a server of a cluster.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Member {
+    pub id: String,
+    pub endpoint: String, // the URI of its gRPC endpoint, e.g. "http://[::1]:50051"
+}
+
+/// Represents the cluster config: the members replicate writes through Raft.
+/// The Raft log is not compacted, every write stays in the memory and the
+/// log file of each member.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cluster {
+    pub node: String, // the id of this server
+    pub members: Vec<Member>,
+    #[serde(default)]
+    pub token: Option<String>, // the admin token of the members if auth is required
+    #[serde(default = "default_election_timeout")]
+    pub election_timeout: u64, // milliseconds, a random part up to as much is added
+    #[serde(default = "default_heartbeat")]
+    pub heartbeat: u64, // milliseconds
+}
+
+fn default_election_timeout() -> u64 {
+    DEFAULT_ELECTION_TIMEOUT
+}
+
+fn default_heartbeat() -> u64 {
+    DEFAULT_HEARTBEAT
+}
+
+/// Represents the TLS config (PEM files).
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>, // enables mutual TLS
+}
+
+/// Represents the level of access to keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+    Admin,
+}
+
+/// Represents the access granted to keys starting with the prefix
+/// (in the given namespace or in all of them).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Grant {
+    #[serde(default)]
+    pub namespace: Option<String>,
+    pub prefix: String,
+    pub access: Access,
+}
+
+/// Represents a user authenticated by bearer token.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub name: String,
+    pub token: String,
+    pub grants: Vec<Grant>,
+}
+
+impl User {
+    /// Returns whether the user administers all keys in all namespaces.
+    pub fn is_admin(&self) -> bool {
+        self.grants.iter().any(|grant| {
+            grant.namespace.is_none() && grant.prefix.is_empty() && grant.access == Access::Admin
+        })
+    }
+}
+
+/// Represents the authentication config.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Auth {
+    pub users: Vec<User>,
+}
+
+impl Auth {
+    /// Returns the token of the first administrator.
+    pub fn admin_token(&self) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|user| user.is_admin())
+            .map(|user| user.token.as_str())
+    }
 }
 
 /// Represents the monitoring config.
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Monitoring {
     pub interval: u64, // seconds
 }
 
+/// Represents the logging config.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Logging {
+    pub level: Option<String>, // error, warn, info, debug, trace or off
+}
+
+/// Represents the request limits config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub max_key_len: usize,
+    pub max_value_len: usize,
+    pub requests_per_second: u64, // 0 means unlimited
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_key_len: MAX_KEY_LEN,
+            max_value_len: MAX_VALUE_LEN,
+            requests_per_second: 0,
+        }
+    }
+}
+
 /// Represents the main config.
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Astrobase {
     pub environment: String,
     pub server: Server,
+    #[serde(default)]
+    pub database: Database,
+    #[serde(default)]
+    pub replication: Option<Replication>,
+    #[serde(default)]
+    pub cluster: Option<Cluster>,
+    #[serde(default)]
+    pub grpc: Grpc,
+    #[serde(default)]
+    pub gateway: Option<Gateway>,
+    #[serde(default)]
+    pub resp: Option<Resp>,
+    #[serde(default)]
+    pub memcached: Option<Memcached>,
+    #[serde(default)]
+    pub tls: Option<Tls>,
+    #[serde(default)]
+    pub auth: Option<Auth>,
     pub monitoring: Monitoring,
+    #[serde(default)]
+    pub logging: Logging,
+    #[serde(default)]
+    pub limits: Limits,
 }
 
 /// Implements construction of the config.
@@ -34,8 +292,164 @@ impl Astrobase {
         let text = read(filename)?;
         let cfg: Astrobase =
             serde_json::from_str(&text).map_err(|e| Error::Parse(e, filename.to_owned()))?;
+        if cfg.server.endpoint.is_none() && cfg.server.unix.is_none() {
+            return Err(Error::NoListener);
+        }
+        if let Some(unix) = &cfg.server.unix {
+            unix.mode()?;
+        }
+        if let Some(cluster) = &cfg.cluster {
+            cfg.ensure_cluster_valid(cluster)?;
+        }
+        if let Some(auth) = &cfg.auth {
+            ensure_auth_valid(auth)?;
+        }
+        if let Some(replication) = &cfg.replication {
+            ensure_replication_valid(replication)?;
+        }
+        if let Some(memcached) = &cfg.memcached {
+            ensure_memcached_valid(memcached)?;
+        }
+        cfg.ensure_plaintext_allowed()?;
         Ok(cfg)
     }
+
+    /// Checks the members of the cluster and the sections it cannot be used with.
+    fn ensure_cluster_valid(&self, cluster: &Cluster) -> Result<()> {
+        let invalid = |reason: &str| Err(Error::Cluster(reason.into()));
+        if !cluster
+            .members
+            .iter()
+            .any(|member| member.id == cluster.node)
+        {
+            return invalid("'node' is not one of the 'members'");
+        }
+        for (i, member) in cluster.members.iter().enumerate() {
+            if cluster.members[..i]
+                .iter()
+                .any(|other| other.id == member.id)
+            {
+                return invalid(&format!("member '{}' is listed twice", member.id));
+            }
+        }
+        if cluster.heartbeat == 0 || cluster.heartbeat >= cluster.election_timeout {
+            return invalid("'heartbeat' must be shorter than 'election_timeout'");
+        }
+        if self.replication.is_some() {
+            return invalid("it cannot be used with 'replication'");
+        }
+        if self.tls.is_some() {
+            return invalid("the members talk without TLS, so it cannot be used with 'tls'");
+        }
+        Ok(())
+    }
+
+    /// Checks the listeners without TLS are explicitly allowed next to 'tls'.
+    fn ensure_plaintext_allowed(&self) -> Result<()> {
+        if self.tls.is_none() {
+            return Ok(());
+        }
+        let listeners = [
+            (
+                "gateway",
+                self.gateway.as_ref().map(|gateway| gateway.plaintext),
+            ),
+            ("resp", self.resp.as_ref().map(|resp| resp.plaintext)),
+            ("memcached", self.memcached.as_ref().map(|mc| mc.plaintext)),
+        ];
+        match listeners
+            .iter()
+            .find(|(_, plaintext)| *plaintext == Some(false))
+        {
+            Some((section, _)) => Err(Error::Plaintext(section)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that a freshly loaded config differs from this one only in
+    /// fields which can be applied without restarting the server.
+    pub fn ensure_reloadable(&self, new: &Astrobase) -> Result<()> {
+        if self.environment != new.environment {
+            return Err(Error::Unsafe("environment"));
+        }
+        if self.server != new.server {
+            return Err(Error::Unsafe("server"));
+        }
+        if self.database != new.database {
+            return Err(Error::Unsafe("database"));
+        }
+        if self.replication != new.replication {
+            return Err(Error::Unsafe("replication"));
+        }
+        if self.cluster != new.cluster {
+            return Err(Error::Unsafe("cluster"));
+        }
+        if self.grpc != new.grpc {
+            return Err(Error::Unsafe("grpc"));
+        }
+        if self.gateway != new.gateway {
+            return Err(Error::Unsafe("gateway"));
+        }
+        if self.resp != new.resp {
+            return Err(Error::Unsafe("resp"));
+        }
+        if self.memcached != new.memcached {
+            return Err(Error::Unsafe("memcached"));
+        }
+        if self.tls != new.tls {
+            return Err(Error::Unsafe("tls"));
+        }
+        if self.auth != new.auth {
+            return Err(Error::Unsafe("auth"));
+        }
+        Ok(())
+    }
+}
+
+/// Checks a follower reaches the leader over TLS exactly when it has its CA.
+fn ensure_replication_valid(replication: &Replication) -> Result<()> {
+    let invalid = |reason: &str| Err(Error::Replication(reason.into()));
+    let https = replication
+        .leader
+        .as_ref()
+        .map(|leader| leader.starts_with("https://"));
+    match (https, &replication.ca) {
+        (Some(true), None) => invalid("a leader over \"https://\" needs 'ca'"),
+        (None, Some(_)) | (Some(false), Some(_)) => {
+            invalid("'ca' is only used with a leader over \"https://\"")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks every token identifies one user.
+fn ensure_auth_valid(auth: &Auth) -> Result<()> {
+    for (i, user) in auth.users.iter().enumerate() {
+        if let Some(other) = auth.users[..i]
+            .iter()
+            .find(|other| other.token == user.token)
+        {
+            return Err(Error::Auth(format!(
+                "users '{}' and '{}' have the same token",
+                other.name, user.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the token every connection runs as is only given to local clients.
+fn ensure_memcached_valid(memcached: &Memcached) -> Result<()> {
+    let loopback = matches!(
+        memcached.endpoint.parse::<std::net::SocketAddr>(),
+        Ok(address) if address.ip().is_loopback()
+    );
+    if memcached.token.is_some() && !loopback {
+        return Err(Error::Memcached(
+            "'token' needs a loopback 'endpoint', remote clients send \"auth <token>\"".into(),
+        ));
+    }
+    Ok(())
 }
 
 /// Reads the main config from a file.
@@ -47,9 +461,25 @@ fn read(filename: &Path) -> Result<String> {
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Failed to read config '{1}': {0}")]
-    Read(#[source] std::io::Error, std::path::PathBuf),
+    Read(#[source] std::io::Error, PathBuf),
     #[error("Failed to parse config '{1}': {0}")]
-    Parse(#[source] serde_json::Error, std::path::PathBuf),
+    Parse(#[source] serde_json::Error, PathBuf),
+    #[error("Section '{0}' cannot be changed without restart")]
+    Unsafe(&'static str),
+    #[error("Invalid section 'auth': {0}")]
+    Auth(String),
+    #[error("Invalid section 'cluster': {0}")]
+    Cluster(String),
+    #[error("Invalid section 'replication': {0}")]
+    Replication(String),
+    #[error("Invalid section 'memcached': {0}")]
+    Memcached(String),
+    #[error("Section '{0}' is served without TLS, set 'plaintext' to allow it next to 'tls'")]
+    Plaintext(&'static str),
+    #[error("Section 'server' needs 'endpoint' or 'unix'")]
+    NoListener,
+    #[error("Invalid socket mode '{0}', expected octal permissions like \"660\"")]
+    Mode(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,73 +1,177 @@
 //! astrobase-server in-memory key-value database.
 
-use super::{Error, Result};
+use super::{
+    ensure_namespace_valid, lossy, plan_batch, scan_range, Conflict, Error, Result, Version,
+    Written, DEFAULT_NAMESPACE,
+};
+use crate::config;
 
 use async_trait::async_trait;
-use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::collections::HashMap;
+use std::collections::btree_map::Entry::{Occupied, Vacant};
+use std::collections::BTreeMap;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
+type Table = BTreeMap<Vec<u8>, Vec<u8>>;
+
 /// Represents the database internals.
 pub struct InMemory {
-    table: RwLock<HashMap<String, String>>,
+    tables: RwLock<BTreeMap<String, Table>>,
+}
+
+/// Constructs the set of tables with the default namespace only.
+fn default_tables() -> BTreeMap<String, Table> {
+    let mut tables = BTreeMap::new();
+    tables.insert(DEFAULT_NAMESPACE.into(), Table::new());
+    tables
 }
 
 #[async_trait]
 impl super::Database for InMemory {
-    /// Construct new instance of the database.
-    fn new() -> Self {
+    /// Construct new instance of the database, the path is not used.
+    fn open(_cfg: &config::Database) -> Self {
         InMemory {
-            table: RwLock::new(HashMap::new()),
+            tables: RwLock::new(default_tables()),
         }
     }
 
-    /// Deletes all records.
+    /// Deletes all records and namespaces.
     async fn clear(&self) -> Result<()> {
-        let mut table = self.table.write().await;
-        table.clear();
+        let mut tables = self.tables.write().await;
+        *tables = default_tables();
         Ok(())
     }
 
     /// Returns a value or error.
-    async fn get(&self, key: &str) -> Result<String> {
-        let table = self.table.read().await;
+    async fn get(&self, ns: &str, key: &[u8]) -> Result<Vec<u8>> {
+        let tables = self.tables.read().await;
+        let table = tables
+            .get(ns)
+            .ok_or_else(|| Error::NamespaceMissing(ns.into()))?;
         let value = table
             .get(key)
-            .ok_or_else(|| Error::RecordMissing(key.into()))?;
+            .ok_or_else(|| Error::RecordMissing(lossy(key)))?;
         Ok(value.clone())
     }
 
     /// Inserts new record if there was no such key or returns error.
-    async fn insert(&self, key: &str, value: &str) -> Result<String> {
-        let mut table = self.table.write().await;
+    async fn insert(&self, ns: &str, key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        let mut tables = self.tables.write().await;
+        let table = tables
+            .get_mut(ns)
+            .ok_or_else(|| Error::NamespaceMissing(ns.into()))?;
         match table.entry(key.into()) {
-            Occupied(_) => return Err(Error::RecordAlreadyExists(key.into())),
+            Occupied(_) => return Err(Error::RecordAlreadyExists(lossy(key))),
             Vacant(entry) => entry.insert(value.into()),
         };
-        Ok(String::default())
+        Ok(Vec::new())
     }
 
     /// Deletes a record or returns error if was missing.
-    async fn delete(&self, key: &str) -> Result<String> {
-        let mut table = self.table.write().await;
+    async fn delete(&self, ns: &str, key: &[u8]) -> Result<Vec<u8>> {
+        let mut tables = self.tables.write().await;
+        let table = tables
+            .get_mut(ns)
+            .ok_or_else(|| Error::NamespaceMissing(ns.into()))?;
         let value = table
             .remove(key)
-            .ok_or_else(|| Error::RecordAlreadyMissing(key.into()))?;
+            .ok_or_else(|| Error::RecordAlreadyMissing(lossy(key)))?;
         Ok(value)
     }
 
     /// Updates record or returns error if the record was missing or identical.
-    async fn update(&self, key: &str, value: &str) -> Result<String> {
-        let mut table = self.table.write().await;
+    async fn update(&self, ns: &str, key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        let mut tables = self.tables.write().await;
+        let table = tables
+            .get_mut(ns)
+            .ok_or_else(|| Error::NamespaceMissing(ns.into()))?;
         match table.entry(key.into()) {
-            Vacant(_) => return Err(Error::RecordMissing(key.into())),
+            Vacant(_) => return Err(Error::RecordMissing(lossy(key))),
             Occupied(mut entry) => {
                 if entry.get() == value {
-                    return Err(Error::RecordAlreadyExistsIdentical(key.into()));
+                    return Err(Error::RecordAlreadyExistsIdentical(lossy(key)));
                 }
                 *entry.get_mut() = value.into();
             }
         };
-        Ok(String::default())
+        Ok(Vec::new())
+    }
+
+    /// Creates new empty namespace or returns error if it exists.
+    async fn create_namespace(&self, ns: &str) -> Result<()> {
+        ensure_namespace_valid(ns)?;
+        let mut tables = self.tables.write().await;
+        match tables.entry(ns.into()) {
+            Occupied(_) => return Err(Error::NamespaceAlreadyExists(ns.into())),
+            Vacant(entry) => entry.insert(Table::new()),
+        };
+        Ok(())
+    }
+
+    /// Drops a namespace with all its records.
+    async fn drop_namespace(&self, ns: &str) -> Result<()> {
+        if ns == DEFAULT_NAMESPACE {
+            return Err(Error::NamespacePermanent(ns.into()));
+        }
+        let mut tables = self.tables.write().await;
+        tables
+            .remove(ns)
+            .ok_or_else(|| Error::NamespaceMissing(ns.into()))?;
+        Ok(())
+    }
+
+    /// Returns sorted names of all namespaces.
+    async fn list_namespaces(&self) -> Result<Vec<String>> {
+        let tables = self.tables.read().await;
+        Ok(tables.keys().cloned().collect())
+    }
+
+    /// Returns a page of the records with keys starting with the prefix sorted by key.
+    async fn scan(
+        &self,
+        ns: &str,
+        prefix: &[u8],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let tables = self.tables.read().await;
+        let table = tables
+            .get(ns)
+            .ok_or_else(|| Error::NamespaceMissing(ns.into()))?;
+        let records = table
+            .range::<[u8], _>(scan_range(prefix, after))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .take(limit)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Ok(records)
+    }
+
+    /// Writes many records at once resolving existing keys by the conflict mode.
+    async fn write_batch(
+        &self,
+        ns: &str,
+        pairs: &[(Vec<u8>, Vec<u8>)],
+        conflict: Conflict,
+    ) -> Result<Written> {
+        let mut tables = self.tables.write().await;
+        let table = tables
+            .get_mut(ns)
+            .ok_or_else(|| Error::NamespaceMissing(ns.into()))?;
+        let (planned, written) = plan_batch(pairs, conflict, |key| table.get(key).cloned())?;
+        for (key, value) in planned {
+            table.insert(key.into(), value.into());
+        }
+        Ok(written)
+    }
+
+    /// Only the current values are kept.
+    async fn history(&self, _ns: &str, _key: &[u8]) -> Result<Vec<Version>> {
+        Err(Error::HistoryUnsupported)
+    }
+
+    /// Nothing to collect: the replaced values are gone at once.
+    async fn compact(&self, _retention: Duration) -> Result<u64> {
+        Ok(0)
     }
 }
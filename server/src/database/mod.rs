@@ -8,22 +8,132 @@ mod storage;
 mod tests;
 
 pub use inmemory::InMemory;
-pub use persistent::Persistent;
+pub use persistent::{segments, Persistent};
+pub use storage::{escape, unescape, Line, Storage};
+
+use crate::config;
 
 use async_trait::async_trait;
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The namespace which always exists and is used when none is specified.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+const MAX_NAMESPACE_LEN: usize = 64;
 
 /// Represents interface of the database.
 #[async_trait]
 pub trait Database: Send + Sync + 'static {
-    fn new() -> Self;
+    /// Opens the database stored as configured.
+    fn open(cfg: &config::Database) -> Self;
     async fn clear(&self) -> Result<()>;
-    async fn get(&self, key: &str) -> Result<String>;
-    async fn insert(&self, key: &str, value: &str) -> Result<String>;
-    async fn delete(&self, key: &str) -> Result<String>;
-    async fn update(&self, key: &str, value: &str) -> Result<String>;
+    async fn get(&self, ns: &str, key: &[u8]) -> Result<Vec<u8>>;
+    async fn insert(&self, ns: &str, key: &[u8], value: &[u8]) -> Result<Vec<u8>>;
+    async fn delete(&self, ns: &str, key: &[u8]) -> Result<Vec<u8>>;
+    async fn update(&self, ns: &str, key: &[u8], value: &[u8]) -> Result<Vec<u8>>;
+    async fn create_namespace(&self, ns: &str) -> Result<()>;
+    async fn drop_namespace(&self, ns: &str) -> Result<()>;
+    async fn list_namespaces(&self) -> Result<Vec<String>>;
+    /// Returns at most `limit` records with keys starting with the prefix
+    /// and following `after`, sorted by key.
+    async fn scan(
+        &self,
+        ns: &str,
+        prefix: &[u8],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+    async fn write_batch(
+        &self,
+        ns: &str,
+        pairs: &[(Vec<u8>, Vec<u8>)],
+        conflict: Conflict,
+    ) -> Result<Written>;
+    /// Returns the versions of a key, the oldest first.
+    async fn history(&self, ns: &str, key: &[u8]) -> Result<Vec<Version>>;
+    /// Removes the versions older than the retention window no read can see,
+    /// returns how many. Writes go on meanwhile.
+    async fn compact(&self, retention: Duration) -> Result<u64>;
+
+    /// Returns the value a key had at the moment.
+    async fn get_at(&self, ns: &str, key: &[u8], at: At) -> Result<Vec<u8>> {
+        let versions = self.history(ns, key).await?;
+        versions
+            .into_iter()
+            .take_while(|version| at.sees(version))
+            .last()
+            .and_then(|version| version.value)
+            .ok_or_else(|| Error::RecordMissing(lossy(key)))
+    }
 }
 
-use std::path::PathBuf;
+pub use astrobase_api::{At, Conflict, Version, Written};
+
+/// Pairs of a batch to be written, borrowed from the request.
+type Planned<'a> = Vec<(&'a [u8], &'a [u8])>;
+
+/// Decides which pairs of a batch are to be written given the current values
+/// of the keys. Fails in insert-only mode if any key exists, so nothing is written.
+fn plan_batch(
+    pairs: &[(Vec<u8>, Vec<u8>)],
+    conflict: Conflict,
+    current: impl Fn(&[u8]) -> Option<Vec<u8>>,
+) -> Result<(Planned<'_>, Written)> {
+    let mut pending: HashMap<&[u8], &[u8]> = HashMap::new();
+    let mut planned = Vec::new();
+    let mut written = Written::default();
+
+    for (key, value) in pairs {
+        let old = match pending.get(key.as_slice()) {
+            Some(old) => Some(old.to_vec()),
+            None => current(key),
+        };
+        match old {
+            None => written.inserted += 1,
+            Some(_) if conflict == Conflict::InsertOnly => {
+                return Err(Error::RecordAlreadyExists(lossy(key)))
+            }
+            Some(old) if conflict == Conflict::Skip || &old == value => {
+                written.skipped += 1;
+                continue;
+            }
+            Some(_) => written.updated += 1,
+        }
+        pending.insert(key, value);
+        planned.push((key.as_slice(), value.as_slice()));
+    }
+    Ok((planned, written))
+}
+
+/// Returns the bounds of the keys a scan goes through: from the prefix or
+/// the key after `after`, whichever is later.
+fn scan_range<'a>(prefix: &'a [u8], after: Option<&'a [u8]>) -> (Bound<&'a [u8]>, Bound<&'a [u8]>) {
+    match after {
+        Some(after) if after >= prefix => (Bound::Excluded(after), Bound::Unbounded),
+        _ => (Bound::Included(prefix), Bound::Unbounded),
+    }
+}
+
+/// Converts a key to text for messages, invalid UTF-8 is replaced.
+fn lossy(key: &[u8]) -> String {
+    String::from_utf8_lossy(key).into_owned()
+}
+
+/// Checks a namespace name is non-empty, short and safe to use in file names.
+fn ensure_namespace_valid(ns: &str) -> Result<()> {
+    let valid = !ns.is_empty()
+        && ns.len() <= MAX_NAMESPACE_LEN
+        && ns
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(Error::NamespaceInvalid(ns.into()));
+    }
+    Ok(())
+}
 
 /// Represents database errors.
 #[derive(thiserror::Error, Debug)]
@@ -39,6 +149,20 @@ pub enum Error {
     #[error("Invalid record '{0}'")]
     RecordInvalid(String),
 
+    #[error("Namespace '{0}' is missing")]
+    NamespaceMissing(String),
+    #[error("Namespace '{0}' already exists")]
+    NamespaceAlreadyExists(String),
+    #[error("Invalid namespace name '{0}'")]
+    NamespaceInvalid(String),
+    #[error("Namespace '{0}' cannot be dropped")]
+    NamespacePermanent(String),
+
+    #[error("The database keeps no history")]
+    HistoryUnsupported,
+    #[error("Merge of the log segments failed: {0}")]
+    Merge(#[source] tokio::task::JoinError),
+
     #[error("Unsupported database file name '{0}'")]
     Filename(PathBuf),
 
@@ -1,149 +1,674 @@
 //! astrobase-server persistent key-value database.
+//!
+//! The log of a namespace is split into numbered segments in the manner of Bitcask:
+//! the namespace file is the segment 0, the later ones are named `<file>.<number>`.
+//! Writes go to the last, active, segment which rolls over once it grows beyond
+//! the segment size. An index in memory keeps where the current version of every
+//! live key is. The older segments never change and are merged into the segment 0
+//! in the background while the active one takes new writes.
 
 use super::storage::Storage;
-use super::{Error, Result};
+use super::{
+    ensure_namespace_valid, lossy, plan_batch, scan_range, Conflict, Error, Result, Version,
+    Written, DEFAULT_NAMESPACE,
+};
 use crate::config;
 
 use async_trait::async_trait;
 use file_lock::FileLock;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const EXTENSION: &str = "db";
+/// The suffix of the file a merge is written to before it replaces the segment 0.
+const MERGING: &str = "merging";
+/// How many versions a merge copies at once.
+const MERGE_BATCH: usize = 1024;
 
 /// Represents the database internals.
+/// The default namespace lives in the main file, others in sibling files
+/// named `<stem>.<namespace>.db`, each followed by its segments.
 pub struct Persistent {
     filename: PathBuf,
-    //index: HashMap<String, u64>,
+    segment_size: u64,
+    logs: Mutex<HashMap<String, Slot>>,
 }
 
-#[async_trait]
-impl super::Database for Persistent {
-    /// Construct new instance of the database.
-    fn new() -> Self {
-        Persistent {
-            filename: config::DEFAULT_DB.into(),
-            //index: HashMap::new(),
+/// Holds the log of a namespace once it is read.
+type Slot = Arc<Mutex<Option<Log>>>;
+
+/// Represents the log of a namespace.
+struct Log {
+    file: PathBuf,
+    segments: Vec<u64>,                 // numbers, the last one is active
+    index: BTreeMap<Vec<u8>, Location>, // of the live keys
+    seq: u64,                           // of the last version written
+}
+
+/// Represents where the current version of a key is.
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    segment: u64,
+    offset: u64,
+}
+
+impl Log {
+    /// Reads every segment of the log building the index.
+    /// A version not newer than the ones before it is left by an interrupted
+    /// merge and is skipped.
+    fn load(file: &Path) -> Result<Self> {
+        let mut log = Log {
+            file: file.into(),
+            segments: segment_numbers(file)?,
+            index: BTreeMap::new(),
+            seq: 0,
+        };
+        for &segment in &log.segments {
+            let path = segment_path(file, segment);
+            if !path.exists() {
+                continue;
+            }
+            let (index, seq) = (&mut log.index, &mut log.seq);
+            Storage::open(&path)?.records(|offset, key, version| {
+                if version.seq <= *seq {
+                    return Ok(());
+                }
+                *seq = version.seq;
+                if version.value.is_some() {
+                    index.insert(key, Location { segment, offset });
+                } else {
+                    index.remove(&key);
+                }
+                Ok(())
+            })?;
         }
+        Ok(log)
     }
 
-    /// Deletes file with records.
-    async fn clear(&self) -> Result<()> {
-        if !self.filename.exists() {
+    /// Returns the file of a segment.
+    fn path(&self, segment: u64) -> PathBuf {
+        segment_path(&self.file, segment)
+    }
+
+    /// Returns the current value of a key.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.index.get(key) {
+            Some(location) => {
+                Storage::open(&self.path(location.segment))?.value_at(location.offset)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Writes new versions to the active segment, None deletes a key.
+    /// Rolls the segment over first if it is full or in the legacy format.
+    fn append(&mut self, records: &[(&[u8], Option<&[u8]>)], segment_size: u64) -> Result<()> {
+        if records.is_empty() {
             return Ok(());
         }
+        let mut segment = *self.segments.last().unwrap_or(&0);
+        let mut storage = Storage::open_w(&self.path(segment))?;
+        if storage.is_legacy() || storage.size()? >= segment_size.max(1) {
+            segment += 1;
+            self.segments.push(segment);
+            storage = Storage::open_w(&self.path(segment))?;
+        }
 
-        let file = lock_write(&self.filename)?;
-        std::fs::remove_file(&self.filename)
-            .map_err(|e| Error::DeleteFile(e, self.filename.clone()))?;
+        let timestamp = now();
+        let versions: Vec<Version> = (1..)
+            .zip(records)
+            .map(|(i, (_, value))| Version {
+                value: value.map(<[u8]>::to_vec),
+                seq: self.seq + i,
+                timestamp,
+            })
+            .collect();
+        let lines: Vec<(&[u8], &Version)> =
+            records.iter().map(|(key, _)| *key).zip(&versions).collect();
+        let offsets = storage.append(&lines)?;
 
-        file.unlock()?;
+        for ((key, version), offset) in lines.into_iter().zip(offsets) {
+            if version.value.is_some() {
+                self.index
+                    .insert(key.to_vec(), Location { segment, offset });
+            } else {
+                self.index.remove(key);
+            }
+        }
+        self.seq += records.len() as u64;
         Ok(())
     }
 
-    /// Returns a value or error if not found.
-    async fn get(&self, key: &str) -> Result<String> {
-        if !self.filename.exists() {
-            return Err(Error::FileMissing(self.filename.clone()));
+    /// Returns every version of a key, the oldest first.
+    fn history(&self, key: &[u8]) -> Result<Vec<Version>> {
+        let mut versions = Vec::new();
+        read_versions(&self.file, &self.segments, |k, version| {
+            if k == key {
+                versions.push(version);
+            }
+            Ok(())
+        })?;
+        Ok(versions)
+    }
+}
+
+impl Persistent {
+    /// Returns the log file of a namespace.
+    fn log_file(&self, ns: &str) -> PathBuf {
+        if ns == DEFAULT_NAMESPACE {
+            return self.filename.clone();
         }
+        self.filename
+            .with_file_name(format!("{}.{}.{}", self.stem(), ns, EXTENSION))
+    }
 
-        let mut value = String::default();
-        let file = lock_read(&self.filename)?;
+    /// Returns the log file of an existing namespace.
+    /// The file of the default namespace may be not created yet.
+    fn existing_log_file(&self, ns: &str) -> Result<PathBuf> {
+        ensure_namespace_valid(ns)?;
+        let filename = self.log_file(ns);
+        if ns != DEFAULT_NAMESPACE && !filename.exists() {
+            return Err(Error::NamespaceMissing(ns.into()));
+        }
+        Ok(filename)
+    }
 
-        // RAII block to close file
-        {
-            if let Ok(storage) = Storage::open(&self.filename) {
-                value = storage.find_last(key)?;
+    /// Returns the name of the main file without extension.
+    fn stem(&self) -> String {
+        self.filename
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Returns names of the namespaces which have their own files.
+    fn extra_namespaces(&self) -> Result<Vec<String>> {
+        let prefix = format!("{}.", self.stem());
+        let suffix = format!(".{}", EXTENSION);
+
+        let mut names = Vec::new();
+        for name in file_names(&self.filename)? {
+            if let Some(ns) = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(&suffix))
+            {
+                if ensure_namespace_valid(ns).is_ok() && ns != DEFAULT_NAMESPACE {
+                    names.push(ns.to_owned());
+                }
             }
         }
+        Ok(names)
+    }
+
+    /// Returns the slot of the log of a namespace. The map is only locked
+    /// to find it, the operations lock the slot.
+    fn slot(&self, ns: &str) -> Slot {
+        let mut logs = self.logs.lock().unwrap_or_else(|e| e.into_inner());
+        logs.entry(ns.into()).or_default().clone()
+    }
+
+    /// Runs an operation on the log of an existing namespace.
+    fn with_log<T>(
+        &self,
+        ns: &str,
+        is_writable: bool,
+        operation: impl FnOnce(&mut Log) -> Result<T>,
+    ) -> Result<T> {
+        let filename = self.existing_log_file(ns)?;
+        with_slot(&self.slot(ns), &filename, is_writable, operation)
+    }
+
+    /// Deletes the files of a namespace, its segments first.
+    /// The operations waiting for the log read it anew.
+    fn remove_files(&self, ns: &str) -> Result<()> {
+        let filename = self.log_file(ns);
+        let slot = self
+            .logs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(ns);
+        let mut log = slot.as_ref().map(lock);
+        if let Some(log) = &mut log {
+            **log = None;
+        }
+        if !filename.exists() {
+            return Ok(());
+        }
 
-        if value.is_empty() {
-            return Err(Error::RecordMissing(key.into()));
+        let file = lock_write(&filename)?;
+        for segment in segment_numbers(&filename)?.into_iter().rev() {
+            let path = segment_path(&filename, segment);
+            std::fs::remove_file(&path).map_err(|e| Error::DeleteFile(e, path))?;
         }
 
         file.unlock()?;
-        Ok(value)
+        Ok(())
     }
+}
 
-    /// Inserts new record if there was no such file or key.
-    async fn insert(&self, key: &str, value: &str) -> Result<String> {
-        let file = lock_write(&self.filename)?;
+/// Locks the slot of a log.
+fn lock(slot: &Slot) -> MutexGuard<'_, Option<Log>> {
+    slot.lock().unwrap_or_else(|e| e.into_inner())
+}
 
-        // RAII block to close file
-        {
-            if let Ok(storage) = Storage::open(&self.filename) {
-                if !storage.find_last(key)?.is_empty() {
-                    return Err(Error::RecordAlreadyExists(key.into()));
-                }
+/// Runs an operation on the log in the slot, reading it first if it is not yet.
+/// The file is locked for the operation.
+fn with_slot<T>(
+    slot: &Slot,
+    filename: &Path,
+    is_writable: bool,
+    operation: impl FnOnce(&mut Log) -> Result<T>,
+) -> Result<T> {
+    let mut log = lock(slot);
+    let file = if is_writable {
+        lock_write(filename)?
+    } else {
+        lock_read(filename)?
+    };
+    if log.is_none() {
+        *log = Some(Log::load(filename)?);
+    }
+    let result = operation(log.as_mut().expect("the log is read"));
+    file.unlock()?;
+    result
+}
+
+/// Merges the segments of a log but the active one into the segment 0
+/// leaving only the versions reads within the retention window can see:
+/// those current at any moment after the horizon and the last one, so the
+/// sequence numbers go on after a restart. The last version of a key is kept
+/// unless it is a delete before the horizon; the merged segments are the oldest,
+/// so nothing older is left to resurface.
+///
+/// The segments are read twice, to find the versions kept and to copy them,
+/// while the writes go on; the log only waits for the merged file to replace
+/// them. Returns the number of the lines removed. A segment 0 in the legacy
+/// format is rewritten even if it is the only one merged.
+fn merge(slot: &Slot, filename: &Path, retention: Duration) -> Result<u64> {
+    let segments = with_slot(slot, filename, false, |log| Ok(log.segments.clone()))?;
+    let legacy = segments.len() == 2 && Storage::open(filename)?.is_legacy();
+    let merged = match segments {
+        segments if segments.len() > 2 || legacy => segments[..segments.len() - 1].to_vec(),
+        _ => return Ok(0),
+    };
+
+    let horizon = now().saturating_sub(retention.as_millis() as u64);
+    let mut last = HashMap::new(); // the sequence number, time and liveness of the last versions
+    let mut kept = HashSet::new(); // the sequence numbers of the versions kept
+    let mut total = 0;
+    let mut max = 0;
+    read_versions(filename, &merged, |key, version| {
+        total += 1;
+        max = version.seq;
+        let next = (version.seq, version.timestamp, version.value.is_some());
+        if let Some((replaced, _, _)) = last.insert(key, next) {
+            if version.timestamp > horizon {
+                kept.insert(replaced);
             }
         }
+        Ok(())
+    })?;
+    for &(seq, timestamp, live) in last.values() {
+        if live || timestamp > horizon || seq == max {
+            kept.insert(seq);
+        }
+    }
 
-        // RAII block to close file
-        {
-            let mut storage = Storage::open_w(&self.filename)?;
-            storage.push(key, value)?;
+    let mut tmp = filename.as_os_str().to_owned();
+    tmp.push(format!(".{}", MERGING));
+    let tmp = PathBuf::from(tmp);
+    let relocated = match copy_versions(filename, &merged, &tmp, &kept, &last) {
+        Ok(relocated) => relocated,
+        Err(e) => {
+            std::fs::remove_file(&tmp).ok();
+            return Err(e);
         }
+    };
 
-        file.unlock()?;
-        Ok(String::default())
+    let mut log = lock(slot);
+    let log = match log.as_mut() {
+        Some(log) if log.segments.starts_with(&merged) => log,
+        _ => {
+            // Dropped meanwhile.
+            std::fs::remove_file(&tmp).ok();
+            return Ok(0);
+        }
+    };
+    let file = lock_write(filename)?;
+    std::fs::rename(&tmp, filename).map_err(|e| Error::OpenFile(e, filename.into()))?;
+    // The oldest first: if interrupted, the segments left only repeat the merged versions.
+    for &segment in &merged[1..] {
+        let path = log.path(segment);
+        std::fs::remove_file(&path).map_err(|e| Error::DeleteFile(e, path))?;
+    }
+    log.segments.drain(1..merged.len());
+    for (key, offset) in relocated {
+        if let Some(location) = log.index.get_mut(&key) {
+            if merged.contains(&location.segment) {
+                *location = Location { segment: 0, offset };
+            }
+        }
     }
 
-    /// Deletes a record or returns error if was missing.
-    async fn delete(&self, key: &str) -> Result<String> {
-        let file = lock_write(&self.filename)?;
-
-        // RAII block to close file
-        let value = {
-            match Storage::open(&self.filename) {
-                Err(_) => return Err(Error::RecordAlreadyMissing(key.into())),
-                Ok(storage) => {
-                    let value = storage.find_last(key)?;
-                    if value.is_empty() {
-                        return Err(Error::RecordAlreadyMissing(key.into()));
-                    }
-                    value
-                }
+    file.unlock()?;
+    Ok(total - kept.len() as u64)
+}
+
+/// Copies the versions kept by a merge to the file in batches.
+/// Returns where the current versions of the live keys are now.
+fn copy_versions(
+    filename: &Path,
+    merged: &[u64],
+    tmp: &Path,
+    kept: &HashSet<u64>,
+    last: &HashMap<Vec<u8>, (u64, u64, bool)>,
+) -> Result<Vec<(Vec<u8>, u64)>> {
+    std::fs::remove_file(tmp).ok();
+    let mut storage = Storage::open_w(tmp)?;
+    let mut batch = Vec::with_capacity(MERGE_BATCH);
+    let mut relocated = Vec::new();
+    let mut flush = |batch: &mut Vec<(Vec<u8>, Version)>| -> Result<()> {
+        let lines: Vec<(&[u8], &Version)> = batch
+            .iter()
+            .map(|(key, version)| (key.as_slice(), version))
+            .collect();
+        let offsets = storage.append(&lines)?;
+        for ((key, version), offset) in batch.drain(..).zip(offsets) {
+            if version.value.is_some() && last[&key].0 == version.seq {
+                relocated.push((key, offset));
             }
-        };
+        }
+        Ok(())
+    };
+    read_versions(filename, merged, |key, version| {
+        if kept.contains(&version.seq) {
+            batch.push((key, version));
+            if batch.len() == MERGE_BATCH {
+                flush(&mut batch)?;
+            }
+        }
+        Ok(())
+    })?;
+    flush(&mut batch)?;
+    storage.sync()?;
+    Ok(relocated)
+}
 
-        // RAII block to close file
-        {
-            let mut storage = Storage::open_w(&self.filename)?;
-            storage.mark_deleted(key)?;
+#[async_trait]
+impl super::Database for Persistent {
+    /// Construct new instance of the database stored in the file.
+    fn open(cfg: &config::Database) -> Self {
+        Persistent {
+            filename: cfg.path.clone(),
+            segment_size: cfg.segment_size,
+            logs: Mutex::new(HashMap::new()),
         }
+    }
 
-        file.unlock()?;
-        Ok(value)
+    /// Deletes files with records of all namespaces.
+    async fn clear(&self) -> Result<()> {
+        for ns in self.extra_namespaces()? {
+            self.drop_namespace(&ns).await?;
+        }
+        self.remove_files(DEFAULT_NAMESPACE)
+    }
+
+    /// Returns a value or error if not found.
+    async fn get(&self, ns: &str, key: &[u8]) -> Result<Vec<u8>> {
+        let filename = self.existing_log_file(ns)?;
+        if !filename.exists() {
+            return Err(Error::FileMissing(filename));
+        }
+
+        self.with_log(ns, false, |log| log.get(key))?
+            .ok_or_else(|| Error::RecordMissing(lossy(key)))
+    }
+
+    /// Inserts new record if there was no such file or key.
+    async fn insert(&self, ns: &str, key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        self.with_log(ns, true, |log| {
+            if log.index.contains_key(key) {
+                return Err(Error::RecordAlreadyExists(lossy(key)));
+            }
+            log.append(&[(key, Some(value))], self.segment_size)?;
+            Ok(Vec::new())
+        })
+    }
+
+    /// Deletes a record or returns error if was missing.
+    async fn delete(&self, ns: &str, key: &[u8]) -> Result<Vec<u8>> {
+        self.with_log(ns, true, |log| {
+            let value = log
+                .get(key)?
+                .ok_or_else(|| Error::RecordAlreadyMissing(lossy(key)))?;
+            log.append(&[(key, None)], self.segment_size)?;
+            Ok(value)
+        })
     }
 
     /// Updates record or returns error if the record was missing or identical.
-    async fn update(&self, key: &str, value: &str) -> Result<String> {
-        let file = lock_write(&self.filename)?;
-
-        // RAII block to close file
-        let old_value = {
-            match Storage::open(&self.filename) {
-                Err(_) => return Err(Error::RecordMissing(key.into())),
-                Ok(storage) => {
-                    let old_value = storage.find_last(key)?;
-                    if old_value.is_empty() {
-                        return Err(Error::RecordMissing(key.into()));
+    async fn update(&self, ns: &str, key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        self.with_log(ns, true, |log| {
+            let old_value = log
+                .get(key)?
+                .ok_or_else(|| Error::RecordMissing(lossy(key)))?;
+            if value == old_value {
+                return Err(Error::RecordAlreadyExistsIdentical(lossy(key)));
+            }
+            log.append(&[(key, Some(value))], self.segment_size)?;
+            Ok(Vec::new())
+        })
+    }
+
+    /// Creates new empty log file for a namespace.
+    async fn create_namespace(&self, ns: &str) -> Result<()> {
+        ensure_namespace_valid(ns)?;
+        let filename = self.log_file(ns);
+        if ns == DEFAULT_NAMESPACE || filename.exists() {
+            return Err(Error::NamespaceAlreadyExists(ns.into()));
+        }
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&filename)
+            .map_err(|e| Error::OpenFile(e, filename))?;
+        Ok(())
+    }
+
+    /// Deletes the log files of a namespace.
+    async fn drop_namespace(&self, ns: &str) -> Result<()> {
+        if ns == DEFAULT_NAMESPACE {
+            return Err(Error::NamespacePermanent(ns.into()));
+        }
+        self.existing_log_file(ns)?;
+        self.remove_files(ns)
+    }
+
+    /// Returns sorted names of all namespaces.
+    async fn list_namespaces(&self) -> Result<Vec<String>> {
+        let mut names = self.extra_namespaces()?;
+        names.push(DEFAULT_NAMESPACE.into());
+        names.sort();
+        Ok(names)
+    }
+
+    /// Returns a page of the records with keys starting with the prefix sorted by key.
+    async fn scan(
+        &self,
+        ns: &str,
+        prefix: &[u8],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let filename = self.existing_log_file(ns)?;
+        if !filename.exists() {
+            return Ok(Vec::new());
+        }
+
+        self.with_log(ns, false, |log| {
+            let found = log
+                .index
+                .range::<[u8], _>(scan_range(prefix, after))
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .take(limit);
+
+            let mut segments = HashMap::new();
+            let mut records = Vec::new();
+            for (key, location) in found {
+                let storage = match segments.entry(location.segment) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        entry.insert(Storage::open(&log.path(location.segment))?)
                     }
-                    old_value
+                };
+                if let Some(value) = storage.value_at(location.offset)? {
+                    records.push((key.clone(), value));
                 }
             }
-        };
+            Ok(records)
+        })
+    }
+
+    /// Writes many records at once resolving existing keys by the conflict mode.
+    async fn write_batch(
+        &self,
+        ns: &str,
+        pairs: &[(Vec<u8>, Vec<u8>)],
+        conflict: Conflict,
+    ) -> Result<Written> {
+        self.with_log(ns, true, |log| {
+            let mut current = HashMap::new();
+            for (key, _) in pairs {
+                if let Some(value) = log.get(key)? {
+                    current.insert(key.as_slice(), value);
+                }
+            }
+
+            let (planned, written) = plan_batch(pairs, conflict, |key| current.get(key).cloned())?;
+            let records: Vec<_> = planned
+                .into_iter()
+                .map(|(key, value)| (key, Some(value)))
+                .collect();
+            log.append(&records, self.segment_size)?;
+            Ok(written)
+        })
+    }
 
-        if value == old_value {
-            return Err(Error::RecordAlreadyExistsIdentical(key.into()));
+    /// Returns every version of a key kept in the log.
+    async fn history(&self, ns: &str, key: &[u8]) -> Result<Vec<Version>> {
+        let filename = self.existing_log_file(ns)?;
+        if !filename.exists() {
+            return Ok(Vec::new());
         }
 
-        // RAII block to close file
+        self.with_log(ns, false, |log| log.history(key))
+    }
+
+    /// Merges the old segments of all namespaces without the versions
+    /// no read within the retention window can see.
+    async fn compact(&self, retention: Duration) -> Result<u64> {
+        let mut removed = 0;
+        for ns in self.list_namespaces().await? {
+            let filename = self.log_file(&ns);
+            if !filename.exists() {
+                continue;
+            }
+            let slot = self.slot(&ns);
+            let merging = tokio::task::spawn_blocking(move || merge(&slot, &filename, retention));
+            removed += merging.await.map_err(Error::Merge)??;
+        }
+        Ok(removed)
+    }
+}
+
+/// Returns the files of the segments of a log the oldest first.
+pub fn segments(file: &Path) -> Result<Vec<PathBuf>> {
+    Ok(segment_numbers(file)?
+        .into_iter()
+        .map(|segment| segment_path(file, segment))
+        .filter(|path| path.exists())
+        .collect())
+}
+
+/// Returns the numbers of the segments of a log in order, 0 included even if
+/// the namespace file is not created yet.
+fn segment_numbers(file: &Path) -> Result<Vec<u64>> {
+    let prefix = format!(
+        "{}.",
+        file.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let mut numbers = vec![0];
+    for name in file_names(file)? {
+        if let Some(number) = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.parse::<u64>().ok())
         {
-            let mut storage = Storage::open_w(&self.filename)?;
-            storage.push(key, value)?;
+            if number > 0 {
+                numbers.push(number);
+            }
         }
+    }
+    numbers.sort_unstable();
+    Ok(numbers)
+}
 
-        file.unlock()?;
-        Ok(String::default())
+/// Returns the file of a segment of a log.
+fn segment_path(file: &Path, segment: u64) -> PathBuf {
+    if segment == 0 {
+        return file.into();
     }
+    let mut path = file.as_os_str().to_owned();
+    path.push(format!(".{}", segment));
+    path.into()
+}
+
+/// Returns the names of the files next to the file.
+fn file_names(file: &Path) -> Result<Vec<String>> {
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::OpenFile(e, dir.into()))? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    Ok(names)
+}
+
+/// Visits every version in the segments, skipping the ones not newer than
+/// those before them.
+fn read_versions(
+    file: &Path,
+    segments: &[u64],
+    mut visit: impl FnMut(Vec<u8>, Version) -> Result<()>,
+) -> Result<()> {
+    let mut seq = 0;
+    for &segment in segments {
+        let path = segment_path(file, segment);
+        if !path.exists() {
+            continue;
+        }
+        Storage::open(&path)?.records(|_, key, version| {
+            if version.seq <= seq {
+                return Ok(());
+            }
+            seq = version.seq;
+            visit(key, version)
+        })?;
+    }
+    Ok(())
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
 }
 
 /// Locks a file for writing.
@@ -1,91 +1,350 @@
 //! astrobase-server persistent key-value database storage.
 
-use super::{Error, Result};
+use super::{lossy, Error, Result, Version};
 
 use std::fs::{File, OpenOptions};
 use std::path::Path;
 
-const SEP: &str = "\t";
-const DELETED: &str = "\0";
+const SEP: u8 = b'\t';
+const EOL: u8 = b'\n';
+const DELETED: &[u8] = b"\0";
+/// Starts every file of the current format. It has no separator, so it is
+/// never a line of the legacy format, where keys and values were not escaped.
+const HEADER: &[u8] = b"astrobase-log 2\n";
 
-/// Represents the storage.
+/// Represents a line of the log as it is written.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line {
+    Record(Vec<u8>, Version),
+    Invalid(Vec<u8>),
+}
+
+/// Represents the storage: a header and a line per record, key and value are
+/// separated by tab and escaped, so any bytes may be stored. The sequence number
+/// and the time of the write follow, separated by tabs as well; the lines written
+/// before they were have the sequence number of their place and no time.
+/// Files without the header are in the legacy format and only read.
 pub struct Storage {
     file: File,
+    legacy: bool,
+}
+
+/// Represents a line split into its fields, the key and the value still escaped.
+struct Fields<'a> {
+    key: &'a [u8],
+    value: &'a [u8],
+    seq: Option<u64>,
+    timestamp: u64,
 }
 
 impl Storage {
     /// Opens the storage for reading only.
     pub fn open(filename: &Path) -> Result<Self> {
         let file = File::open(filename).map_err(|e| Error::OpenFile(e, filename.into()))?;
-        Ok(Storage { file })
+        let legacy = is_legacy(&file)?;
+        Ok(Storage { file, legacy })
     }
 
     /// Opens the storage for append (creates new file if missing).
     pub fn open_w(filename: &Path) -> Result<Self> {
         let file = OpenOptions::new()
+            .read(true)
             .append(true)
             .create(true)
             .open(filename)
             .map_err(|e| Error::OpenFile(e, filename.into()))?;
-        Ok(Storage { file })
+        if file.metadata()?.len() == 0 {
+            use std::io::Write as _;
+            (&file).write_all(HEADER)?;
+        }
+        let legacy = is_legacy(&file)?;
+        if !legacy {
+            truncate_torn(&file)?;
+        }
+        Ok(Storage { file, legacy })
+    }
+
+    /// Returns whether the file is in the legacy format.
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    /// Returns the offset of the first line.
+    fn start(&self) -> u64 {
+        if self.legacy {
+            0
+        } else {
+            HEADER.len() as u64
+        }
     }
 
-    /// Searches the key and returns the value or empty string if not found.
-    /// We have to scan the entire file because only the last record with given key is actual.
-    pub fn find_last(&self, key: &str) -> Result<String> {
-        use std::io::{BufRead as _, BufReader};
+    /// Splits a line into the fields. A legacy line which is not one of the
+    /// current format is the key and the value after the first tab, since the
+    /// tabs of the values were not escaped.
+    fn split<'a>(&self, line: &'a [u8]) -> Result<Fields<'a>> {
+        let fields = split(line);
+        if fields.is_ok() || !self.legacy {
+            return fields;
+        }
+        let mut fields = line.splitn(2, |&b| b == SEP);
+        match (fields.next(), fields.next()) {
+            (Some(key), Some(value)) => Ok(Fields {
+                key,
+                value,
+                seq: None,
+                timestamp: 0,
+            }),
+            _ => Err(Error::RecordInvalid(lossy(line))),
+        }
+    }
 
-        let mut value = String::default();
-        let reader = BufReader::new(&self.file);
-        for line in reader.lines() {
-            if let Some(v) = parse(&line?, key)? {
-                value = v;
+    /// Restores a key or a value of a line.
+    fn decode(&self, bytes: &[u8]) -> Vec<u8> {
+        if self.legacy {
+            bytes.to_vec()
+        } else {
+            unescape(bytes)
+        }
+    }
+
+    /// Makes the version of the key of a line.
+    fn version(&self, fields: &Fields<'_>, seq: u64) -> Version {
+        Version {
+            value: (fields.value != DELETED).then(|| self.decode(fields.value)),
+            seq,
+            timestamp: fields.timestamp,
+        }
+    }
+
+    /// Returns the size of the file in bytes.
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Visits every record with its offset, fails on an invalid line
+    /// or if the visit does. A last line cut short by a crash is skipped.
+    pub fn records(
+        &self,
+        mut visit: impl FnMut(u64, Vec<u8>, Version) -> Result<()>,
+    ) -> Result<()> {
+        use std::io::{BufRead as _, BufReader, Seek as _, SeekFrom};
+
+        let mut file = &self.file;
+        let mut offset = self.start();
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+        let mut place = 0;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let len = reader.read_until(EOL, &mut line)?;
+            if len == 0 {
+                return Ok(());
+            }
+            place += 1;
+            if line.last() == Some(&EOL) {
+                line.pop();
+            } else if !self.legacy {
+                return Ok(());
             }
+            let fields = self.split(&line)?;
+            let version = self.version(&fields, fields.seq.unwrap_or(place));
+            visit(offset, self.decode(fields.key), version)?;
+            offset += len as u64;
         }
+    }
+
+    /// Returns the value of the record at the offset, None if it is deleted.
+    pub fn value_at(&self, offset: u64) -> Result<Option<Vec<u8>>> {
+        use std::io::{BufRead as _, BufReader, Seek as _, SeekFrom};
 
-        if value == DELETED {
-            value.clear();
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut line = Vec::new();
+        BufReader::new(file).read_until(EOL, &mut line)?;
+        if line.last() == Some(&EOL) {
+            line.pop();
         }
-        Ok(value)
+        let fields = self.split(&line)?;
+        Ok(self.version(&fields, 0).value)
     }
 
-    /// Writes new record.
-    pub fn push(&mut self, key: &str, value: &str) -> Result<String> {
+    /// Writes new records at once, returns their offsets.
+    pub fn append(&mut self, records: &[(&[u8], &Version)]) -> Result<Vec<u64>> {
         use std::io::{BufWriter, Write as _};
 
+        let mut offset = self.size()?;
+        let mut offsets = Vec::with_capacity(records.len());
         let mut writer = BufWriter::new(&self.file);
-        writeln!(&mut writer, "{}{}{}", key, SEP, value)?;
+        for (key, version) in records {
+            let value = match &version.value {
+                Some(value) => escape(value),
+                None => DELETED.to_vec(),
+            };
+            let line = record(&escape(key), &value, version.seq, version.timestamp);
+            writer.write_all(&line)?;
+            offsets.push(offset);
+            offset += line.len() as u64;
+        }
         writer.flush()?;
+        Ok(offsets)
+    }
 
-        Ok(String::default())
+    /// Flushes the written records to the disk.
+    pub fn sync(&self) -> Result<()> {
+        Ok(self.file.sync_all()?)
     }
 
-    /// Adds new record with special value to mark a key as deleted.
-    pub fn mark_deleted(&mut self, key: &str) -> Result<String> {
-        self.push(key, DELETED)
+    /// Visits every line with its offset, including the invalid ones the other
+    /// methods fail on. Returns whether the last line ends with a line break;
+    /// it does not while the line is being written.
+    pub fn walk(&self, mut visit: impl FnMut(u64, Line)) -> Result<bool> {
+        use std::io::{BufRead as _, BufReader, Seek as _, SeekFrom};
+
+        let mut file = &self.file;
+        let mut offset = self.start();
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+        let mut place = 0;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let len = reader.read_until(EOL, &mut line)?;
+            if len == 0 {
+                return Ok(true);
+            }
+            place += 1;
+            let terminated = line.last() == Some(&EOL);
+            if terminated {
+                line.pop();
+            }
+            let parsed = match self.split(&line) {
+                Ok(fields) => {
+                    let version = self.version(&fields, fields.seq.unwrap_or(place));
+                    Line::Record(self.decode(fields.key), version)
+                }
+                Err(_) => Line::Invalid(line.clone()),
+            };
+            visit(offset, parsed);
+            if !terminated {
+                return Ok(false);
+            }
+            offset += len as u64;
+        }
+    }
+}
+
+/// Makes a line of an escaped record.
+fn record(key: &[u8], value: &[u8], seq: u64, timestamp: u64) -> Vec<u8> {
+    let mut line = Vec::with_capacity(key.len() + value.len() + 32);
+    line.extend_from_slice(key);
+    line.push(SEP);
+    line.extend_from_slice(value);
+    line.extend_from_slice(format!("\t{}\t{}", seq, timestamp).as_bytes());
+    line.push(EOL);
+    line
+}
+
+/// Splits a line into the fields.
+fn split(record: &[u8]) -> Result<Fields<'_>> {
+    let invalid = || Error::RecordInvalid(lossy(record));
+    let mut fields = record.split(|&b| b == SEP);
+    let key = fields.next().ok_or_else(invalid)?;
+    let value = fields.next().ok_or_else(invalid)?;
+    let (seq, timestamp) = match (fields.next(), fields.next(), fields.next()) {
+        (None, _, _) => (None, 0),
+        (Some(seq), Some(timestamp), None) => (
+            Some(number(seq).ok_or_else(invalid)?),
+            number(timestamp).ok_or_else(invalid)?,
+        ),
+        _ => return Err(invalid()),
+    };
+    Ok(Fields {
+        key,
+        value,
+        seq,
+        timestamp,
+    })
+}
+
+/// Parses a decimal number of a line.
+fn number(bytes: &[u8]) -> Option<u64> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+/// Drops the last line of a file if a crash cut it short,
+/// so the next record written starts on a line of its own.
+fn truncate_torn(file: &File) -> Result<()> {
+    use std::io::{Read as _, Seek as _, SeekFrom};
+
+    let size = file.metadata()?.len();
+    let mut end = size;
+    let mut chunk = [0; 4096];
+    let mut reader = file;
+    while end > HEADER.len() as u64 {
+        let start = end
+            .saturating_sub(chunk.len() as u64)
+            .max(HEADER.len() as u64);
+        let chunk = &mut chunk[..(end - start) as usize];
+        reader.seek(SeekFrom::Start(start))?;
+        reader.read_exact(chunk)?;
+        if let Some(last) = chunk.iter().rposition(|&b| b == EOL) {
+            end = start + last as u64 + 1;
+            break;
+        }
+        end = start;
     }
+    if end < size {
+        file.set_len(end)?;
+    }
+    Ok(())
+}
+
+/// Checks whether a file has lines but no header.
+fn is_legacy(file: &File) -> Result<bool> {
+    use std::io::{Read as _, Seek as _, SeekFrom};
 
-    /// Collects garbage — removes duplicates and deleted records.
-    #[allow(unused)]
-    #[allow(clippy::unused_self)]
-    pub fn compact(&mut self) -> Result<()> {
-        unimplemented!()
+    let mut file = file;
+    file.seek(SeekFrom::Start(0))?;
+    let mut start = Vec::with_capacity(HEADER.len());
+    file.take(HEADER.len() as u64).read_to_end(&mut start)?;
+    Ok(!start.is_empty() && start != HEADER)
+}
+
+/// Escapes the bytes which would break the line format:
+/// the separator, line breaks, the deleted marker and backslash itself.
+pub fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\t' => escaped.extend_from_slice(b"\\t"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            b'\r' => escaped.extend_from_slice(b"\\r"),
+            b'\0' => escaped.extend_from_slice(b"\\0"),
+            b => escaped.push(b),
+        }
     }
+    escaped
 }
 
-/// Parses a string retrieving key and value.
-/// Returns None if another key present.
-fn parse(record: &str, key: &str) -> Result<Option<String>> {
-    let mut pair = record.split(SEP);
-    let head = pair
-        .next()
-        .ok_or_else(|| Error::RecordInvalid(record.into()))?;
-    if head == key {
-        return Ok(Some(
-            pair.next()
-                .ok_or_else(|| Error::RecordInvalid(record.into()))?
-                .into(),
-        ));
-    }
-    Ok(None)
+/// Restores bytes escaped by `escape`.
+pub fn unescape(bytes: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        if b != b'\\' {
+            unescaped.push(b);
+            continue;
+        }
+        match iter.next() {
+            Some(b't') => unescaped.push(b'\t'),
+            Some(b'n') => unescaped.push(b'\n'),
+            Some(b'r') => unescaped.push(b'\r'),
+            Some(b'0') => unescaped.push(b'\0'),
+            Some(&b) => unescaped.push(b),
+            None => unescaped.push(b'\\'),
+        }
+    }
+    unescaped
 }
@@ -1,6 +1,12 @@
 //! astrobase-server key-value database unit tests.
 
-use super::{Database, InMemory, Persistent};
+use super::{
+    segments, At, Conflict, Database, InMemory, Line, Persistent, Storage, Version, Written,
+    DEFAULT_NAMESPACE as NS,
+};
+use crate::config;
+use std::path::Path;
+use std::time::Duration;
 
 #[tokio::test]
 async fn inmemory() {
@@ -15,12 +21,12 @@ async fn persistent() {
 }
 
 async fn populate_database<Db: Database>() -> Db {
-    let db = Db::new();
+    let db = Db::open(&config::Database::default());
     db.clear().await.ok();
-    db.insert("a", "1").await.ok();
-    db.insert("b", "2").await.ok();
-    db.insert("c", "3").await.ok();
-    db.insert("d", "4").await.ok();
+    db.insert(NS, b"a", b"1").await.ok();
+    db.insert(NS, b"b", b"2").await.ok();
+    db.insert(NS, b"c", b"3").await.ok();
+    db.insert(NS, b"d", b"4").await.ok();
     db
 }
 
@@ -29,87 +35,447 @@ async fn run_tests<Db: Database>(db: Db) {
     test_insert(&db).await;
     test_delete(&db).await;
     test_update(&db).await;
+    test_namespaces(&db).await;
+    test_scan(&db).await;
+    test_write_batch(&db).await;
+    test_binary(&db).await;
 }
 
 async fn test_get<Db: Database>(db: &Db) {
-    let r = db.get("a").await;
+    let r = db.get(NS, b"a").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "1");
+    assert_eq!(r.unwrap(), b"1");
 
-    let r = db.get("z").await;
+    let r = db.get(NS, b"z").await;
     assert!(r.is_err());
     assert_eq!(r.unwrap_err().to_string(), "Record 'z' is missing");
 }
 
 async fn test_insert<Db: Database>(db: &Db) {
-    let r = db.insert("z", "26").await;
+    let r = db.insert(NS, b"z", b"26").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "");
+    assert_eq!(r.unwrap(), b"");
 
-    let r = db.get("z").await;
+    let r = db.get(NS, b"z").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "26");
+    assert_eq!(r.unwrap(), b"26");
 
-    let r = db.insert("z", "26").await;
+    let r = db.insert(NS, b"z", b"26").await;
     assert!(r.is_err());
     assert_eq!(r.unwrap_err().to_string(), "Record 'z' already exists");
 
-    let r = db.delete("z").await;
+    let r = db.delete(NS, b"z").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "26");
+    assert_eq!(r.unwrap(), b"26");
 
-    let r = db.insert("z", "1000").await;
+    let r = db.insert(NS, b"z", b"1000").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "");
+    assert_eq!(r.unwrap(), b"");
 
-    let r = db.get("z").await;
+    let r = db.get(NS, b"z").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "1000");
+    assert_eq!(r.unwrap(), b"1000");
 }
 
 async fn test_delete<Db: Database>(db: &Db) {
-    let r = db.delete("d").await;
+    let r = db.delete(NS, b"d").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "4");
+    assert_eq!(r.unwrap(), b"4");
 
-    let r = db.delete("z").await;
+    let r = db.delete(NS, b"z").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "1000");
+    assert_eq!(r.unwrap(), b"1000");
 
-    let r = db.delete("z").await;
+    let r = db.delete(NS, b"z").await;
     assert!(r.is_err());
     assert_eq!(r.unwrap_err().to_string(), "Record 'z' is already missing");
 
-    let r = db.delete("d").await;
+    let r = db.delete(NS, b"d").await;
     assert!(r.is_err());
     assert_eq!(r.unwrap_err().to_string(), "Record 'd' is already missing");
 }
 
 async fn test_update<Db: Database>(db: &Db) {
-    let r = db.update("a", "100").await;
+    let r = db.update(NS, b"a", b"100").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "");
+    assert_eq!(r.unwrap(), b"");
 
-    let r = db.get("a").await;
+    let r = db.get(NS, b"a").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "100");
+    assert_eq!(r.unwrap(), b"100");
 
-    let r = db.update("b", "2").await;
+    let r = db.update(NS, b"b", b"2").await;
     assert!(r.is_err());
     assert_eq!(
         r.unwrap_err().to_string(),
         "Record 'b' already exists and identical"
     );
 
-    let r = db.update("z", "26").await;
+    let r = db.update(NS, b"z", b"26").await;
     assert!(r.is_err());
     assert_eq!(r.unwrap_err().to_string(), "Record 'z' is missing");
 
-    let r = db.delete("a").await;
+    let r = db.delete(NS, b"a").await;
     assert!(r.is_ok());
-    assert_eq!(r.unwrap(), "100");
+    assert_eq!(r.unwrap(), b"100");
 
-    let r = db.update("a", "100").await;
+    let r = db.update(NS, b"a", b"100").await;
     assert!(r.is_err());
     assert_eq!(r.unwrap_err().to_string(), "Record 'a' is missing");
 }
+
+async fn test_namespaces<Db: Database>(db: &Db) {
+    let r = db.list_namespaces().await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), vec![NS]);
+
+    let r = db.insert("team", b"b", b"20").await;
+    assert!(r.is_err());
+    assert_eq!(r.unwrap_err().to_string(), "Namespace 'team' is missing");
+
+    let r = db.create_namespace("team").await;
+    assert!(r.is_ok());
+
+    let r = db.create_namespace("team").await;
+    assert!(r.is_err());
+    assert_eq!(
+        r.unwrap_err().to_string(),
+        "Namespace 'team' already exists"
+    );
+
+    let r = db.create_namespace("../team").await;
+    assert!(r.is_err());
+    assert_eq!(
+        r.unwrap_err().to_string(),
+        "Invalid namespace name '../team'"
+    );
+
+    let r = db.list_namespaces().await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), vec![NS, "team"]);
+
+    let r = db.insert("team", b"b", b"20").await;
+    assert!(r.is_ok());
+
+    let r = db.get("team", b"b").await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), b"20");
+
+    let r = db.get(NS, b"b").await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), b"2");
+
+    let r = db.get("team", b"c").await;
+    assert!(r.is_err());
+    assert_eq!(r.unwrap_err().to_string(), "Record 'c' is missing");
+
+    let r = db.drop_namespace(NS).await;
+    assert!(r.is_err());
+    assert_eq!(
+        r.unwrap_err().to_string(),
+        "Namespace 'default' cannot be dropped"
+    );
+
+    let r = db.drop_namespace("team").await;
+    assert!(r.is_ok());
+
+    let r = db.get("team", b"b").await;
+    assert!(r.is_err());
+    assert_eq!(r.unwrap_err().to_string(), "Namespace 'team' is missing");
+
+    let r = db.drop_namespace("team").await;
+    assert!(r.is_err());
+    assert_eq!(r.unwrap_err().to_string(), "Namespace 'team' is missing");
+}
+
+async fn test_scan<Db: Database>(db: &Db) {
+    let r = db.scan(NS, b"", None, usize::MAX).await;
+    assert!(r.is_ok());
+    assert_eq!(
+        r.unwrap(),
+        vec![
+            (b"b".to_vec(), b"2".to_vec()),
+            (b"c".to_vec(), b"3".to_vec())
+        ]
+    );
+
+    let r = db.scan(NS, b"c", None, usize::MAX).await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), vec![(b"c".to_vec(), b"3".to_vec())]);
+
+    let r = db.scan(NS, b"", None, 1).await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), vec![(b"b".to_vec(), b"2".to_vec())]);
+
+    let r = db.scan(NS, b"", Some(b"b"), 1).await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), vec![(b"c".to_vec(), b"3".to_vec())]);
+
+    let r = db.scan(NS, b"c", Some(b"a"), usize::MAX).await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), vec![(b"c".to_vec(), b"3".to_vec())]);
+
+    let r = db.scan(NS, b"", Some(b"c"), usize::MAX).await;
+    assert!(r.is_ok());
+    assert!(r.unwrap().is_empty());
+
+    let r = db.scan("team", b"", None, usize::MAX).await;
+    assert!(r.is_err());
+    assert_eq!(r.unwrap_err().to_string(), "Namespace 'team' is missing");
+}
+
+async fn test_write_batch<Db: Database>(db: &Db) {
+    let pairs = vec![
+        (b"b".to_vec(), b"20".to_vec()),
+        (b"e".to_vec(), b"5".to_vec()),
+    ];
+    let r = db.write_batch(NS, &pairs, Conflict::InsertOnly).await;
+    assert!(r.is_err());
+    assert_eq!(r.unwrap_err().to_string(), "Record 'b' already exists");
+
+    let r = db.get(NS, b"e").await;
+    assert!(r.is_err());
+    assert_eq!(r.unwrap_err().to_string(), "Record 'e' is missing");
+
+    let r = db.write_batch(NS, &pairs, Conflict::Skip).await;
+    assert!(r.is_ok());
+    let expected = Written {
+        inserted: 1,
+        updated: 0,
+        skipped: 1,
+    };
+    assert_eq!(r.unwrap(), expected);
+
+    let r = db.get(NS, b"b").await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), b"2");
+
+    let special: &[u8] = b"tab\there\nnew line \\ \xe2\x88\x91 \0 \xff\xfe";
+    let pairs = vec![
+        (b"b".to_vec(), b"20".to_vec()),
+        (b"c".to_vec(), b"3".to_vec()),
+        (b"f\tg".to_vec(), special.to_vec()),
+    ];
+    let r = db.write_batch(NS, &pairs, Conflict::Overwrite).await;
+    assert!(r.is_ok());
+    let expected = Written {
+        inserted: 1,
+        updated: 1,
+        skipped: 1,
+    };
+    assert_eq!(r.unwrap(), expected);
+
+    let r = db.get(NS, b"f\tg").await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), special);
+
+    let r = db.scan(NS, b"", None, usize::MAX).await;
+    assert!(r.is_ok());
+    assert_eq!(
+        r.unwrap(),
+        vec![
+            (b"b".to_vec(), b"20".to_vec()),
+            (b"c".to_vec(), b"3".to_vec()),
+            (b"e".to_vec(), b"5".to_vec()),
+            (b"f\tg".to_vec(), special.to_vec()),
+        ]
+    );
+}
+
+async fn test_binary<Db: Database>(db: &Db) {
+    let key = b"\xff\0binary";
+    let value: Vec<u8> = (0..=255).collect();
+
+    let r = db.insert(NS, key, &value).await;
+    assert!(r.is_ok());
+
+    let r = db.get(NS, key).await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), value);
+
+    let r = db.scan(NS, b"\xff", None, usize::MAX).await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), vec![(key.to_vec(), value.clone())]);
+
+    let r = db.delete(NS, key).await;
+    assert!(r.is_ok());
+    assert_eq!(r.unwrap(), value);
+
+    let r = db.get(NS, key).await;
+    assert!(r.is_err());
+    assert_eq!(
+        r.unwrap_err().to_string(),
+        "Record '\u{fffd}\u{0}binary' is missing"
+    );
+}
+
+#[test]
+fn storage_walk() {
+    let filename = Path::new("/tmp/astrobase-walk.db");
+    let lines = b"astrobase-log 2\na\t1\ngarbage\na\t\0\nb\\t\t2\\n\t9\t1000\nc\t3";
+    std::fs::write(filename, lines).unwrap();
+    let mut lines = Vec::new();
+    let terminated = Storage::open(filename)
+        .unwrap()
+        .walk(|offset, line| lines.push((offset, line)))
+        .unwrap();
+    std::fs::remove_file(filename).ok();
+
+    let version = |value: Option<&[u8]>, seq, timestamp| Version {
+        value: value.map(<[u8]>::to_vec),
+        seq,
+        timestamp,
+    };
+    assert!(!terminated);
+    assert_eq!(
+        lines,
+        vec![
+            (16, Line::Record(b"a".to_vec(), version(Some(b"1"), 1, 0))),
+            (20, Line::Invalid(b"garbage".to_vec())),
+            (28, Line::Record(b"a".to_vec(), version(None, 3, 0))),
+            (
+                32,
+                Line::Record(b"b\t".to_vec(), version(Some(b"2\n"), 9, 1000))
+            ),
+            (47, Line::Record(b"c".to_vec(), version(Some(b"3"), 5, 0))),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn persistent_history() {
+    let filename = Path::new("/tmp/astrobase-history.db");
+    for segment in segments(filename).unwrap() {
+        std::fs::remove_file(segment).unwrap();
+    }
+    std::fs::write(filename, b"a\t1\nb\t2\n").unwrap();
+    let cfg = config::Database {
+        path: filename.into(),
+        segment_size: 1,
+        ..Default::default()
+    };
+    let db = Persistent::open(&cfg);
+    db.update(NS, b"a", b"10").await.unwrap();
+    db.delete(NS, b"b").await.unwrap();
+    db.update(NS, b"a", b"100").await.unwrap();
+
+    let history = db.history(NS, b"a").await.unwrap();
+    let seqs: Vec<u64> = history.iter().map(|version| version.seq).collect();
+    assert_eq!(seqs, [1, 3, 5]);
+    assert_eq!(history[0].timestamp, 0);
+    assert!(history[1].timestamp > 0 && history[1].timestamp <= history[2].timestamp);
+    let history = db.history(NS, b"b").await.unwrap();
+    assert_eq!(history[1].value, None);
+    assert!(db.history(NS, b"z").await.unwrap().is_empty());
+
+    assert_eq!(db.get_at(NS, b"a", At::Seq(1)).await.unwrap(), b"1");
+    assert_eq!(db.get_at(NS, b"a", At::Seq(4)).await.unwrap(), b"10");
+    assert_eq!(db.get_at(NS, b"b", At::Seq(3)).await.unwrap(), b"2");
+    assert!(db.get_at(NS, b"b", At::Seq(4)).await.is_err());
+    assert!(db.get_at(NS, b"a", At::Seq(0)).await.is_err());
+    let written = history[1].timestamp;
+    assert_eq!(db.get_at(NS, b"a", At::Timestamp(0)).await.unwrap(), b"1");
+    assert_eq!(
+        db.get_at(NS, b"b", At::Timestamp(written - 1))
+            .await
+            .unwrap(),
+        b"2"
+    );
+
+    // Every write started a segment; all but the active one are merged.
+    // The versions written within the window stay, so do the ones they replaced.
+    assert_eq!(segments(filename).unwrap().len(), 4);
+    assert_eq!(db.compact(Duration::from_secs(60)).await.unwrap(), 0);
+    assert_eq!(segments(filename).unwrap().len(), 2);
+    assert_eq!(db.history(NS, b"a").await.unwrap().len(), 3);
+    db.insert(NS, b"c", b"3").await.unwrap();
+    assert_eq!(db.history(NS, b"c").await.unwrap()[0].seq, 6);
+    // Then only the current values and the last line are left.
+    assert_eq!(db.compact(Duration::ZERO).await.unwrap(), 4);
+    let history = db.history(NS, b"a").await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].value.as_deref(), Some(&b"100"[..]));
+    assert!(db.history(NS, b"b").await.unwrap().is_empty());
+    assert_eq!(db.get(NS, b"a").await.unwrap(), b"100");
+    assert_eq!(db.get(NS, b"c").await.unwrap(), b"3");
+    db.insert(NS, b"d", b"4").await.unwrap();
+
+    // A merge interrupted before the old segments are deleted leaves
+    // the versions repeated, they are skipped.
+    let mut stale = filename.as_os_str().to_owned();
+    stale.push(".1");
+    std::fs::copy(filename, stale).unwrap();
+    let db = Persistent::open(&cfg);
+    assert_eq!(db.history(NS, b"a").await.unwrap().len(), 1);
+    let scanned = db.scan(NS, b"", None, usize::MAX).await.unwrap();
+    let keys: Vec<&[u8]> = scanned.iter().map(|(key, _)| key.as_slice()).collect();
+    assert_eq!(keys, [&b"a"[..], b"c", b"d"]);
+    db.clear().await.unwrap();
+    assert!(segments(filename).unwrap().is_empty());
+
+    assert!(InMemory::open(&cfg).history(NS, b"a").await.is_err());
+}
+
+#[tokio::test]
+async fn persistent_legacy_format() {
+    let filename = Path::new("/tmp/astrobase-legacy.db");
+    for segment in segments(filename).unwrap() {
+        std::fs::remove_file(segment).unwrap();
+    }
+    // Written before keys and values were escaped, backslashes are as they are.
+    let lines = b"path\tC:\\new\\table\nold\t1\nold\t\0\ncells\ta\tb\tc\n";
+    std::fs::write(filename, lines).unwrap();
+    let cfg = config::Database {
+        path: filename.into(),
+        ..Default::default()
+    };
+    let db = Persistent::open(&cfg);
+    assert_eq!(db.get(NS, b"path").await.unwrap(), b"C:\\new\\table");
+    assert_eq!(db.get(NS, b"cells").await.unwrap(), b"a\tb\tc");
+    assert!(db.get(NS, b"old").await.is_err());
+
+    // The writes go to a segment of the current format
+    // and a merge rewrites the legacy one in it.
+    db.insert(NS, b"tab", b"a\tb").await.unwrap();
+    assert_eq!(segments(filename).unwrap().len(), 2);
+    assert_eq!(db.compact(Duration::ZERO).await.unwrap(), 2);
+    assert!(!Storage::open(filename).unwrap().is_legacy());
+    let db = Persistent::open(&cfg);
+    assert_eq!(db.get(NS, b"path").await.unwrap(), b"C:\\new\\table");
+    assert_eq!(db.get(NS, b"cells").await.unwrap(), b"a\tb\tc");
+    assert_eq!(db.get(NS, b"tab").await.unwrap(), b"a\tb");
+    db.clear().await.unwrap();
+}
+
+#[tokio::test]
+async fn persistent_torn_line() {
+    let filename = Path::new("/tmp/astrobase-torn.db");
+    for segment in segments(filename).unwrap() {
+        std::fs::remove_file(segment).unwrap();
+    }
+    // The last line was being written when the server crashed.
+    std::fs::write(filename, b"astrobase-log 2\na\t1\t1\t100\nb\t2\t2").unwrap();
+    let cfg = config::Database {
+        path: filename.into(),
+        ..Default::default()
+    };
+    let db = Persistent::open(&cfg);
+    assert_eq!(db.get(NS, b"a").await.unwrap(), b"1");
+    assert!(db.get(NS, b"b").await.is_err());
+
+    // It is dropped before the next write, which gets a line of its own.
+    db.insert(NS, b"c", b"3").await.unwrap();
+    let mut keys = Vec::new();
+    let terminated = Storage::open(filename)
+        .unwrap()
+        .walk(|_, line| match line {
+            Line::Record(key, _) => keys.push(key),
+            Line::Invalid(line) => panic!("invalid line: {:?}", line),
+        })
+        .unwrap();
+    assert!(terminated);
+    assert_eq!(keys, [b"a".to_vec(), b"c".to_vec()]);
+    let db = Persistent::open(&cfg);
+    assert_eq!(db.get(NS, b"c").await.unwrap(), b"3");
+    db.clear().await.unwrap();
+}
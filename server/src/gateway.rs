@@ -0,0 +1,332 @@
+//! astrobase-server HTTP/JSON gateway to the API.
+//!
+//! Every request is turned into the `gRPC` request of the same command and
+//! handled by the service, so authorization, limits, statistics and errors
+//! are the same for both protocols.
+
+use crate::auth::Authenticator;
+
+use astrobase_api::proto::astrobase_server::Astrobase;
+use astrobase_api::proto::{Key, Output, Pair};
+use astrobase_api::{Encoding, Failure, LEADER_METADATA, MAX_VALUE_LEN};
+use axum::body::Bytes;
+use axum::extract::rejection::{BytesRejection, ContentLengthLimitRejection, PathRejection};
+use axum::extract::{ContentLengthLimit, Extension, Path, RawQuery};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tonic::{Code, Request, Status};
+use tracing::info;
+
+// A JSON string takes up to 6 bytes ("\u00XX") per byte of the value.
+const MAX_BODY_LEN: u64 = 6 * MAX_VALUE_LEN as u64 + 1024;
+
+type Body = ContentLengthLimit<Bytes, MAX_BODY_LEN>;
+type BodyRejection = ContentLengthLimitRejection<BytesRejection>;
+
+/// Represents the state shared by the handlers.
+struct Gateway<S> {
+    service: Arc<S>,
+    authenticator: Authenticator,
+}
+
+/// Serves the gateway until the shutdown future completes.
+pub async fn serve<S: Astrobase>(
+    address: SocketAddr,
+    service: Arc<S>,
+    authenticator: Authenticator,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let gateway = Arc::new(Gateway {
+        service,
+        authenticator,
+    });
+    let app = Router::new()
+        .route(
+            "/v1/keys/:key",
+            get(get_key::<S>)
+                .post(insert_key::<S>)
+                .put(update_key::<S>)
+                .delete(delete_key::<S>),
+        )
+        .layer(Extension(gateway));
+
+    info!("Gateway listening on {}", address);
+    axum::Server::try_bind(&address)?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
+
+/// Represents the body of requests writing a value.
+#[derive(Deserialize)]
+struct Value {
+    value: String,
+}
+
+/// Represents the body of successful replies.
+#[derive(Serialize)]
+struct Record {
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+}
+
+/// Represents the body of failed replies.
+#[derive(Serialize)]
+struct ErrorBody {
+    error: ErrorInfo,
+}
+
+#[derive(Serialize)]
+struct ErrorInfo {
+    kind: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    leader: Option<String>,
+}
+
+/// Represents a failed request.
+pub struct Error {
+    pub status: StatusCode,
+    pub kind: &'static str,
+    pub message: String,
+    /// The address of the leader a write to a follower is to be sent to.
+    pub leader: Option<String>,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let leader = self.leader.as_deref().map(HeaderValue::from_str);
+        let body = ErrorBody {
+            error: ErrorInfo {
+                kind: self.kind,
+                message: self.message,
+                leader: self.leader,
+            },
+        };
+        let mut response = (self.status, Json(body)).into_response();
+        if let Some(Ok(leader)) = leader {
+            response.headers_mut().insert(LEADER_METADATA, leader);
+        }
+        response
+    }
+}
+
+type Reply = Result<(StatusCode, Json<Record>), Error>;
+
+/// Handles `GET /v1/keys/{key}`: command "Get".
+async fn get_key<S: Astrobase>(
+    Extension(gateway): Extension<Arc<Gateway<S>>>,
+    key: Result<Path<String>, PathRejection>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Reply {
+    let key = key.map_err(rejected)?;
+    let params = Params::parse(query.as_deref())?;
+    let key = params.encoding.decode(&key).map_err(invalid)?;
+    let msg = Key {
+        key: key.clone(),
+        namespace: params.namespace,
+    };
+    let output = gateway.service.get(gateway.request(&headers, msg)?).await;
+    let value = reply(output)?;
+    Ok(record(StatusCode::OK, params.encoding, &key, Some(&value)))
+}
+
+/// Handles `POST /v1/keys/{key}`: command "Insert".
+async fn insert_key<S: Astrobase>(
+    Extension(gateway): Extension<Arc<Gateway<S>>>,
+    key: Result<Path<String>, PathRejection>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    body: Result<Body, BodyRejection>,
+) -> Reply {
+    let (key, ContentLengthLimit(body)) = (key.map_err(rejected)?, body.map_err(rejected)?);
+    let params = Params::parse(query.as_deref())?;
+    let msg = params.pair(&key, &body)?;
+    let key = msg.key.clone();
+    let output = gateway
+        .service
+        .insert(gateway.request(&headers, msg)?)
+        .await;
+    reply(output)?;
+    Ok(record(StatusCode::CREATED, params.encoding, &key, None))
+}
+
+/// Handles `PUT /v1/keys/{key}`: command "Update".
+async fn update_key<S: Astrobase>(
+    Extension(gateway): Extension<Arc<Gateway<S>>>,
+    key: Result<Path<String>, PathRejection>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    body: Result<Body, BodyRejection>,
+) -> Reply {
+    let (key, ContentLengthLimit(body)) = (key.map_err(rejected)?, body.map_err(rejected)?);
+    let params = Params::parse(query.as_deref())?;
+    let msg = params.pair(&key, &body)?;
+    let key = msg.key.clone();
+    let output = gateway
+        .service
+        .update(gateway.request(&headers, msg)?)
+        .await;
+    reply(output)?;
+    Ok(record(StatusCode::OK, params.encoding, &key, None))
+}
+
+/// Handles `DELETE /v1/keys/{key}`: command "Delete", replies with the deleted value.
+async fn delete_key<S: Astrobase>(
+    Extension(gateway): Extension<Arc<Gateway<S>>>,
+    key: Result<Path<String>, PathRejection>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Reply {
+    let key = key.map_err(rejected)?;
+    let params = Params::parse(query.as_deref())?;
+    let key = params.encoding.decode(&key).map_err(invalid)?;
+    let msg = Key {
+        key: key.clone(),
+        namespace: params.namespace,
+    };
+    let output = gateway
+        .service
+        .delete(gateway.request(&headers, msg)?)
+        .await;
+    let value = reply(output)?;
+    Ok(record(StatusCode::OK, params.encoding, &key, Some(&value)))
+}
+
+impl<S> Gateway<S> {
+    /// Constructs the authenticated `gRPC` request carrying the bearer token.
+    fn request<T>(&self, headers: &HeaderMap, msg: T) -> Result<Request<T>, Error> {
+        let authorization = headers
+            .get(header::AUTHORIZATION)
+            .map(|value| value.as_bytes());
+        self.authenticator
+            .request(authorization, msg)
+            .map_err(|s| status(&s))
+    }
+}
+
+/// Represents the query parameters: `namespace` and `encoding` of the key and values.
+pub struct Params {
+    pub namespace: String,
+    pub encoding: Encoding,
+}
+
+impl Params {
+    pub fn parse(query: Option<&str>) -> Result<Self, Error> {
+        let mut params = Params {
+            namespace: String::new(),
+            encoding: Encoding::Utf8,
+        };
+        let query = query.unwrap_or_default();
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = percent_decode_str(value)
+                .decode_utf8()
+                .map_err(|_| invalid(format!("Invalid parameter '{}'", name)))?;
+            match name {
+                "namespace" => params.namespace = value.into_owned(),
+                "encoding" => params.encoding = value.parse().map_err(invalid)?,
+                _ => return Err(invalid(format!("Unknown parameter '{}'", name))),
+            }
+        }
+        Ok(params)
+    }
+
+    /// Decodes the key of the path and the value of the body.
+    fn pair(&self, key: &str, body: &[u8]) -> Result<Pair, Error> {
+        let value: Value = serde_json::from_slice(body).map_err(invalid)?;
+        Ok(Pair {
+            key: self.encoding.decode(key).map_err(invalid)?,
+            value: self.encoding.decode(&value.value).map_err(invalid)?,
+            namespace: self.namespace.clone(),
+        })
+    }
+}
+
+/// Returns the value of an accepted command or the error of a rejected or failed one.
+pub fn reply(output: Result<tonic::Response<Output>, Status>) -> Result<Vec<u8>, Error> {
+    let output = output.map_err(|s| status(&s))?;
+    output.into_inner().into_result().map_err(|rejection| {
+        let (status, kind) = match rejection.failure {
+            Failure::NotFound => (StatusCode::NOT_FOUND, "not_found"),
+            Failure::AlreadyExists => (StatusCode::CONFLICT, "already_exists"),
+            Failure::Identical => (StatusCode::CONFLICT, "identical"),
+            Failure::Invalid => (StatusCode::BAD_REQUEST, "invalid"),
+            Failure::Other => (StatusCode::INTERNAL_SERVER_ERROR, "other"),
+        };
+        Error {
+            status,
+            kind,
+            message: rejection.info,
+            leader: None,
+        }
+    })
+}
+
+/// Constructs the successful reply.
+fn record(
+    code: StatusCode,
+    encoding: Encoding,
+    key: &[u8],
+    value: Option<&[u8]>,
+) -> (StatusCode, Json<Record>) {
+    let record = Record {
+        key: encoding.encode(key),
+        value: value.map(|value| encoding.encode(value)),
+    };
+    (code, Json(record))
+}
+
+/// Converts the `gRPC` status of a failed request.
+pub fn status(status: &Status) -> Error {
+    let (code, kind) = match status.code() {
+        Code::Unauthenticated => (StatusCode::UNAUTHORIZED, "unauthenticated"),
+        Code::PermissionDenied => (StatusCode::FORBIDDEN, "permission_denied"),
+        Code::InvalidArgument => (StatusCode::BAD_REQUEST, "invalid"),
+        Code::ResourceExhausted => (StatusCode::TOO_MANY_REQUESTS, "rate_limited"),
+        Code::NotFound => (StatusCode::NOT_FOUND, "not_found"),
+        Code::FailedPrecondition => (StatusCode::MISDIRECTED_REQUEST, "read_only"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "other"),
+    };
+    Error {
+        status: code,
+        kind,
+        message: status.message().into(),
+        leader: status
+            .metadata()
+            .get(LEADER_METADATA)
+            .and_then(|leader| leader.to_str().ok())
+            .map(String::from),
+    }
+}
+
+/// Converts the rejection of a request the handler cannot even extract.
+fn rejected(rejection: impl IntoResponse + ToString) -> Error {
+    let message = rejection.to_string();
+    Error {
+        status: rejection.into_response().status(),
+        kind: "invalid",
+        message,
+        leader: None,
+    }
+}
+
+/// Constructs the error of a malformed request.
+fn invalid(err: impl ToString) -> Error {
+    Error {
+        status: StatusCode::BAD_REQUEST,
+        kind: "invalid",
+        message: err.to_string(),
+        leader: None,
+    }
+}
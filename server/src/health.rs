@@ -0,0 +1,80 @@
+//! astrobase-server health checking (`grpc.health.v1.Health`).
+
+use astrobase_api::health::health_check_response::ServingStatus;
+use astrobase_api::health::{health_server, HealthCheckRequest, HealthCheckResponse};
+use std::pin::Pin;
+use tokio::sync::watch;
+use tokio_stream::{Stream, StreamExt as _};
+use tonic::{Request, Response, Status};
+
+/// The services whose status is reported: the server as a whole and the API.
+const SERVICES: [&str; 2] = ["", "api.Astrobase"];
+
+/// Sets the status reported by the health service.
+pub struct Reporter {
+    status: watch::Sender<ServingStatus>,
+}
+
+impl Reporter {
+    /// Creates the reporter (starting with NOT_SERVING) and the service reading it.
+    pub fn new() -> (Self, Health) {
+        let (status, receiver) = watch::channel(ServingStatus::NotServing);
+        (Reporter { status }, Health { status: receiver })
+    }
+
+    /// Reports that the server accepts requests.
+    pub fn serving(&self) {
+        self.status.send_replace(ServingStatus::Serving);
+    }
+
+    /// Reports that the server does not accept requests.
+    pub fn not_serving(&self) {
+        self.status.send_replace(ServingStatus::NotServing);
+    }
+}
+
+/// Represents the health service.
+pub struct Health {
+    status: watch::Receiver<ServingStatus>,
+}
+
+type WatchStream = Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl health_server::Health for Health {
+    /// Handles "Check": the current status of the service.
+    async fn check(
+        &self,
+        req: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = &req.get_ref().service;
+        if !SERVICES.contains(&service.as_str()) {
+            return Err(Status::not_found(format!("Unknown service '{}'", service)));
+        }
+        Ok(Response::new(response(*self.status.borrow())))
+    }
+
+    type WatchStream = WatchStream;
+
+    /// Handles "Watch": the current status and then every change of it.
+    async fn watch(
+        &self,
+        req: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        if !SERVICES.contains(&req.get_ref().service.as_str()) {
+            let unknown = tokio_stream::once(Ok(response(ServingStatus::ServiceUnknown)));
+            return Ok(Response::new(Box::pin(
+                unknown.chain(tokio_stream::pending()),
+            )));
+        }
+        let changes = tokio_stream::wrappers::WatchStream::new(self.status.clone());
+        Ok(Response::new(Box::pin(changes.map(response).map(Ok))))
+    }
+}
+
+/// Constructs the reply carrying the status.
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse {
+        status: status as i32,
+    }
+}
@@ -0,0 +1,148 @@
+//! astrobase-server inspection of a database file without the server.
+//!
+//! The file and its segments are read as is without taking a lock, so a running
+//! server may keep appending to them; the line being written may show as not terminated.
+
+use crate::database::{self, escape, Line, Storage};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// How much of an invalid line is shown.
+const PREVIEW_LEN: usize = 60;
+
+/// Represents what a log holds.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub segments: Vec<PathBuf>, // the files read, the oldest first
+    pub records: u64,           // the valid lines, tombstones included
+    pub tombstones: u64,
+    pub repeated: u64, // the lines an interrupted merge left behind, not counted as records
+    pub invalid: Vec<(usize, u64, Vec<u8>)>, // segments, offsets and lines
+    pub terminated: bool, // whether the last line of every segment ends with a line break
+    pub keys: BTreeMap<Vec<u8>, Option<Vec<u8>>>, // the last value of every key written, None if deleted
+    pub history: Vec<(usize, u64, Line)>, // segments, offsets and lines of the key looked up
+}
+
+impl Report {
+    /// Returns the number of the keys having a value.
+    pub fn live(&self) -> u64 {
+        self.keys.values().filter(|value| value.is_some()).count() as u64
+    }
+
+    /// Returns the number of the lines compaction would drop:
+    /// overwritten and deleted values and the tombstones.
+    pub fn dead(&self) -> u64 {
+        self.records - self.live()
+    }
+
+    /// Returns the share of the lines repeating a key written before.
+    pub fn duplicate_ratio(&self) -> f64 {
+        if self.records == 0 {
+            return 0.0;
+        }
+        (self.records - self.keys.len() as u64) as f64 / self.records as f64
+    }
+
+    /// Prints the report, with the live pairs in the line format if asked.
+    pub fn print(&self, file: &Path, key: Option<&[u8]>, dump: bool) {
+        println!("File: {}", file.display());
+        println!("Segments: {}", self.segments.len());
+        for segment in &self.segments {
+            println!("  {}", segment.display());
+        }
+        println!("Records: {}", self.records);
+        if self.repeated > 0 {
+            println!("Repeated by an interrupted merge: {}", self.repeated);
+        }
+        println!("Live: {}", self.live());
+        println!("Dead: {}", self.dead());
+        println!("Tombstones: {}", self.tombstones);
+        println!("Duplicate ratio: {:.1}%", self.duplicate_ratio() * 100.0);
+        println!("Invalid lines: {}", self.invalid.len());
+        for (segment, offset, line) in &self.invalid {
+            let shown = &line[..line.len().min(PREVIEW_LEN)];
+            println!(
+                "  {} offset {}: '{}'",
+                self.segments[*segment].display(),
+                offset,
+                String::from_utf8_lossy(shown)
+            );
+        }
+        if !self.terminated {
+            println!("The last line is not terminated: being written or cut short");
+        }
+        if let Some(key) = key {
+            println!("History of '{}': {} lines", text(key), self.history.len());
+            for (segment, offset, line) in &self.history {
+                if let Line::Record(_, version) = line {
+                    let change = match &version.value {
+                        Some(value) => format!("put '{}'", text(value)),
+                        None => "deleted".into(),
+                    };
+                    println!(
+                        "  {} offset {}: seq {}, time {}: {}",
+                        self.segments[*segment].display(),
+                        offset,
+                        version.seq,
+                        version.timestamp,
+                        change
+                    );
+                }
+            }
+        }
+        if dump {
+            println!("Live pairs:");
+            for (key, value) in &self.keys {
+                if let Some(value) = value {
+                    println!("{}\t{}", text(key), text(value));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the segments of the log counting their lines and collecting the history of the key.
+/// The lines not newer than the ones before them are skipped, as the server does.
+pub fn inspect(file: &Path, key: Option<&[u8]>) -> database::Result<Report> {
+    Storage::open(file)?;
+    let mut report = Report {
+        segments: database::segments(file)?,
+        terminated: true,
+        ..Report::default()
+    };
+    let mut seq = 0;
+    for (segment, path) in report.segments.clone().iter().enumerate() {
+        let terminated = Storage::open(path)?.walk(|offset, line| {
+            let (k, value) = match &line {
+                Line::Record(_, version) if version.seq <= seq => {
+                    report.repeated += 1;
+                    return;
+                }
+                Line::Record(k, version) => {
+                    seq = version.seq;
+                    (k, version.value.clone())
+                }
+                Line::Invalid(bytes) => {
+                    report.invalid.push((segment, offset, bytes.clone()));
+                    return;
+                }
+            };
+            report.records += 1;
+            if value.is_none() {
+                report.tombstones += 1;
+            }
+            report.keys.insert(k.clone(), value);
+            if key == Some(k.as_slice()) {
+                report.history.push((segment, offset, line));
+            }
+        })?;
+        report.terminated &= terminated;
+    }
+    Ok(report)
+}
+
+/// Shows bytes as escaped in the file, so every record takes one line.
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(&escape(bytes)).into_owned()
+}
@@ -0,0 +1,39 @@
+//! astrobase-server request rate limiter.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Represents a fixed-window counter of requests.
+pub struct RateLimiter {
+    window: Mutex<(Instant, u64)>,
+}
+
+impl RateLimiter {
+    /// Constructs new limiter with an empty window.
+    pub fn new() -> Self {
+        RateLimiter {
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Counts a request and returns false if the limit per second is exceeded.
+    /// Zero limit means unlimited.
+    pub fn admit(&self, limit: u64) -> bool {
+        if limit == 0 {
+            return true;
+        }
+
+        let mut window = self.window.lock().expect("poisoned rate limiter");
+        let now = Instant::now();
+        if now.duration_since(window.0) >= WINDOW {
+            *window = (now, 0);
+        }
+        if window.1 >= limit {
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+}
@@ -0,0 +1,55 @@
+//! astrobase-server logger with adjustable verbosity.
+
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, Registry};
+
+/// Represents the level filter layer of the logger.
+pub type Layer = reload::Layer<LevelFilter, Registry>;
+
+/// Represents a handle to change the log level at runtime.
+#[derive(Clone)]
+pub struct Handle {
+    filter: reload::Handle<LevelFilter, Registry>,
+    default: LevelFilter,
+}
+
+impl Handle {
+    /// Creates the filter layer starting with the default level and its handle.
+    pub fn new(default: LevelFilter) -> (Layer, Self) {
+        let (layer, filter) = reload::Layer::new(default);
+        (layer, Handle { filter, default })
+    }
+
+    /// Sets new maximum level of the log messages, the default one if None.
+    pub fn set_level(&self, level: Option<&str>) -> anyhow::Result<()> {
+        let filter = match level {
+            Some(level) => level
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid log level '{}'", level))?,
+            None => self.default,
+        };
+        self.filter.modify(|current| *current = filter)?;
+        Ok(())
+    }
+
+    /// Returns the current maximum level of the log messages,
+    /// None if the logger is gone.
+    pub fn level(&self) -> Option<LevelFilter> {
+        self.filter.clone_current()
+    }
+}
+
+/// Initializes the logger (takes default level from RUST_LOG, `info` otherwise).
+pub fn init() -> Handle {
+    let level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(LevelFilter::INFO);
+    let (filter, handle) = Handle::new(level);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .init();
+    handle
+}
@@ -3,41 +3,82 @@
 #![forbid(unsafe_code)]
 #![deny(warnings)]
 
-// For the generated code
-#![allow(clippy::derive_partial_eq_without_eq)]
-#![allow(clippy::too_many_lines)]
-#![allow(clippy::wildcard_imports)]
-#![allow(clippy::similar_names)]
-#![allow(clippy::default_trait_access)]
-
+mod auth;
+mod backup;
 mod cli;
 mod config;
 mod database;
+mod gateway;
+mod health;
+mod inspect;
+mod limiter;
+mod logger;
+mod memcached;
+mod protocol;
+mod raft;
+mod reflection;
+mod reload;
+mod replication;
+mod resp;
 mod server;
 mod stats;
 
+#[cfg(test)]
+mod tests;
+
 fn main() {
-    init_logger();
-    if let Err(err) = execute(&cli::application()) {
+    let logger = logger::init();
+    if let Err(err) = execute(&cli::application(), logger) {
         eprintln!("Error: {:#}", err);
         std::process::exit(config::FAILURE);
     }
 }
 
-/// Initializes the logger.
-fn init_logger() {
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", "info");
-    }
-    tracing_subscriber::fmt::init();
-}
-
 /// Dispatches CLI commands.
-fn execute(app: &cli::Application) -> anyhow::Result<()> {
-    match app.cmd {
-        cli::Command::Run => {
-            let rt = tokio::runtime::Runtime::new()?;
-            rt.block_on(run(&app.config))?;
+fn execute(app: &cli::Application, logger: logger::Handle) -> anyhow::Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+    match &app.cmd {
+        cli::Command::Run { restore } => {
+            rt.block_on(run(&app.config, restore.clone(), logger))?;
+        }
+        cli::Command::Backup {
+            archive,
+            endpoint,
+            token,
+            ca,
+            cert,
+            key,
+            domain,
+        } => {
+            let cfg = config::Astrobase::load(&app.config)?;
+            let endpoint = match endpoint {
+                Some(endpoint) => endpoint.clone(),
+                None => default_endpoint(&cfg)?,
+            };
+            let token = token
+                .as_deref()
+                .or_else(|| cfg.auth.as_ref().and_then(config::Auth::admin_token));
+            let identity = cert.as_deref().zip(key.as_deref());
+            let tls = if endpoint.starts_with("https://") {
+                Some(backup::tls_config(&cfg, ca.as_deref(), identity, domain.clone())?)
+            } else {
+                None
+            };
+            rt.block_on(backup::save(&endpoint, token, tls, archive))?;
+            tracing::info!("Saved the backup to '{}'", archive.display());
+        }
+        cli::Command::Restore { archive } => {
+            let cfg = config::Astrobase::load(&app.config)?;
+            rt.block_on(restore(&cfg, archive))?;
+        }
+        cli::Command::Inspect { db, key, dump } => {
+            let key = key.as_ref().map(|key| database::unescape(key.as_bytes()));
+            let report = inspect::inspect(db, key.as_deref())?;
+            report.print(db, key.as_deref(), *dump);
+            if !report.invalid.is_empty() {
+                anyhow::bail!("'{}' has invalid lines", db.display());
+            }
+            return Ok(());
         }
     }
 
@@ -46,7 +87,41 @@ fn execute(app: &cli::Application) -> anyhow::Result<()> {
 }
 
 /// Runs the server.
-async fn run(config_file: &std::path::Path) -> anyhow::Result<()> {
+async fn run(
+    config_file: &std::path::Path,
+    restore: Option<std::path::PathBuf>,
+    logger: logger::Handle,
+) -> anyhow::Result<()> {
     let cfg = config::Astrobase::load(config_file)?;
-    server::run(cfg).await
+    server::run(cfg, config_file.to_owned(), restore, logger).await
+}
+
+/// Returns the endpoint of the server in the config, the Unix socket if any.
+fn default_endpoint(cfg: &config::Astrobase) -> anyhow::Result<String> {
+    if let Some(unix) = &cfg.server.unix {
+        return Ok(format!("unix://{}", unix.path.display()));
+    }
+    match &cfg.server.endpoint {
+        Some(endpoint) if cfg.tls.is_some() => Ok(format!("https://{}", endpoint)),
+        Some(endpoint) => Ok(format!("http://{}", endpoint)),
+        None => anyhow::bail!("No endpoint of the server in the config"),
+    }
+}
+
+/// Loads a backup archive into the empty database of the config.
+async fn restore(cfg: &config::Astrobase, archive: &std::path::Path) -> anyhow::Result<()> {
+    if cfg!(feature = "inmemory") {
+        anyhow::bail!("The in-memory database is lost on exit, use \"run --restore\"");
+    }
+    if cfg.cluster.is_some() || cfg.replication.as_ref().is_some_and(|r| r.leader.is_some()) {
+        anyhow::bail!("A member of a cluster or a follower gets its records from the others");
+    }
+    let batches = backup::load(archive)?;
+    #[cfg(feature = "inmemory")]
+    let db = <database::InMemory as database::Database>::open(&cfg.database);
+    #[cfg(feature = "persistent")]
+    let db = <database::Persistent as database::Database>::open(&cfg.database);
+    let count = backup::restore(&db, batches).await?;
+    tracing::info!("Restored {} records from '{}'", count, archive.display());
+    Ok(())
 }
@@ -0,0 +1,463 @@
+//! astrobase-server memcached text protocol listener.
+//!
+//! The storage ("set", "add", "replace"), retrieval ("get", "gets") and
+//! "delete" commands of the text protocol call the matching `gRPC` method of
+//! the service, so limits and statistics are the ones of the API. Keys are in
+//! the default namespace, flags are not stored (replied as 0), expiration
+//! times are ignored and the "gets" unique value is a hash of the value.
+//!
+//! The protocol has no authentication of its own: a connection runs its
+//! commands as the user of the token it sent with "auth <token>", or as
+//! the user of the configured token until then.
+
+use crate::auth::Authenticator;
+use crate::protocol::{self, parse};
+use crate::stats::{Report, Stats};
+
+use astrobase_api::proto::astrobase_server::Astrobase;
+use astrobase_api::proto::{self, Batch, Key, Pair};
+use astrobase_api::{Conflict, Failure, MAX_VALUE_LEN};
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::io::{BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+use tonic::{Request, Status};
+use tracing::{debug, error, info};
+
+const MAX_LINE_LEN: u64 = 2048;
+
+/// Represents the state shared by the connections.
+struct Memcached<S> {
+    service: Arc<S>,
+    stats: Arc<RwLock<Stats>>,
+    authenticator: Authenticator,
+    authorization: Option<Vec<u8>>,
+}
+
+/// Represents the state of a connection.
+struct Connection {
+    authorization: Option<Vec<u8>>,
+}
+
+/// Accepts connections until the shutdown future completes.
+/// The commands are authorized as the user of the token, if any,
+/// until the connection authenticates by itself.
+pub async fn serve<S: Astrobase>(
+    address: SocketAddr,
+    service: Arc<S>,
+    stats: Arc<RwLock<Stats>>,
+    authenticator: Authenticator,
+    token: Option<String>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    let memcached = Arc::new(Memcached {
+        service,
+        stats,
+        authenticator,
+        authorization: token.map(|token| format!("Bearer {}", token).into_bytes()),
+    });
+
+    info!("Memcached protocol listening on {}", address);
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => return Ok(()),
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let memcached = memcached.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = memcached.connection(stream).await {
+                            debug!("Memcached connection {} closed: {}", peer, err);
+                        }
+                    });
+                }
+                Err(err) => error!("Cannot accept memcached connection: {}", err),
+            },
+        }
+    }
+}
+
+/// Represents the storage commands.
+#[derive(Clone, Copy)]
+enum Store {
+    Add,
+    Replace,
+    Set,
+}
+
+impl<S: Astrobase> Memcached<S> {
+    /// Answers the commands of a connection in order until it is closed.
+    async fn connection(&self, stream: TcpStream) -> Result<(), Error> {
+        let (reader, writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+        let mut conn = Connection {
+            authorization: self.authorization.clone(),
+        };
+
+        while let Some(line) = protocol::read_line(&mut reader, MAX_LINE_LEN).await? {
+            let reply = match words(&line).split_first() {
+                None => "ERROR\r\n".into(),
+                Some((command, _)) if *command == b"quit" => break,
+                Some((command, args)) => {
+                    match self.execute(&mut conn, &mut reader, command, args).await {
+                        Ok(reply) => reply,
+                        Err(Error::DataChunk) => {
+                            writer.write_all(&client_error("bad data chunk")).await?;
+                            writer.flush().await?;
+                            return Err(Error::DataChunk);
+                        }
+                        Err(Error::LineFormat) => {
+                            writer
+                                .write_all(&client_error("bad command line format"))
+                                .await?;
+                            writer.flush().await?;
+                            return Err(Error::LineFormat);
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+            };
+            writer.write_all(&reply).await?;
+            protocol::flush(&reader, &mut writer).await?;
+        }
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Executes a command, returns the reply (empty with "noreply").
+    async fn execute<R: AsyncBufRead + Unpin>(
+        &self,
+        conn: &mut Connection,
+        reader: &mut R,
+        command: &[u8],
+        args: &[&[u8]],
+    ) -> Result<Vec<u8>, Error> {
+        let reply = match command {
+            b"auth" => self.auth(conn, args),
+            b"get" => self.get(conn, args, false).await,
+            b"gets" => self.get(conn, args, true).await,
+            b"add" => self.store(conn, reader, Store::Add, args).await?,
+            b"replace" => self.store(conn, reader, Store::Replace, args).await?,
+            b"set" => self.store(conn, reader, Store::Set, args).await?,
+            b"delete" => self.delete(conn, args).await,
+            b"stats" => self.stats(conn, args).await,
+            b"version" => format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).into_bytes(),
+            _ => "ERROR\r\n".into(),
+        };
+        Ok(reply)
+    }
+
+    /// Constructs the authenticated `gRPC` request of the connection.
+    fn request<T>(&self, conn: &Connection, msg: T) -> Result<Request<T>, Vec<u8>> {
+        self.authenticator
+            .request(conn.authorization.as_deref(), msg)
+            .map_err(|s| status(&s))
+    }
+
+    /// Handles "auth <token>": the next commands run as the user of the token.
+    fn auth(&self, conn: &mut Connection, args: &[&[u8]]) -> Vec<u8> {
+        let token = match args {
+            [token] => token,
+            _ => return client_error("bad command line format"),
+        };
+        let authorization = [b"Bearer ".as_ref(), token].concat();
+        if let Err(s) = self.authenticator.request(Some(&authorization), ()) {
+            return status(&s);
+        }
+        conn.authorization = Some(authorization);
+        "OK\r\n".into()
+    }
+
+    /// Handles "get <key>*" and "gets <key>*": the found records.
+    async fn get(&self, conn: &Connection, keys: &[&[u8]], cas: bool) -> Vec<u8> {
+        if keys.is_empty() {
+            return "ERROR\r\n".into();
+        }
+        let mut reply = Vec::new();
+        for &key in keys {
+            let msg = Key {
+                key: key.to_vec(),
+                namespace: String::new(),
+            };
+            let req = match self.request(conn, msg) {
+                Ok(req) => req,
+                Err(reply) => return reply,
+            };
+            let output = match self.service.get(req).await {
+                Ok(output) => output.into_inner(),
+                Err(s) => return status(&s),
+            };
+            let value = match output.into_result() {
+                Ok(value) => value,
+                Err(rejection) if rejection.failure == Failure::NotFound => continue,
+                Err(rejection) => return server_error(&rejection.info),
+            };
+            reply.extend_from_slice(b"VALUE ");
+            reply.extend_from_slice(key);
+            if cas {
+                reply.extend_from_slice(
+                    format!(" 0 {} {}\r\n", value.len(), unique(&value)).as_bytes(),
+                );
+            } else {
+                reply.extend_from_slice(format!(" 0 {}\r\n", value.len()).as_bytes());
+            }
+            reply.extend_from_slice(&value);
+            reply.extend_from_slice(b"\r\n");
+        }
+        reply.extend_from_slice(b"END\r\n");
+        reply
+    }
+
+    /// Handles "<command> <key> <flags> <exptime> <bytes> [noreply]" followed
+    /// by the data block: "add" inserts, "replace" updates and "set" writes
+    /// a batch overwriting the record. A refused command still consumes its
+    /// data block, so the value is never taken for commands; the connection
+    /// is closed if the length of the block is unknown.
+    async fn store<R: AsyncBufRead + Unpin>(
+        &self,
+        conn: &Connection,
+        reader: &mut R,
+        store: Store,
+        args: &[&[u8]],
+    ) -> Result<Vec<u8>, Error> {
+        let line = match StoreLine::parse(args) {
+            Ok(line) => line,
+            Err(Some(len)) => {
+                skip_data(reader, len).await?;
+                return Ok(client_error("bad command line format"));
+            }
+            Err(None) => return Err(Error::LineFormat),
+        };
+        if line.len > MAX_VALUE_LEN {
+            skip_data(reader, line.len).await?;
+            return Ok(server_error("object too large for cache"));
+        }
+        let value = read_data(reader, line.len).await?;
+
+        let reply = self.write(conn, store, line.key, value).await;
+        Ok(if line.noreply { Vec::new() } else { reply })
+    }
+
+    /// Writes the record by the storage command.
+    async fn write(&self, conn: &Connection, store: Store, key: &[u8], value: Vec<u8>) -> Vec<u8> {
+        let pair = Pair {
+            key: key.to_vec(),
+            value,
+            namespace: String::new(),
+        };
+        let output = match store {
+            Store::Add | Store::Replace => {
+                let req = match self.request(conn, pair) {
+                    Ok(req) => req,
+                    Err(reply) => return reply,
+                };
+                let output = match store {
+                    Store::Add => self.service.insert(req).await,
+                    _ => self.service.update(req).await,
+                };
+                match output {
+                    Ok(output) => output.into_inner().into_result().map(|_| ()),
+                    Err(s) => return status(&s),
+                }
+            }
+            Store::Set => {
+                let batch = Batch {
+                    namespace: String::new(),
+                    conflict: proto::Conflict::from(Conflict::Overwrite) as i32,
+                    pairs: vec![pair],
+                };
+                let req = match self.request(conn, batch) {
+                    Ok(req) => req,
+                    Err(reply) => return reply,
+                };
+                match self.service.write_batch(req).await {
+                    Ok(written) => written.into_inner().into_result().map(|_| ()),
+                    Err(s) => return status(&s),
+                }
+            }
+        };
+        match output {
+            Ok(()) => "STORED\r\n".into(),
+            Err(rejection) => match rejection.failure {
+                // The record already has the value, as if it was replaced.
+                Failure::Identical => "STORED\r\n".into(),
+                Failure::AlreadyExists | Failure::NotFound => "NOT_STORED\r\n".into(),
+                Failure::Invalid => client_error(&rejection.info),
+                Failure::Other => server_error(&rejection.info),
+            },
+        }
+    }
+
+    /// Handles "delete <key> [noreply]".
+    async fn delete(&self, conn: &Connection, args: &[&[u8]]) -> Vec<u8> {
+        let (key, noreply) = match args {
+            [key] => (key, false),
+            [key, noreply] if *noreply == b"noreply" => (key, true),
+            _ => return client_error("bad command line format"),
+        };
+        let msg = Key {
+            key: key.to_vec(),
+            namespace: String::new(),
+        };
+        let req = match self.request(conn, msg) {
+            Ok(req) => req,
+            Err(reply) => return reply,
+        };
+        let reply = match self.service.delete(req).await {
+            Ok(output) => match output.into_inner().into_result() {
+                Ok(_) => "DELETED\r\n".into(),
+                Err(rejection) if rejection.failure == Failure::NotFound => "NOT_FOUND\r\n".into(),
+                Err(rejection) => server_error(&rejection.info),
+            },
+            Err(s) => status(&s),
+        };
+        if noreply {
+            Vec::new()
+        } else {
+            reply
+        }
+    }
+
+    /// Handles "stats": the counters of the statistics.
+    async fn stats(&self, conn: &Connection, args: &[&[u8]]) -> Vec<u8> {
+        if !args.is_empty() {
+            return client_error("only general statistics are supported");
+        }
+        if let Err(reply) = self.request(conn, ()) {
+            return reply;
+        }
+        stats(&self.stats.read().await.report()).into_bytes()
+    }
+}
+
+/// Formats the statistics: the usual memcached names, then all the counters.
+fn stats(report: &Report) -> String {
+    let total = |name| {
+        report
+            .total
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map_or(0, |(_, value)| *value)
+    };
+    let mut text = format!("STAT pid {}\r\n", std::process::id());
+    text += &format!("STAT version {}\r\n", env!("CARGO_PKG_VERSION"));
+    text += &format!("STAT curr_items {}\r\n", total("records"));
+    text += &format!("STAT get_hits {}\r\n", total("get_ok"));
+    text += &format!("STAT get_misses {}\r\n", total("get_fail"));
+    text += &format!("STAT delete_hits {}\r\n", total("delete_ok"));
+    text += &format!("STAT delete_misses {}\r\n", total("delete_fail"));
+    for (name, value) in &report.total {
+        text += &format!("STAT {} {}\r\n", name, value);
+    }
+    text += "END\r\n";
+    text
+}
+
+/// Represents the arguments of a storage command.
+pub struct StoreLine<'a> {
+    pub key: &'a [u8],
+    pub len: usize,
+    pub noreply: bool,
+}
+
+impl<'a> StoreLine<'a> {
+    /// Parses "<key> <flags> <exptime> <bytes> [noreply]". A malformed line
+    /// fails with the length of the data block following it, if it is known.
+    pub fn parse(args: &[&'a [u8]]) -> Result<Self, Option<usize>> {
+        let (key, flags, exptime, len, rest) = match args {
+            [key, flags, exptime, len, rest @ ..] => (key, flags, exptime, len, rest),
+            _ => return Err(None),
+        };
+        let len = parse::<usize>(len).ok_or(None)?;
+        let valid =
+            rest.len() <= 1 && parse::<u32>(flags).is_some() && parse::<i64>(exptime).is_some();
+        if !valid {
+            return Err(Some(len));
+        }
+        Ok(StoreLine {
+            key,
+            len,
+            noreply: matches!(rest, [option] if *option == b"noreply"),
+        })
+    }
+}
+
+/// Splits a command line into words.
+pub fn words(line: &[u8]) -> Vec<&[u8]> {
+    line.split(|&b| b == b' ')
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Skips a data block of `len` bytes followed by CRLF without keeping it.
+pub async fn skip_data<R: AsyncBufRead + Unpin>(reader: &mut R, len: usize) -> Result<(), Error> {
+    let mut block = (&mut *reader).take(len as u64 + 2);
+    tokio::io::copy(&mut block, &mut tokio::io::sink()).await?;
+    Ok(())
+}
+
+/// Reads a data block of `len` bytes followed by CRLF.
+pub async fn read_data<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    len: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut data = vec![0; len + 2];
+    reader.read_exact(&mut data).await?;
+    if !data.ends_with(b"\r\n") {
+        return Err(Error::DataChunk);
+    }
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Returns the unique value of "gets": the hash of the value.
+fn unique(value: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Converts the `gRPC` status of a failed command.
+fn status(status: &Status) -> Vec<u8> {
+    match status.code() {
+        tonic::Code::Internal | tonic::Code::Unknown => server_error(status.message()),
+        _ => client_error(status.message()),
+    }
+}
+
+fn client_error(message: &str) -> Vec<u8> {
+    format!("CLIENT_ERROR {}\r\n", message).into_bytes()
+}
+
+fn server_error(message: &str) -> Vec<u8> {
+    format!("SERVER_ERROR {}\r\n", message).into_bytes()
+}
+
+/// Represents connection errors.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Command line is too long")]
+    LineTooLong,
+    #[error("Bad data chunk")]
+    DataChunk,
+    #[error("Bad command line format")]
+    LineFormat,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<protocol::Error> for Error {
+    fn from(err: protocol::Error) -> Self {
+        match err {
+            protocol::Error::TooLong => Error::LineTooLong,
+            protocol::Error::Io(err) => Error::Io(err),
+        }
+    }
+}
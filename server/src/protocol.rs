@@ -0,0 +1,55 @@
+//! astrobase-server helpers of the line based listeners (Redis, memcached).
+
+use std::str::FromStr;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt as _, AsyncReadExt as _};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt as _, BufReader};
+
+/// Reads a line of at most `max` bytes without the line ending.
+/// Returns None at the end of the stream, dropping a line it cuts short.
+pub async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max: u64,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut line = Vec::new();
+    let n = (&mut *reader)
+        .take(max)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.last() != Some(&b'\n') {
+        if n as u64 == max {
+            return Err(Error::TooLong);
+        }
+        return Ok(None);
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+/// Parses a decimal number argument.
+pub fn parse<T: FromStr>(arg: &[u8]) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+/// Flushes the replies written so far, unless the client has already sent
+/// more commands: replies to pipelined commands are sent together.
+pub async fn flush<R: AsyncRead, W: AsyncWrite + Unpin>(
+    reader: &BufReader<R>,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    if reader.buffer().is_empty() {
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+/// Represents line reading errors.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Line is too long")]
+    TooLong,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
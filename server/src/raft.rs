@@ -0,0 +1,786 @@
+//! astrobase-server cluster mode: Raft consensus between the members.
+//!
+//! The leader appends every write to its log and sends the log to the members.
+//! An entry stored by the majority is committed and then applied to the database
+//! of every member. The term, the vote and the log are kept in files next to the
+//! database, so a restarted member (even an in-memory one) replays its log.
+//! The log is never compacted (there are no snapshots): every member keeps all
+//! of it in memory and in its file, one entry per write, and a restart replays
+//! it from the first entry, so both grow for the whole life of the cluster.
+
+use crate::auth;
+use crate::config;
+
+use astrobase_api::proto::raft_client::RaftClient;
+use astrobase_api::proto::raft_server::Raft;
+use astrobase_api::proto::{command, Command, Empty, Entry};
+use astrobase_api::proto::{AppendReply, AppendRequest, VoteReply, VoteRequest};
+use prost::Message;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::time::Instant;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+/// The most entries sent to a member in one request.
+const MAX_ENTRIES: usize = 512;
+
+/// Represents the database the committed commands are applied to.
+#[tonic::async_trait]
+pub trait StateMachine: Send + Sync + 'static {
+    type Outcome: Send + 'static;
+
+    /// Applies a committed command; every member applies the same commands in the same order.
+    async fn apply(&self, command: Command) -> Self::Outcome;
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Not the leader of the cluster, the leader is at {0}")]
+    NotLeader(String),
+    #[error("No leader of the cluster is elected")]
+    NoLeader,
+    #[error("The leadership was lost, the write may or may not be applied")]
+    LeadershipLost,
+    #[error("Cannot write the Raft state: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Represents another member of the cluster.
+struct Peer {
+    id: String,
+    client: RaftClient<Channel>,
+}
+
+/// Represents a member of the cluster.
+pub struct Node<O> {
+    id: String,
+    members: Vec<config::Member>,
+    peers: Vec<Peer>,
+    bearer: Option<MetadataValue<Ascii>>,
+    election_timeout: Duration,
+    heartbeat: Duration,
+    state: Mutex<State<O>>,
+    appended: watch::Sender<u64>, // the last index of the log, wakes the replication
+    committed: watch::Sender<u64>, // wakes the state machine
+    applied: watch::Sender<u64>,  // wakes the reads
+}
+
+struct State<O> {
+    role: Role,
+    term: u64,
+    voted_for: Option<String>,
+    leader: Option<String>,
+    log: Vec<Entry>, // the entry with index i is log[i - 1]
+    commit: u64,
+    applied: u64,
+    deadline: Instant, // of the election
+    next: Vec<u64>,    // of every peer, when the leader
+    matched: Vec<u64>, // of every peer, when the leader
+    waiters: HashMap<u64, oneshot::Sender<O>>,
+    random: u64,
+    storage: Storage,
+}
+
+impl<O> State<O> {
+    fn last_index(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    fn last_term(&self) -> u64 {
+        self.term_at(self.last_index())
+    }
+
+    fn term_at(&self, index: u64) -> u64 {
+        match index {
+            0 => 0,
+            i => self.log.get(i as usize - 1).map_or(0, |entry| entry.term),
+        }
+    }
+
+    /// Postpones the election by the timeout plus a random part up to as much,
+    /// so the members rarely start elections at the same time.
+    fn postpone(&mut self, timeout: Duration) {
+        // xorshift
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        let random = self.random % (timeout.as_millis() as u64 + 1);
+        self.deadline = Instant::now() + timeout + Duration::from_millis(random);
+    }
+
+    /// Starts following in the term, dropping the writes waiting as a leader.
+    fn step_down(&mut self, term: u64) -> io::Result<()> {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.leader = None;
+            self.storage
+                .save_vote(self.term, self.voted_for.as_deref())?;
+        }
+        if self.role == Role::Leader {
+            info!("Stepped down as the leader in term {}", self.term);
+        }
+        self.role = Role::Follower;
+        self.waiters.clear();
+        Ok(())
+    }
+}
+
+impl<O: Send + 'static> Node<O> {
+    /// Creates the member and loads its state from the files next to the database.
+    /// `durable` tells the database keeps the applied records over a restart.
+    pub fn new(cluster: &config::Cluster, db: &Path, durable: bool) -> anyhow::Result<Arc<Self>> {
+        let election_timeout = Duration::from_millis(cluster.election_timeout);
+        let heartbeat = Duration::from_millis(cluster.heartbeat);
+        let peers = cluster
+            .members
+            .iter()
+            .filter(|member| member.id != cluster.node)
+            .map(|member| {
+                let channel = Endpoint::from_shared(member.endpoint.clone())?
+                    .connect_timeout(election_timeout / 2)
+                    .connect_lazy();
+                Ok(Peer {
+                    id: member.id.clone(),
+                    client: RaftClient::new(channel),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let bearer = match &cluster.token {
+            Some(token) => Some(format!("Bearer {}", token).parse()?),
+            None => None,
+        };
+
+        let storage = Storage::new(db, durable);
+        let (term, voted_for, log, applied) = storage.load()?;
+        let applied = applied.min(log.len() as u64);
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        let seed = cluster
+            .node
+            .bytes()
+            .fold(seed, |seed, b| seed.rotate_left(5) ^ b as u64);
+        let mut state = State {
+            role: Role::Follower,
+            term,
+            voted_for,
+            leader: None,
+            log,
+            commit: applied,
+            applied,
+            deadline: Instant::now(),
+            next: vec![1; peers.len()],
+            matched: vec![0; peers.len()],
+            waiters: HashMap::new(),
+            random: seed | 1,
+            storage,
+        };
+        state.postpone(election_timeout);
+        info!(
+            "Cluster member '{}', term {}, {} log entries, {} applied",
+            cluster.node,
+            term,
+            state.log.len(),
+            applied
+        );
+
+        Ok(Arc::new(Node {
+            id: cluster.node.clone(),
+            members: cluster.members.clone(),
+            peers,
+            bearer,
+            election_timeout,
+            heartbeat,
+            appended: watch::channel(state.last_index()).0,
+            committed: watch::channel(applied).0,
+            applied: watch::channel(applied).0,
+            state: Mutex::new(state),
+        }))
+    }
+
+    /// Starts the elections, the replication to the peers and applying the
+    /// committed commands, until the server stops.
+    pub fn start<M>(self: &Arc<Self>, machine: Arc<M>, stopping: watch::Receiver<()>)
+    where
+        M: StateMachine<Outcome = O>,
+    {
+        tokio::spawn(self.clone().elect(stopping.clone()));
+        for i in 0..self.peers.len() {
+            tokio::spawn(self.clone().replicate_to(i, stopping.clone()));
+        }
+        tokio::spawn(self.clone().apply(machine, stopping));
+    }
+
+    /// Waits until the member knows the leader and has applied the entries
+    /// committed by then; a leader waits for an entry of its term to commit.
+    pub async fn ready(&self) {
+        let mut applied = self.applied.subscribe();
+        loop {
+            let commit = {
+                let state = self.state.lock().await;
+                let known = match state.role {
+                    Role::Leader => state.term_at(state.commit) == state.term,
+                    _ => state.leader.is_some(),
+                };
+                known.then(|| state.commit)
+            };
+            if let Some(commit) = commit {
+                if *applied.borrow_and_update() >= commit {
+                    return;
+                }
+            }
+            tokio::select! {
+                _ = applied.changed() => {}
+                _ = tokio::time::sleep(self.heartbeat) => {}
+            }
+        }
+    }
+
+    /// Appends the command to the log and waits until it is applied.
+    pub async fn propose(&self, command: command::Op) -> Result<O, Error> {
+        let outcome = {
+            let mut state = self.state.lock().await;
+            if state.role != Role::Leader {
+                return Err(self.not_leader(&state));
+            }
+            let entry = Entry {
+                term: state.term,
+                index: state.last_index() + 1,
+                command: Some(Command { op: Some(command) }),
+            };
+            let index = entry.index;
+            state.storage.append(std::slice::from_ref(&entry))?;
+            state.log.push(entry);
+            let (sender, receiver) = oneshot::channel();
+            state.waiters.insert(index, sender);
+            self.advance_commit(&mut state);
+            self.appended.send_replace(index);
+            receiver
+        };
+        outcome.await.map_err(|_| Error::LeadershipLost)
+    }
+
+    /// Waits until the database has every write committed before the call,
+    /// making sure this member is still the leader (the ReadIndex of Raft).
+    pub async fn read_barrier(self: &Arc<Self>) -> Result<(), Error> {
+        let mut committed = self.committed.subscribe();
+        let deadline = Instant::now() + self.election_timeout;
+        // The commit index is known once an entry of the current term is committed.
+        let index = loop {
+            {
+                let state = self.state.lock().await;
+                if state.role != Role::Leader {
+                    return Err(self.not_leader(&state));
+                }
+                if state.term_at(state.commit) == state.term {
+                    break state.commit;
+                }
+            }
+            match tokio::time::timeout_at(deadline, committed.changed()).await {
+                Ok(Ok(())) => {}
+                _ => return Err(Error::NoLeader),
+            }
+        };
+        if !self.confirm_leadership().await {
+            return Err(Error::LeadershipLost);
+        }
+        let mut applied = self.applied.subscribe();
+        while *applied.borrow_and_update() < index {
+            if applied.changed().await.is_err() {
+                return Err(Error::NoLeader);
+            }
+        }
+        Ok(())
+    }
+
+    fn not_leader(&self, state: &State<O>) -> Error {
+        let leader = state.leader.as_ref().and_then(|leader| {
+            let member = self.members.iter().find(|member| &member.id == leader)?;
+            Some(member.endpoint.clone())
+        });
+        match leader {
+            Some(endpoint) => Error::NotLeader(endpoint),
+            None => Error::NoLeader,
+        }
+    }
+
+    fn request<T>(&self, msg: T) -> Request<T> {
+        let mut req = Request::new(msg);
+        if let Some(bearer) = &self.bearer {
+            req.metadata_mut().insert("authorization", bearer.clone());
+        }
+        req
+    }
+
+    /// Starts an election whenever the leader is not heard from in time.
+    async fn elect(self: Arc<Self>, mut stopping: watch::Receiver<()>) {
+        loop {
+            let deadline = self.state.lock().await.deadline;
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => {}
+                _ = stopping.changed() => return,
+            }
+            if let Err(err) = self.campaign().await {
+                error!("Election failed: {}", err);
+            }
+        }
+    }
+
+    async fn campaign(&self) -> io::Result<()> {
+        let req = {
+            let mut state = self.state.lock().await;
+            if state.role == Role::Leader || Instant::now() < state.deadline {
+                state.postpone(self.election_timeout);
+                return Ok(());
+            }
+            state.role = Role::Candidate;
+            state.term += 1;
+            state.voted_for = Some(self.id.clone());
+            state.leader = None;
+            state
+                .storage
+                .save_vote(state.term, state.voted_for.as_deref())?;
+            state.postpone(self.election_timeout);
+            info!("Starting the election of term {}", state.term);
+            if self.is_majority(1) {
+                return self.lead(&mut state);
+            }
+            VoteRequest {
+                term: state.term,
+                candidate: self.id.clone(),
+                last_log_index: state.last_index(),
+                last_log_term: state.last_term(),
+            }
+        };
+
+        let (sender, mut replies) = mpsc::channel(self.peers.len());
+        for peer in &self.peers {
+            let mut client = peer.client.clone();
+            let req = self.request(req.clone());
+            let sender = sender.clone();
+            let timeout = self.election_timeout / 2;
+            tokio::spawn(async move {
+                if let Ok(Ok(reply)) = tokio::time::timeout(timeout, client.request_vote(req)).await
+                {
+                    let _ = sender.send(reply.into_inner()).await;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut votes = 1;
+        while let Some(reply) = replies.recv().await {
+            let mut state = self.state.lock().await;
+            if reply.term > state.term {
+                return state.step_down(reply.term);
+            }
+            if state.role != Role::Candidate || state.term != req.term {
+                return Ok(());
+            }
+            if reply.granted {
+                votes += 1;
+                if self.is_majority(votes) {
+                    return self.lead(&mut state);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_majority(&self, count: usize) -> bool {
+        count * 2 > self.members.len()
+    }
+
+    /// Becomes the leader and appends an empty entry, committing which
+    /// commits the entries of the previous terms too.
+    fn lead(&self, state: &mut State<O>) -> io::Result<()> {
+        info!("Elected the leader of term {}", state.term);
+        state.role = Role::Leader;
+        state.leader = Some(self.id.clone());
+        state.next = vec![state.last_index() + 1; self.peers.len()];
+        state.matched = vec![0; self.peers.len()];
+        let entry = Entry {
+            term: state.term,
+            index: state.last_index() + 1,
+            command: Some(Command {
+                op: Some(command::Op::Noop(Empty {})),
+            }),
+        };
+        state.storage.append(std::slice::from_ref(&entry))?;
+        state.log.push(entry);
+        self.advance_commit(state);
+        self.appended.send_replace(state.last_index());
+        Ok(())
+    }
+
+    /// Commits the entries of the current term the majority has.
+    fn advance_commit(&self, state: &mut State<O>) {
+        let mut indexes = state.matched.clone();
+        indexes.push(state.last_index());
+        indexes.sort_unstable_by(|a, b| b.cmp(a));
+        let index = indexes[self.members.len() / 2];
+        if index > state.commit && state.term_at(index) == state.term {
+            state.commit = index;
+            self.committed.send_replace(index);
+        }
+    }
+
+    /// Sends the log to a peer while this member is the leader: the new
+    /// entries as soon as they are appended, otherwise a heartbeat.
+    async fn replicate_to(self: Arc<Self>, i: usize, mut stopping: watch::Receiver<()>) {
+        let mut appended = self.appended.subscribe();
+        loop {
+            if let Some(true) = self.replicate(i).await {
+                continue; // more entries to send
+            }
+            tokio::select! {
+                _ = appended.changed() => {}
+                _ = tokio::time::sleep(self.heartbeat) => {}
+                _ = stopping.changed() => return,
+            }
+        }
+    }
+
+    /// Sends the entries the peer is missing. Returns None if the peer did not
+    /// acknowledge this member as the leader, otherwise whether entries remain.
+    async fn replicate(&self, i: usize) -> Option<bool> {
+        let req = {
+            let state = self.state.lock().await;
+            if state.role != Role::Leader {
+                return None;
+            }
+            let prev = state.next[i] - 1;
+            AppendRequest {
+                term: state.term,
+                leader: self.id.clone(),
+                prev_log_index: prev,
+                prev_log_term: state.term_at(prev),
+                entries: state.log[prev as usize..]
+                    .iter()
+                    .take(MAX_ENTRIES)
+                    .cloned()
+                    .collect(),
+                commit: state.commit,
+            }
+        };
+        let (term, prev, sent) = (req.term, req.prev_log_index, req.entries.len() as u64);
+        let mut client = self.peers[i].client.clone();
+        let call = client.append_entries(self.request(req));
+        let reply = match tokio::time::timeout(self.election_timeout / 2, call).await {
+            Ok(Ok(reply)) => reply.into_inner(),
+            _ => return None,
+        };
+
+        let mut state = self.state.lock().await;
+        if reply.term > state.term {
+            if let Err(err) = state.step_down(reply.term) {
+                error!("Cannot step down: {}", err);
+            }
+            return None;
+        }
+        if state.role != Role::Leader || state.term != term {
+            return None;
+        }
+        if reply.success {
+            let matched = prev + sent;
+            state.matched[i] = state.matched[i].max(matched);
+            state.next[i] = state.next[i].max(matched + 1);
+            self.advance_commit(&mut state);
+        } else {
+            let next = state.next[i].saturating_sub(1).min(reply.match_index + 1);
+            state.next[i] = next.max(1);
+            info!(
+                "Member '{}' misses entries, resending from {}",
+                self.peers[i].id, state.next[i]
+            );
+        }
+        Some(state.next[i] <= state.last_index())
+    }
+
+    /// Checks the majority still follows this member.
+    async fn confirm_leadership(self: &Arc<Self>) -> bool {
+        let (sender, mut replies) = mpsc::channel(self.peers.len().max(1));
+        for i in 0..self.peers.len() {
+            let node = self.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let _ = sender.send(node.replicate(i).await.is_some()).await;
+            });
+        }
+        drop(sender);
+        let mut acks = 1;
+        while !self.is_majority(acks) {
+            match replies.recv().await {
+                Some(true) => acks += 1,
+                Some(false) => {}
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Applies the committed entries to the state machine and answers the writes waiting.
+    async fn apply<M>(self: Arc<Self>, machine: Arc<M>, mut stopping: watch::Receiver<()>)
+    where
+        M: StateMachine<Outcome = O>,
+    {
+        let mut committed = self.committed.subscribe();
+        loop {
+            loop {
+                let entry = {
+                    let state = self.state.lock().await;
+                    if state.applied >= state.commit {
+                        break;
+                    }
+                    state.log[state.applied as usize].clone()
+                };
+                let outcome = match entry.command {
+                    Some(command) => Some(machine.apply(command).await),
+                    None => None,
+                };
+                let mut state = self.state.lock().await;
+                state.applied = entry.index;
+                if let Err(err) = state.storage.save_applied(entry.index) {
+                    error!("Cannot save the applied index: {}", err);
+                }
+                if let (Some(waiter), Some(outcome)) = (state.waiters.remove(&entry.index), outcome)
+                {
+                    let _ = waiter.send(outcome);
+                }
+                self.applied.send_replace(entry.index);
+            }
+            tokio::select! {
+                changed = committed.changed() => if changed.is_err() { return },
+                _ = stopping.changed() => return,
+            }
+        }
+    }
+}
+
+fn internal(err: io::Error) -> Status {
+    Status::internal(Error::Io(err).to_string())
+}
+
+#[tonic::async_trait]
+impl<O: Send + 'static> Raft for Node<O> {
+    /// Handles "RequestVote" of a candidate.
+    async fn request_vote(&self, req: Request<VoteRequest>) -> Result<Response<VoteReply>, Status> {
+        auth::authorize_admin(&req).map_err(|err| Status::permission_denied(err.to_string()))?;
+        let req = req.into_inner();
+        let mut state = self.state.lock().await;
+        if req.term > state.term {
+            state.step_down(req.term).map_err(internal)?;
+        }
+        let up_to_date =
+            (req.last_log_term, req.last_log_index) >= (state.last_term(), state.last_index());
+        let free = state.voted_for.is_none() || state.voted_for.as_ref() == Some(&req.candidate);
+        let granted = req.term == state.term && up_to_date && free;
+        if granted {
+            state.voted_for = Some(req.candidate);
+            state
+                .storage
+                .save_vote(state.term, state.voted_for.as_deref())
+                .map_err(internal)?;
+            state.postpone(self.election_timeout);
+        }
+        Ok(Response::new(VoteReply {
+            term: state.term,
+            granted,
+        }))
+    }
+
+    /// Handles "AppendEntries" of the leader: the entries after the previous one
+    /// replace the conflicting ones and the commit index follows the leader.
+    async fn append_entries(
+        &self,
+        req: Request<AppendRequest>,
+    ) -> Result<Response<AppendReply>, Status> {
+        auth::authorize_admin(&req).map_err(|err| Status::permission_denied(err.to_string()))?;
+        let req = req.into_inner();
+        let mut state = self.state.lock().await;
+        let reply = |state: &State<O>, success, match_index| AppendReply {
+            term: state.term,
+            success,
+            match_index,
+        };
+        if req.term < state.term {
+            return Ok(Response::new(reply(&state, false, state.last_index())));
+        }
+        if req.term > state.term || state.role != Role::Follower {
+            state.step_down(req.term).map_err(internal)?;
+        }
+        if state.leader.as_ref() != Some(&req.leader) {
+            info!("Following the leader '{}' of term {}", req.leader, req.term);
+            state.leader = Some(req.leader.clone());
+        }
+        state.postpone(self.election_timeout);
+
+        if req.prev_log_index > state.last_index()
+            || state.term_at(req.prev_log_index) != req.prev_log_term
+        {
+            let hint = state.last_index().min(req.prev_log_index.saturating_sub(1));
+            return Ok(Response::new(reply(&state, false, hint)));
+        }
+        let last = req.prev_log_index + req.entries.len() as u64;
+        let mut appended = Vec::new();
+        let mut truncated = false;
+        for entry in req.entries {
+            if entry.index <= state.last_index() {
+                if state.term_at(entry.index) == entry.term {
+                    continue;
+                }
+                state.log.truncate(entry.index as usize - 1);
+                truncated = true;
+            }
+            appended.push(entry.clone());
+            state.log.push(entry);
+        }
+        if truncated {
+            state.storage.rewrite(&state.log).map_err(internal)?;
+        } else if !appended.is_empty() {
+            state.storage.append(&appended).map_err(internal)?;
+        }
+        let commit = req.commit.min(last);
+        if commit > state.commit {
+            state.commit = commit;
+            self.committed.send_replace(commit);
+        }
+        Ok(Response::new(reply(&state, true, last)))
+    }
+}
+
+/// Represents the files of a member: the term and the vote, the log and, if the
+/// database keeps its records, the index of the last entry applied to it.
+struct Storage {
+    vote: PathBuf,
+    log: PathBuf,
+    applied: Option<PathBuf>,
+}
+
+impl Storage {
+    fn new(db: &Path, durable: bool) -> Self {
+        Storage {
+            vote: db.with_extension("raft"),
+            log: db.with_extension("raftlog"),
+            applied: durable.then(|| db.with_extension("applied")),
+        }
+    }
+
+    /// Loads the term, the vote, the log and the applied index; a log cut short
+    /// by a crash is truncated to the entries written in full.
+    fn load(&self) -> io::Result<(u64, Option<String>, Vec<Entry>, u64)> {
+        let (term, voted_for) = match read(&self.vote)? {
+            Some(text) => {
+                let text = String::from_utf8_lossy(&text);
+                let (term, vote) = text
+                    .trim_end_matches('\n')
+                    .split_once(' ')
+                    .unwrap_or(("0", ""));
+                let vote = (!vote.is_empty()).then(|| vote.to_owned());
+                (term.parse().map_err(|_| broken(&self.vote))?, vote)
+            }
+            None => (0, None),
+        };
+
+        let mut log = Vec::new();
+        if let Some(bytes) = read(&self.log)? {
+            let mut buf = bytes.as_slice();
+            while !buf.is_empty() {
+                match Entry::decode_length_delimited(&mut buf) {
+                    Ok(entry) => log.push(entry),
+                    Err(_) => {
+                        self.rewrite(&log)?;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let applied = match &self.applied {
+            Some(file) => match read(file)? {
+                Some(text) => String::from_utf8_lossy(&text)
+                    .trim()
+                    .parse()
+                    .map_err(|_| broken(file))?,
+                None => 0,
+            },
+            None => 0,
+        };
+        Ok((term, voted_for, log, applied))
+    }
+
+    fn save_vote(&self, term: u64, voted_for: Option<&str>) -> io::Result<()> {
+        let text = format!("{} {}\n", term, voted_for.unwrap_or(""));
+        replace(&self.vote, text.as_bytes())
+    }
+
+    fn append(&self, entries: &[Entry]) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log)?;
+        file.write_all(&encode(entries))?;
+        file.sync_data()
+    }
+
+    fn rewrite(&self, log: &[Entry]) -> io::Result<()> {
+        replace(&self.log, &encode(log))
+    }
+
+    fn save_applied(&self, index: u64) -> io::Result<()> {
+        match &self.applied {
+            Some(file) => replace(file, format!("{}\n", index).as_bytes()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn encode(entries: &[Entry]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for entry in entries {
+        entry.encode_length_delimited(&mut buf).expect("Vec grows");
+    }
+    buf
+}
+
+/// Reads a file, None if it does not exist.
+fn read(file: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(file) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Replaces the content of a file at once, so a crash leaves the old or the new one.
+pub fn replace(file: &Path, content: &[u8]) -> io::Result<()> {
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut out = fs::File::create(&tmp)?;
+    out.write_all(content)?;
+    out.sync_data()?;
+    fs::rename(tmp, file)
+}
+
+fn broken(file: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("'{}' is broken", file.display()),
+    )
+}
@@ -0,0 +1,175 @@
+//! astrobase-server reflection (`grpc.reflection.v1alpha.ServerReflection`).
+
+use astrobase_api::reflection::server_reflection_request::MessageRequest;
+use astrobase_api::reflection::server_reflection_response::MessageResponse;
+use astrobase_api::reflection::{
+    server_reflection_server, ErrorResponse, FileDescriptorResponse, ListServiceResponse,
+    ServerReflectionRequest, ServerReflectionResponse, ServiceResponse,
+};
+use prost::Message as _;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::{Stream, StreamExt as _};
+use tonic::{Code, Request, Response, Status, Streaming};
+
+/// Represents the reflection service describing the given services.
+pub struct Reflection {
+    index: Arc<Index>,
+}
+
+impl Reflection {
+    /// Creates the service from the descriptors shipped with the API.
+    pub fn new(
+        services: &[&str],
+    ) -> anyhow::Result<server_reflection_server::ServerReflectionServer<Self>> {
+        let set = FileDescriptorSet::decode(astrobase_api::FILE_DESCRIPTOR_SET)?;
+        let index = Index::new(set, services);
+        Ok(server_reflection_server::ServerReflectionServer::new(
+            Reflection {
+                index: Arc::new(index),
+            },
+        ))
+    }
+}
+
+type InfoStream = Pin<Box<dyn Stream<Item = Result<ServerReflectionResponse, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl server_reflection_server::ServerReflection for Reflection {
+    type ServerReflectionInfoStream = InfoStream;
+
+    /// Handles "ServerReflectionInfo": answers every request of the stream.
+    #[allow(clippy::result_large_err)] // a failed request passes its status on
+    async fn server_reflection_info(
+        &self,
+        req: Request<Streaming<ServerReflectionRequest>>,
+    ) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+        let index = self.index.clone();
+        let answers = req
+            .into_inner()
+            .map(move |request| request.map(|request| index.answer(request)));
+        Ok(Response::new(Box::pin(answers)))
+    }
+}
+
+/// Represents the descriptors looked up by file name and by symbol.
+struct Index {
+    services: Vec<String>,
+    files: HashMap<String, FileDescriptorProto>,
+    symbols: HashMap<String, String>, // fully qualified name -> file name
+}
+
+impl Index {
+    fn new(set: FileDescriptorSet, services: &[&str]) -> Self {
+        let mut files = HashMap::new();
+        let mut symbols = HashMap::new();
+        for file in set.file {
+            let name = file.name().to_owned();
+            let package = file.package();
+            for service in &file.service {
+                let service_name = qualified(package, service.name());
+                for method in &service.method {
+                    symbols.insert(qualified(&service_name, method.name()), name.clone());
+                }
+                symbols.insert(service_name, name.clone());
+            }
+            for message in &file.message_type {
+                index_message(package, message, &name, &mut symbols);
+            }
+            for enumeration in &file.enum_type {
+                symbols.insert(qualified(package, enumeration.name()), name.clone());
+            }
+            files.insert(name, file);
+        }
+        Index {
+            services: services.iter().map(|&service| service.to_owned()).collect(),
+            files,
+            symbols,
+        }
+    }
+
+    /// Constructs the reply to one request of the stream.
+    fn answer(&self, request: ServerReflectionRequest) -> ServerReflectionResponse {
+        let response = match &request.message_request {
+            Some(MessageRequest::ListServices(_)) => {
+                MessageResponse::ListServicesResponse(ListServiceResponse {
+                    service: self
+                        .services
+                        .iter()
+                        .map(|name| ServiceResponse { name: name.clone() })
+                        .collect(),
+                })
+            }
+            Some(MessageRequest::FileByFilename(name)) => self.file(name),
+            Some(MessageRequest::FileContainingSymbol(symbol)) => {
+                match self.symbols.get(symbol.trim_start_matches('.')) {
+                    Some(name) => self.file(name),
+                    None => error(Code::NotFound, format!("Unknown symbol '{}'", symbol)),
+                }
+            }
+            Some(_) => error(Code::Unimplemented, "Extensions are not used".into()),
+            None => error(Code::InvalidArgument, "Empty request".into()),
+        };
+        ServerReflectionResponse {
+            valid_host: request.host.clone(),
+            original_request: Some(request),
+            message_response: Some(response),
+        }
+    }
+
+    /// Returns the file with all the files it depends on.
+    fn file(&self, name: &str) -> MessageResponse {
+        let mut names = vec![name.to_owned()];
+        let mut seen = HashSet::new();
+        let mut encoded = Vec::new();
+        while let Some(name) = names.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let file = match self.files.get(&name) {
+                Some(file) => file,
+                None => return error(Code::NotFound, format!("Unknown file '{}'", name)),
+            };
+            encoded.push(file.encode_to_vec());
+            names.extend(file.dependency.iter().cloned());
+        }
+        MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+            file_descriptor_proto: encoded,
+        })
+    }
+}
+
+/// Registers the message, its nested messages and enums.
+fn index_message(
+    scope: &str,
+    message: &DescriptorProto,
+    file: &str,
+    symbols: &mut HashMap<String, String>,
+) {
+    let name = qualified(scope, message.name());
+    for nested in &message.nested_type {
+        index_message(&name, nested, file, symbols);
+    }
+    for enumeration in &message.enum_type {
+        symbols.insert(qualified(&name, enumeration.name()), file.to_owned());
+    }
+    symbols.insert(name, file.to_owned());
+}
+
+/// Returns the fully qualified name of the symbol in the scope.
+fn qualified(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        return name.to_owned();
+    }
+    format!("{}.{}", scope, name)
+}
+
+/// Constructs the reply to a request which cannot be answered.
+fn error(code: Code, message: String) -> MessageResponse {
+    MessageResponse::ErrorResponse(ErrorResponse {
+        error_code: code as i32,
+        error_message: message,
+    })
+}
@@ -0,0 +1,62 @@
+//! astrobase-server live configuration reload.
+
+use crate::{config, logger};
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+/// Represents the config shared between the service and background tasks.
+pub type SharedConfig = Arc<RwLock<config::Astrobase>>;
+
+/// Re-reads the config file and applies the changes which are safe to apply live.
+pub struct Reloader {
+    filename: PathBuf,
+    cfg: SharedConfig,
+    logger: logger::Handle,
+}
+
+impl Reloader {
+    pub fn new(filename: PathBuf, cfg: SharedConfig, logger: logger::Handle) -> Self {
+        Reloader {
+            filename,
+            cfg,
+            logger,
+        }
+    }
+
+    /// Loads the config file; rejects it entirely if an unsafe section was changed.
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let new = config::Astrobase::load(&self.filename)?;
+        let mut cfg = self.cfg.write().await;
+        cfg.ensure_reloadable(&new)?;
+
+        if new.logging != cfg.logging {
+            // Without a level in the file the logger returns to the default one.
+            self.logger.set_level(new.logging.level.as_deref())?;
+            if let Some(level) = self.logger.level() {
+                info!("Log level set to '{}'", level);
+            }
+        }
+
+        *cfg = new;
+        info!("Config reloaded from '{}'", self.filename.display());
+        Ok(())
+    }
+}
+
+/// Launches additional task which reloads the config on SIGHUP.
+pub fn start_listening(reloader: Arc<Reloader>) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if let Err(err) = reloader.reload().await {
+                error!("Config reload rejected: {:#}", err);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,414 @@
+//! astrobase-server leader/follower replication.
+//!
+//! The leader records every change written to its database in a journal.
+//! A follower streams the changes after its position, or a snapshot if the
+//! leader no longer keeps them, and applies them to its own database.
+//! Changes are applied idempotently, so repeating some after a reconnect is harmless.
+
+use crate::config;
+use crate::database::{self, Database, DEFAULT_NAMESPACE};
+use crate::raft::replace;
+use crate::server::read_pem;
+
+use astrobase_api::proto::change::Op;
+use astrobase_api::proto::replication_client::ReplicationClient;
+use astrobase_api::proto::{Batch, Change, Conflict, Empty, Namespace, Pair, Position};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, watch, Mutex, MutexGuard};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Request, Status};
+use tracing::{info, warn};
+
+/// How many changes are buffered for a follower which reads them slowly.
+const FEED_BUFFER: usize = 64;
+/// The size of the batches a snapshot is sent in.
+const SNAPSHOT_BATCH: usize = 1024 * 1024;
+/// How many records a snapshot reads from the database at once.
+const SNAPSHOT_CHUNK: usize = 1024;
+/// The delay before a follower reconnects to the leader.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Represents the journal of the recent changes of the leader.
+pub struct Journal {
+    epoch: u64,
+    log: Mutex<Log>,
+    head: watch::Receiver<u64>,
+}
+
+impl Journal {
+    /// Creates the journal keeping up to `capacity` changes.
+    /// The epoch is new every time, so followers of a restarted leader resync.
+    pub fn new(capacity: usize) -> Self {
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |time| time.as_nanos() as u64);
+        let (sender, head) = watch::channel(0);
+        Journal {
+            epoch,
+            log: Mutex::new(Log {
+                epoch,
+                offset: 0,
+                changes: VecDeque::new(),
+                capacity,
+                head: sender,
+            }),
+            head,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Locks the log; a write holds it until the change is recorded,
+    /// so the changes are recorded in the order they are applied.
+    pub async fn lock(&self) -> MutexGuard<'_, Log> {
+        self.log.lock().await
+    }
+}
+
+/// Represents the changes kept in the journal.
+pub struct Log {
+    epoch: u64,
+    offset: u64, // of the last change
+    changes: VecDeque<Change>,
+    capacity: usize,
+    head: watch::Sender<u64>,
+}
+
+impl Log {
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Records a change written to the database, dropping the oldest one if full.
+    pub fn record(&mut self, op: Op) {
+        self.offset += 1;
+        self.changes.push_back(Change {
+            epoch: self.epoch,
+            offset: self.offset,
+            op: Some(op),
+        });
+        if self.changes.len() > self.capacity {
+            self.changes.pop_front();
+        }
+        self.head.send_replace(self.offset);
+    }
+
+    /// Returns the changes after the offset, None if some of them are not kept.
+    pub fn since(&self, offset: u64) -> Option<Vec<Change>> {
+        let first = self.offset - self.changes.len() as u64;
+        if offset < first || offset > self.offset {
+            return None;
+        }
+        let skipped = (offset - first) as usize;
+        Some(self.changes.iter().skip(skipped).cloned().collect())
+    }
+}
+
+/// Represents the records of a namespace.
+pub type Records = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Sends a follower the records of all namespaces, read in chunks while the
+/// writes go on, and then the changes recorded since the offset, so it ends up
+/// where the leader is. Returns the offset the follower is synced at.
+pub async fn snapshot<Db: Database>(
+    db: &Db,
+    journal: &Journal,
+    offset: u64,
+    sender: &mpsc::Sender<Result<Change, Status>>,
+) -> Result<u64, Status> {
+    let epoch = journal.epoch();
+    let send = |offset, op| async move {
+        let change = Change {
+            epoch,
+            offset,
+            op: Some(op),
+        };
+        let gone = |_| Status::cancelled("The follower is gone");
+        sender.send(Ok(change)).await.map_err(gone)
+    };
+    // Nothing is a position of the follower until it is synced.
+    send(0, Op::Reset(Empty {})).await?;
+    let namespaces = db.list_namespaces().await;
+    for ns in namespaces.map_err(|err| Status::internal(err.to_string()))? {
+        if ns != DEFAULT_NAMESPACE {
+            send(0, Op::CreateNamespace(Namespace { name: ns.clone() })).await?;
+        }
+        let mut pairs = Vec::new();
+        let mut size = 0;
+        let mut after: Option<Vec<u8>> = None;
+        loop {
+            let chunk = match db.scan(&ns, b"", after.as_deref(), SNAPSHOT_CHUNK).await {
+                Ok(chunk) => chunk,
+                // Dropped meanwhile, so it is by the changes sent after the records.
+                Err(database::Error::NamespaceMissing(_)) => break,
+                Err(err) => return Err(Status::internal(err.to_string())),
+            };
+            let last = chunk.len() < SNAPSHOT_CHUNK;
+            after = chunk.last().map(|(key, _)| key.clone());
+            for (key, value) in chunk {
+                size += key.len() + value.len();
+                pairs.push(Pair {
+                    key,
+                    value,
+                    namespace: String::new(),
+                });
+                if size >= SNAPSHOT_BATCH {
+                    send(0, batch(&ns, std::mem::take(&mut pairs))).await?;
+                    size = 0;
+                }
+            }
+            if last {
+                break;
+            }
+        }
+        if !pairs.is_empty() {
+            send(0, batch(&ns, pairs)).await?;
+        }
+    }
+
+    // The changes written while the records were read are applied again; an
+    // insert-only batch overwrites, since some of its records may be sent already.
+    let changes = journal.lock().await.since(offset).ok_or_else(|| {
+        Status::out_of_range("The journal dropped the changes written during the snapshot")
+    })?;
+    let mut synced = offset;
+    for change in changes {
+        synced = change.offset;
+        let op = match change.op {
+            Some(Op::Batch(written)) => Op::Batch(Batch {
+                conflict: Conflict::Overwrite as i32,
+                ..written
+            }),
+            Some(op) => op,
+            None => continue,
+        };
+        send(0, op).await?;
+    }
+    send(synced, Op::Synced(Empty {})).await?;
+    Ok(synced)
+}
+
+/// Constructs a batch overwriting the records of a namespace.
+fn batch(ns: &str, pairs: Vec<Pair>) -> Op {
+    Op::Batch(Batch {
+        namespace: ns.into(),
+        conflict: Conflict::Overwrite as i32,
+        pairs,
+    })
+}
+
+pub type Feed = ReceiverStream<Result<Change, Status>>;
+
+/// Streams the backlog, or a snapshot of the database if there is none, and
+/// then the changes recorded after the offset, until the follower disconnects,
+/// falls too far behind or the server stops.
+pub fn feed<Db: Database>(
+    journal: Arc<Journal>,
+    db: Arc<Db>,
+    backlog: Option<Vec<Change>>,
+    offset: u64,
+    mut stopping: watch::Receiver<()>,
+) -> Feed {
+    let (sender, receiver) = mpsc::channel(FEED_BUFFER);
+    tokio::spawn(async move {
+        let mut head = journal.head.clone();
+        let mut offset = offset;
+        match backlog {
+            Some(changes) => {
+                for change in changes {
+                    if sender.send(Ok(change)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            None => match snapshot(&*db, &journal, offset, &sender).await {
+                Ok(synced) => offset = synced,
+                Err(status) => {
+                    let _ = sender.send(Err(status)).await;
+                    return;
+                }
+            },
+        }
+        loop {
+            let changes = journal.lock().await.since(offset);
+            let changes = match changes {
+                Some(changes) => changes,
+                None => {
+                    let status = Status::out_of_range("The follower fell behind the journal");
+                    let _ = sender.send(Err(status)).await;
+                    return;
+                }
+            };
+            for change in changes {
+                offset = change.offset;
+                if sender.send(Ok(change)).await.is_err() {
+                    return;
+                }
+            }
+            tokio::select! {
+                changed = head.changed() => if changed.is_err() { return },
+                _ = stopping.changed() => return,
+                _ = sender.closed() => return,
+            }
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+/// Represents the database of a follower.
+#[tonic::async_trait]
+pub trait Replica: Send + Sync + 'static {
+    /// Applies a change of the leader, accepting changes applied already.
+    async fn apply(&self, op: Op) -> anyhow::Result<()>;
+}
+
+/// Represents where a follower is, kept in a file if the records survive a restart.
+pub struct Tracker {
+    pub position: Position,
+    file: Option<PathBuf>,
+}
+
+impl Tracker {
+    /// Loads the position from the file, the start if it is missing or broken.
+    pub fn new(file: Option<PathBuf>) -> Self {
+        let position = file
+            .as_ref()
+            .and_then(|file| std::fs::read_to_string(file).ok())
+            .and_then(|text| {
+                let (epoch, offset) = text.trim().split_once(' ')?;
+                Some(Position {
+                    epoch: epoch.parse().ok()?,
+                    offset: offset.parse().ok()?,
+                })
+            })
+            .unwrap_or_default();
+        Tracker { position, file }
+    }
+
+    /// Moves to the position, saving it to the file first.
+    pub fn set(&mut self, position: Position) -> anyhow::Result<()> {
+        use anyhow::Context as _;
+        if let Some(file) = &self.file {
+            let text = format!("{} {}\n", position.epoch, position.offset);
+            replace(file, text.as_bytes())
+                .with_context(|| format!("Cannot write '{}'", file.display()))?;
+        }
+        self.position = position;
+        Ok(())
+    }
+}
+
+/// Makes the endpoint of the leader of a follower, over TLS if it has the CA
+/// of the leader; the follower presents its own certificate for mutual TLS.
+pub fn leader_endpoint(
+    replication: &config::Replication,
+    tls: Option<&config::Tls>,
+) -> anyhow::Result<Endpoint> {
+    let leader = replication.leader.clone().unwrap_or_default();
+    let mut endpoint = Endpoint::from_shared(leader)?;
+    if let Some(ca) = &replication.ca {
+        let mut config =
+            ClientTlsConfig::new().ca_certificate(Certificate::from_pem(read_pem(ca)?));
+        if let Some(tls) = tls {
+            config = config.identity(Identity::from_pem(
+                read_pem(&tls.cert)?,
+                read_pem(&tls.key)?,
+            ));
+        }
+        if let Some(domain) = &replication.domain {
+            config = config.domain_name(domain.clone());
+        }
+        endpoint = endpoint.tls_config(config)?;
+    }
+    Ok(endpoint)
+}
+
+/// Follows the leader until the server stops, reconnecting after failures.
+/// Tells it is ready once its records are the leader's as of some point.
+pub async fn follow<R: Replica>(
+    leader: Endpoint,
+    token: Option<String>,
+    replica: Arc<R>,
+    mut tracker: Tracker,
+    ready: watch::Sender<bool>,
+    mut stopping: watch::Receiver<()>,
+) {
+    info!("Following the leader at {}", leader.uri());
+    loop {
+        tokio::select! {
+            r = session(&leader, token.as_deref(), replica.as_ref(), &mut tracker, &ready) => {
+                if let Err(err) = r {
+                    warn!("Replication from {} failed: {:#}", leader.uri(), err);
+                }
+            }
+            _ = stopping.changed() => return,
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RETRY_DELAY) => {}
+            _ = stopping.changed() => return,
+        }
+    }
+}
+
+/// Streams the changes from the leader and applies them.
+async fn session<R: Replica>(
+    leader: &Endpoint,
+    token: Option<&str>,
+    replica: &R,
+    tracker: &mut Tracker,
+    ready: &watch::Sender<bool>,
+) -> anyhow::Result<()> {
+    let channel = leader.connect().await?;
+    let mut req = Request::new(tracker.position.clone());
+    if let Some(token) = token {
+        let bearer = format!("Bearer {}", token).parse()?;
+        req.metadata_mut().insert("authorization", bearer);
+    }
+    let mut changes = ReplicationClient::new(channel)
+        .follow(req)
+        .await?
+        .into_inner();
+    info!(
+        "Connected to the leader at {}, position {}:{}",
+        leader.uri(),
+        tracker.position.epoch,
+        tracker.position.offset
+    );
+    // The leader resumes from a position it accepts, otherwise it resets the records first.
+    if tracker.position != Position::default() {
+        ready.send_replace(true);
+    }
+
+    while let Some(change) = changes.message().await? {
+        let op = match change.op {
+            Some(op) => op,
+            None => continue,
+        };
+        if let Op::Reset(_) = op {
+            // The records are about to be dropped, the old position is useless.
+            tracker.set(Position::default())?;
+            ready.send_replace(false);
+        }
+        let synced = matches!(op, Op::Synced(_));
+        if synced {
+            info!("Synced with the leader at offset {}", change.offset);
+        }
+        replica.apply(op).await?;
+        if change.offset != 0 {
+            tracker.set(Position {
+                epoch: change.epoch,
+                offset: change.offset,
+            })?;
+        }
+        if synced {
+            ready.send_replace(true);
+        }
+    }
+    anyhow::bail!("The leader closed the stream")
+}
@@ -0,0 +1,624 @@
+//! astrobase-server Redis protocol (RESP2/RESP3) listener.
+//!
+//! Commands arrive as arrays of bulk strings or as inline lines; GET, SET,
+//! DEL, EXISTS and SCAN call the matching `gRPC` method of the service, so
+//! the insert and update semantics, limits, statistics and error messages
+//! are the ones of the API. Keys are in the default namespace, HELLO 3
+//! switches the replies of a connection to RESP3 and AUTH sets its token.
+
+use crate::auth::Authenticator;
+use crate::protocol::{self, parse};
+use crate::stats::{Report, Stats};
+
+use astrobase_api::proto::astrobase_server::Astrobase;
+use astrobase_api::proto::{self, Batch, Key, Pair, Range};
+use astrobase_api::{Conflict, Encoding, Failure, Rejection, MAX_VALUE_LEN};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::io::{BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+use tokio_stream::StreamExt as _;
+use tonic::{Code, Request, Status};
+use tracing::{debug, error, info};
+
+const MAX_INLINE_LEN: u64 = 64 * 1024;
+// A value over the limit is still read, so the service rejects it by its limits.
+const MAX_BULK_LEN: usize = MAX_VALUE_LEN + 1024;
+const MAX_ARGS: usize = 1024;
+const MAX_FRAME_LEN: usize = 2 * MAX_VALUE_LEN;
+const SCAN_COUNT: usize = 10;
+
+/// Represents the state shared by the connections.
+struct Resp<S> {
+    service: Arc<S>,
+    stats: Arc<RwLock<Stats>>,
+    authenticator: Authenticator,
+}
+
+/// Represents the state of a connection.
+struct Connection {
+    protocol: u8,
+    token: Option<Vec<u8>>,
+}
+
+/// Accepts connections until the shutdown future completes.
+pub async fn serve<S: Astrobase>(
+    address: SocketAddr,
+    service: Arc<S>,
+    stats: Arc<RwLock<Stats>>,
+    authenticator: Authenticator,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    let resp = Arc::new(Resp {
+        service,
+        stats,
+        authenticator,
+    });
+
+    info!("Redis protocol listening on {}", address);
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => return Ok(()),
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let resp = resp.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = resp.connection(stream).await {
+                            debug!("Redis connection {} closed: {}", peer, err);
+                        }
+                    });
+                }
+                Err(err) => error!("Cannot accept Redis connection: {}", err),
+            },
+        }
+    }
+}
+
+impl<S: Astrobase> Resp<S> {
+    /// Answers the commands of a connection in order until it is closed.
+    async fn connection(&self, stream: TcpStream) -> Result<(), Error> {
+        let (reader, writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+        let mut conn = Connection {
+            protocol: 2,
+            token: None,
+        };
+
+        loop {
+            let args = match read_command(&mut reader).await {
+                Ok(Some(args)) if args.is_empty() => continue,
+                Ok(Some(args)) => args,
+                Ok(None) => return Ok(()),
+                Err(Error::Protocol(message)) => {
+                    let reply = Reply::Error(format!("ERR Protocol error: {}", message));
+                    writer.write_all(&reply.encode(conn.protocol)).await?;
+                    writer.flush().await?;
+                    return Err(Error::Protocol(message));
+                }
+                Err(err) => return Err(err),
+            };
+            let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+            let reply = self.execute(&mut conn, &args).await;
+            writer.write_all(&reply.encode(conn.protocol)).await?;
+            if quit {
+                writer.flush().await?;
+                return Ok(());
+            }
+            protocol::flush(&reader, &mut writer).await?;
+        }
+    }
+
+    /// Executes a command, errors are replied as they are.
+    async fn execute(&self, conn: &mut Connection, args: &[Vec<u8>]) -> Reply {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let args = &args[1..];
+        let r = match name.as_str() {
+            "PING" => ping(args),
+            "QUIT" => Ok(Reply::ok()),
+            "COMMAND" => Ok(Reply::Array(Vec::new())),
+            "HELLO" => self.hello(conn, args),
+            "AUTH" => self.auth(conn, args),
+            "GET" => self.get(conn, args).await,
+            "SET" => self.set(conn, args).await,
+            "DEL" => self.del(conn, args).await,
+            "EXISTS" => self.exists(conn, args).await,
+            "SCAN" => self.scan(conn, args).await,
+            "INFO" => self.info(conn, args).await,
+            _ => Err(format!("ERR unknown command '{}'", name)),
+        };
+        r.unwrap_or_else(Reply::Error)
+    }
+
+    /// Constructs the authenticated `gRPC` request of the connection.
+    fn request<T>(&self, conn: &Connection, msg: T) -> Result<Request<T>, String> {
+        let authorization = conn
+            .token
+            .as_ref()
+            .map(|token| [b"Bearer ".as_ref(), token].concat());
+        self.authenticator
+            .request(authorization.as_deref(), msg)
+            .map_err(|s| status(&s))
+    }
+
+    /// Handles "HELLO [protover [AUTH username password] [SETNAME name]]".
+    fn hello(&self, conn: &mut Connection, args: &[Vec<u8>]) -> Result<Reply, String> {
+        let protocol = match args.first().map(|arg| arg.as_slice()) {
+            None => conn.protocol,
+            Some(b"2") => 2,
+            Some(b"3") => 3,
+            Some(_) => return Err("NOPROTO unsupported protocol version".into()),
+        };
+        let mut options = args.iter().skip(1);
+        while let Some(option) = options.next() {
+            if option.eq_ignore_ascii_case(b"AUTH") {
+                let credentials: Vec<Vec<u8>> = options.by_ref().take(2).cloned().collect();
+                if credentials.len() != 2 {
+                    return Err("ERR syntax error".into());
+                }
+                self.auth(conn, &credentials)?;
+            } else if option.eq_ignore_ascii_case(b"SETNAME") {
+                options.next().ok_or("ERR syntax error")?;
+            } else {
+                return Err("ERR syntax error".into());
+            }
+        }
+        conn.protocol = protocol;
+
+        let field = |name: &str, value: Reply| (Reply::bulk(name), value);
+        Ok(Reply::Map(vec![
+            field("server", Reply::bulk("astrobase")),
+            field("version", Reply::bulk(env!("CARGO_PKG_VERSION"))),
+            field("proto", Reply::Integer(protocol.into())),
+            field("mode", Reply::bulk("standalone")),
+            field("role", Reply::bulk("master")),
+            field("modules", Reply::Array(Vec::new())),
+        ]))
+    }
+
+    /// Handles "AUTH [username] password": the password is the bearer token.
+    fn auth(&self, conn: &mut Connection, args: &[Vec<u8>]) -> Result<Reply, String> {
+        arity("auth", args, 1, 2)?;
+        let token = args[args.len() - 1].clone();
+        let authorization = [b"Bearer ".as_ref(), &token].concat();
+        self.authenticator
+            .request(Some(&authorization), ())
+            .map_err(|_| "WRONGPASS invalid username-password pair".to_owned())?;
+        conn.token = Some(token);
+        Ok(Reply::ok())
+    }
+
+    /// Handles "GET key": nil if the record is missing.
+    async fn get(&self, conn: &Connection, args: &[Vec<u8>]) -> Result<Reply, String> {
+        arity("get", args, 1, 1)?;
+        match self.get_value(conn, &args[0]).await? {
+            Some(value) => Ok(Reply::Bulk(value)),
+            None => Ok(Reply::Nil),
+        }
+    }
+
+    /// Handles "SET key value [NX|XX]": NX inserts, XX updates and
+    /// the plain command writes a batch overwriting the record.
+    async fn set(&self, conn: &Connection, args: &[Vec<u8>]) -> Result<Reply, String> {
+        arity("set", args, 2, 3)?;
+        let pair = Pair {
+            key: args[0].clone(),
+            value: args[1].clone(),
+            namespace: String::new(),
+        };
+        let output = match args.get(2) {
+            Some(option) if option.eq_ignore_ascii_case(b"NX") => {
+                let req = self.request(conn, pair)?;
+                self.service.insert(req).await
+            }
+            Some(option) if option.eq_ignore_ascii_case(b"XX") => {
+                let req = self.request(conn, pair)?;
+                self.service.update(req).await
+            }
+            Some(option) => {
+                let option = String::from_utf8_lossy(option).to_ascii_uppercase();
+                return Err(format!("ERR option '{}' is not supported", option));
+            }
+            None => {
+                let batch = Batch {
+                    namespace: String::new(),
+                    conflict: proto::Conflict::from(Conflict::Overwrite) as i32,
+                    pairs: vec![pair],
+                };
+                let req = self.request(conn, batch)?;
+                let written = self.service.write_batch(req).await;
+                let written = written.map_err(|s| status(&s))?.into_inner();
+                written.into_result().map_err(|r| rejected(&r))?;
+                return Ok(Reply::ok());
+            }
+        };
+        let output = output.map_err(|s| status(&s))?.into_inner();
+        output.into_result().map_err(|r| rejected(&r))?;
+        Ok(Reply::ok())
+    }
+
+    /// Handles "DEL key [key ...]": the number of deleted records.
+    async fn del(&self, conn: &Connection, args: &[Vec<u8>]) -> Result<Reply, String> {
+        arity("del", args, 1, usize::MAX)?;
+        let mut deleted = 0;
+        for key in args {
+            let msg = Key {
+                key: key.clone(),
+                namespace: String::new(),
+            };
+            let output = self.service.delete(self.request(conn, msg)?).await;
+            match output.map_err(|s| status(&s))?.into_inner().into_result() {
+                Ok(_) => deleted += 1,
+                Err(rejection) if rejection.failure == Failure::NotFound => {}
+                Err(rejection) => return Err(rejected(&rejection)),
+            }
+        }
+        Ok(Reply::Integer(deleted))
+    }
+
+    /// Handles "EXISTS key [key ...]": the number of existing records.
+    async fn exists(&self, conn: &Connection, args: &[Vec<u8>]) -> Result<Reply, String> {
+        arity("exists", args, 1, usize::MAX)?;
+        let mut existing = 0;
+        for key in args {
+            if self.get_value(conn, key).await?.is_some() {
+                existing += 1;
+            }
+        }
+        Ok(Reply::Integer(existing))
+    }
+
+    /// Handles "SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]".
+    /// The cursor is the last key looked at in hex, COUNT keys are looked at.
+    async fn scan(&self, conn: &Connection, args: &[Vec<u8>]) -> Result<Reply, String> {
+        arity("scan", args, 1, 7)?;
+        let after = match args[0].as_slice() {
+            b"0" => Vec::new(),
+            cursor => std::str::from_utf8(cursor)
+                .ok()
+                .and_then(|cursor| Encoding::Hex.decode(cursor).ok())
+                .ok_or("ERR invalid cursor")?,
+        };
+        let mut pattern = b"*".as_ref();
+        let mut count = SCAN_COUNT;
+        let mut strings = true;
+        for option in args[1..].chunks(2) {
+            let (name, value) = match option {
+                [name, value] => (name, value),
+                _ => return Err("ERR syntax error".into()),
+            };
+            if name.eq_ignore_ascii_case(b"MATCH") {
+                pattern = value;
+            } else if name.eq_ignore_ascii_case(b"COUNT") {
+                count = parse(value)
+                    .filter(|&count| count > 0)
+                    .ok_or("ERR syntax error")?;
+            } else if name.eq_ignore_ascii_case(b"TYPE") {
+                strings = value.eq_ignore_ascii_case(b"string");
+            } else {
+                return Err("ERR syntax error".into());
+            }
+        }
+
+        let mut keys = Vec::new();
+        let mut next = String::from("0");
+        if strings {
+            let range = Range {
+                namespace: String::new(),
+                prefix: literal_prefix(pattern),
+                after,
+            };
+            let records = self.service.scan(self.request(conn, range)?).await;
+            let records = Box::pin(records.map_err(|s| status(&s))?.into_inner());
+            let mut records = records.take(count);
+            let mut seen = 0;
+            while let Some(pair) = records.next().await {
+                let pair = pair.map_err(|s| status(&s))?;
+                seen += 1;
+                if seen == count {
+                    next = Encoding::Hex.encode(&pair.key);
+                }
+                if matches(pattern, &pair.key) {
+                    keys.push(Reply::Bulk(pair.key));
+                }
+            }
+        }
+
+        Ok(Reply::Array(vec![
+            Reply::Bulk(next.into_bytes()),
+            Reply::Array(keys),
+        ]))
+    }
+
+    /// Handles "INFO [section]": the server and the statistics.
+    async fn info(&self, conn: &Connection, args: &[Vec<u8>]) -> Result<Reply, String> {
+        arity("info", args, 0, 1)?;
+        self.request(conn, ())?;
+        let section = args
+            .first()
+            .map(|arg| String::from_utf8_lossy(arg).to_ascii_lowercase());
+        let wanted = |name: &str| match section.as_deref() {
+            None | Some("all") | Some("default") | Some("everything") => true,
+            Some(section) => section == name,
+        };
+        let report = self.stats.read().await.report();
+        Ok(Reply::Bulk(info(&report, wanted).into_bytes()))
+    }
+
+    /// Reads the value of a record, None if it is missing.
+    async fn get_value(&self, conn: &Connection, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let msg = Key {
+            key: key.to_vec(),
+            namespace: String::new(),
+        };
+        let output = self.service.get(self.request(conn, msg)?).await;
+        match output.map_err(|s| status(&s))?.into_inner().into_result() {
+            Ok(value) => Ok(Some(value)),
+            Err(rejection) if rejection.failure == Failure::NotFound => Ok(None),
+            Err(rejection) => Err(rejected(&rejection)),
+        }
+    }
+}
+
+/// Handles "PING [message]".
+fn ping(args: &[Vec<u8>]) -> Result<Reply, String> {
+    arity("ping", args, 0, 1)?;
+    match args.first() {
+        Some(message) => Ok(Reply::Bulk(message.clone())),
+        None => Ok(Reply::Simple("PONG".into())),
+    }
+}
+
+/// Formats the statistics as the sections of "INFO".
+fn info(report: &Report, wanted: impl Fn(&str) -> bool) -> String {
+    let mut sections = Vec::new();
+    if wanted("server") {
+        sections.push(format!(
+            "# Server\r\nastrobase_version:{}\r\n",
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+    if wanted("stats") {
+        let mut section = "# Stats\r\n".to_owned();
+        for (name, value) in &report.total {
+            section += &format!("{}:{}\r\n", name, value);
+        }
+        sections.push(section);
+    }
+    if wanted("keyspace") {
+        let mut section = "# Keyspace\r\n".to_owned();
+        for (ns, fields) in &report.namespaces {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            section += &format!("{}:{}\r\n", ns, fields.join(","));
+        }
+        sections.push(section);
+    }
+    sections.join("\r\n")
+}
+
+/// Checks the number of arguments of a command.
+fn arity(command: &str, args: &[Vec<u8>], min: usize, max: usize) -> Result<(), String> {
+    if args.len() < min || args.len() > max {
+        return Err(format!(
+            "ERR wrong number of arguments for '{}' command",
+            command
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the part of a glob pattern before the first special character.
+fn literal_prefix(pattern: &[u8]) -> Vec<u8> {
+    pattern
+        .iter()
+        .take_while(|&&b| !matches!(b, b'*' | b'?' | b'[' | b'\\'))
+        .copied()
+        .collect()
+}
+
+/// Matches a key against a glob pattern with `*`, `?` and `\` escapes,
+/// returning to the last star on mismatch.
+pub fn matches(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    let mut star = None;
+    while k < key.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p + 1, k));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                k += 1;
+                continue;
+            }
+            Some(b'\\') if pattern.get(p + 1) == Some(&key[k]) => {
+                p += 2;
+                k += 1;
+                continue;
+            }
+            Some(&b) if b != b'\\' && b == key[k] => {
+                p += 1;
+                k += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match star {
+            Some((after, from)) => {
+                p = after;
+                k = from + 1;
+                star = Some((after, from + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Converts the `gRPC` status of a failed command.
+fn status(status: &Status) -> String {
+    let code = match status.code() {
+        Code::Unauthenticated => "NOAUTH",
+        Code::PermissionDenied => "NOPERM",
+        Code::FailedPrecondition => "READONLY",
+        _ => "ERR",
+    };
+    format!("{} {}", code, status.message())
+}
+
+/// Converts the rejection of a command keeping the message of astrobase.
+fn rejected(rejection: &Rejection) -> String {
+    let code = match rejection.failure {
+        Failure::NotFound => "NOTFOUND",
+        Failure::AlreadyExists => "EXISTS",
+        Failure::Identical => "IDENTICAL",
+        Failure::Invalid => "INVALID",
+        Failure::Other => "ERR",
+    };
+    format!("{} {}", code, rejection.info)
+}
+
+/// Represents the replies of the protocol.
+enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Nil,
+    Array(Vec<Reply>),
+    Map(Vec<(Reply, Reply)>),
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Reply::Simple("OK".into())
+    }
+
+    fn bulk(text: &str) -> Self {
+        Reply::Bulk(text.as_bytes().to_vec())
+    }
+
+    /// Encodes the reply in the protocol version of the connection.
+    fn encode(&self, protocol: u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(protocol, &mut out);
+        out
+    }
+
+    fn write(&self, protocol: u8, out: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(text) => out.extend_from_slice(format!("+{}\r\n", text).as_bytes()),
+            Reply::Error(text) => out.extend_from_slice(format!("-{}\r\n", text).as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(bytes) => {
+                out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Nil if protocol >= 3 => out.extend_from_slice(b"_\r\n"),
+            Reply::Nil => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.write(protocol, out);
+                }
+            }
+            Reply::Map(fields) => {
+                if protocol >= 3 {
+                    out.extend_from_slice(format!("%{}\r\n", fields.len()).as_bytes());
+                } else {
+                    out.extend_from_slice(format!("*{}\r\n", fields.len() * 2).as_bytes());
+                }
+                for (name, value) in fields {
+                    name.write(protocol, out);
+                    value.write(protocol, out);
+                }
+            }
+        }
+    }
+}
+
+/// Reads a command: an array of bulk strings or an inline line of words.
+/// Returns None at the end of the stream. The number and the size of the
+/// arguments are limited before they are read, as the client may not be
+/// authenticated yet.
+pub async fn read_command<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<Vec<Vec<u8>>>, Error> {
+    let line = match protocol::read_line(reader, MAX_INLINE_LEN).await? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let count = match line.strip_prefix(b"*") {
+        Some(count) => length(count, MAX_ARGS)?,
+        None => {
+            let words = line
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|word| !word.is_empty())
+                .map(<[u8]>::to_vec)
+                .collect();
+            return Ok(Some(words));
+        }
+    };
+
+    let mut args = Vec::new();
+    let mut size = 0;
+    for _ in 0..count {
+        let line = protocol::read_line(reader, MAX_INLINE_LEN)
+            .await?
+            .ok_or(Error::Protocol("unexpected end of stream"))?;
+        let len = line
+            .strip_prefix(b"$")
+            .ok_or(Error::Protocol("expected '$'"))?;
+        let len = length(len, MAX_BULK_LEN)?;
+        size += len;
+        if size > MAX_FRAME_LEN {
+            return Err(Error::Protocol("too big request"));
+        }
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).await?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(Error::Protocol("expected CRLF"));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// Parses the length of an array or a bulk string.
+fn length(text: &[u8], max: usize) -> Result<usize, Error> {
+    match parse(text) {
+        Some(len) if len <= max => Ok(len),
+        Some(_) => Err(Error::Protocol("too big request")),
+        None => Err(Error::Protocol("invalid length")),
+    }
+}
+
+/// Represents connection errors.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Protocol error: {0}")]
+    Protocol(&'static str),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<protocol::Error> for Error {
+    fn from(err: protocol::Error) -> Self {
+        match err {
+            protocol::Error::TooLong => Error::Protocol("too big inline request"),
+            protocol::Error::Io(err) => Error::Io(err),
+        }
+    }
+}
@@ -1,65 +1,564 @@
 //! astrobase-server implementation.
 
-mod api {
-    tonic::include_proto!("api");
-}
-
-use crate::stats::Stats;
-use crate::{config, database, database::Database};
+use crate::auth::{self, Authenticator};
+use crate::backup;
+use crate::config::{self, Access};
+use crate::gateway;
+use crate::health;
+use crate::limiter::RateLimiter;
+use crate::memcached;
+use crate::raft::{self, StateMachine};
+use crate::reflection::Reflection;
+use crate::reload::{Reloader, SharedConfig};
+use crate::replication::{self, Journal, Replica, Tracker};
+use crate::resp;
+use crate::stats::{Op, Stats};
+use crate::{database, database::Database, logger};
 
-use api::{astrobase_server, Key, Output, Pair};
+use astrobase_api::health::health_server::HealthServer;
+use astrobase_api::proto::change::Op as Change;
+use astrobase_api::proto::command::Op as Command;
+use astrobase_api::proto::{
+    astrobase_server, raft_server, replication_server, Batch, Chunk, Empty, Key, Moment, Namespace,
+    Namespaces, Output, Pair, Position, Range, Versions, Written,
+};
+use astrobase_api::{Consistency, Failure, Rejection, CONSISTENCY_METADATA, LEADER_METADATA};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::RwLock;
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, watch, RwLock};
+use tokio_stream::wrappers::{ReceiverStream, UnixListenerStream};
+use tonic::codegen::InterceptedService;
 use tonic::{transport, Request, Response, Status};
-use tracing::info;
+use tracing::{error, info, warn};
+
+/// The name of the API service.
+const SERVICE: &str = "api.Astrobase";
+/// The name of the replication service served by a leader.
+const REPLICATION_SERVICE: &str = "api.Replication";
+/// The name of the consensus service served by the members of a cluster.
+const RAFT_SERVICE: &str = "api.Raft";
+/// How many records "Scan" reads from the database at once.
+const SCAN_CHUNK: usize = 1024;
 
 /// Starts the server in listening mode plus task for monitoring.
-pub async fn run(cfg: config::Astrobase) -> anyhow::Result<()> {
-    use anyhow::Context as _;
+pub async fn run(
+    cfg: config::Astrobase,
+    config_file: PathBuf,
+    restore: Option<PathBuf>,
+    logger: logger::Handle,
+) -> anyhow::Result<()> {
+    logger.set_level(cfg.logging.level.as_deref())?;
+
+    let address = listener_address(cfg.server.endpoint.as_ref())?;
+    let unix = cfg.server.unix.clone();
+    let gateway_address = listener_address(cfg.gateway.as_ref().map(|gw| &gw.endpoint))?;
+    let resp_address = listener_address(cfg.resp.as_ref().map(|resp| &resp.endpoint))?;
+    let memcached_address = listener_address(cfg.memcached.as_ref().map(|mc| &mc.endpoint))?;
+    let memcached_token = cfg.memcached.as_ref().and_then(|mc| mc.token.clone());
+    let grpc = cfg.grpc.clone();
+    let tls = cfg.tls.clone();
+    let db_path = cfg.database.path.clone();
+    let replication = cfg.replication.clone();
+    let leader = replication.as_ref().and_then(|r| r.leader.clone());
+    let leader_endpoint = match &replication {
+        Some(replication) if leader.is_some() => {
+            Some(replication::leader_endpoint(replication, tls.as_ref())?)
+        }
+        _ => None,
+    };
+    let authenticator = Authenticator::new(cfg.auth.as_ref());
+    let cfg = Arc::new(RwLock::new(cfg));
+    let reloader = Arc::new(Reloader::new(config_file, cfg.clone(), logger));
+    crate::reload::start_listening(reloader.clone())?;
+    let (reporter, health) = health::Reporter::new();
+    let health = HealthServer::new(health);
+    let (stop, stopping) = watch::channel(());
+    let journal = match &replication {
+        Some(replication) if replication.leader.is_none() => {
+            Some(Arc::new(Journal::new(replication.journal)))
+        }
+        _ => None,
+    };
+    // Whether the records survive a restart, so a follower or a member resumes where it stopped.
+    let durable = cfg!(feature = "persistent");
+    let raft = match &cfg.read().await.cluster {
+        Some(cluster) => Some(raft::Node::new(cluster, &db_path, durable)?),
+        None => None,
+    };
+    let role = Role {
+        journal: journal.clone(),
+        leader: leader.clone(),
+        raft: raft.clone(),
+        stopping: stopping.clone(),
+    };
 
     #[cfg(feature = "inmemory")]
-    let service = Service::<database::InMemory>::new();
+    let service = Service::<database::InMemory>::new(cfg.clone(), reloader, role).await?;
     #[cfg(feature = "persistent")]
-    let service = Service::<database::Persistent>::new();
+    let service = Service::<database::Persistent>::new(cfg.clone(), reloader, role).await?;
+    let position_file = durable.then(|| db_path.with_extension("replica"));
+    if let Some(archive) = restore {
+        if raft.is_some() || leader.is_some() {
+            anyhow::bail!("A member of a cluster or a follower gets its records from the others");
+        }
+        let batches = backup::load(&archive)?;
+        let count = backup::restore(service.db.as_ref(), batches).await?;
+        let mut stats = service.stats.write().await;
+        for ns in service.db.list_namespaces().await? {
+            stats.create_namespace(&ns);
+            stats.records(&ns, self::count(service.db.as_ref(), &ns).await?, 0);
+        }
+        info!("Restored {} records from '{}'", count, archive.display());
+    }
+
+    let stats = service.stats.clone();
+    start_monitoring(stats.clone(), cfg);
+    let service = Arc::new(service);
+    start_merging(service.clone());
+
+    let mut services = vec![SERVICE];
+    if journal.is_some() {
+        services.push(REPLICATION_SERVICE);
+    }
+    if raft.is_some() {
+        services.push(RAFT_SERVICE);
+    }
+    let health = grpc.health.then(|| {
+        services.push("grpc.health.v1.Health");
+        health
+    });
+    let reflection = if grpc.reflection {
+        services.push("grpc.reflection.v1alpha.ServerReflection");
+        Some(Reflection::new(&services)?)
+    } else {
+        None
+    };
 
-    start_monitoring(
-        service.stats.clone(),
-        Duration::from_secs(cfg.monitoring.interval),
+    let unix_listener = unix.as_ref().map(bind_unix).transpose()?;
+
+    // A follower or a cluster member serves once it has caught up.
+    let (role_ready, ready) = watch::channel(leader.is_none() && raft.is_none());
+    tokio::spawn(async move {
+        shutdown(reporter, ready).await;
+        let _ = stop.send(());
+    });
+
+    if let Some(leader) = leader_endpoint {
+        let token = replication.and_then(|r| r.token);
+        let tracker = Tracker::new(position_file);
+        let follower = replication::follow(
+            leader,
+            token,
+            service.clone(),
+            tracker,
+            role_ready,
+            stopping.clone(),
+        );
+        tokio::spawn(follower);
+    } else if let Some(raft) = &raft {
+        raft.start(service.clone(), stopping.clone());
+        let raft = raft.clone();
+        tokio::spawn(async move {
+            raft.ready().await;
+            role_ready.send_replace(true);
+        });
+    }
+
+    let api = InterceptedService::new(
+        astrobase_server::AstrobaseServer::from_arc(service.clone()),
+        authenticator.clone(),
     );
+    let replication_api = journal.as_ref().map(|_| {
+        InterceptedService::new(
+            replication_server::ReplicationServer::from_arc(service.clone()),
+            authenticator.clone(),
+        )
+    });
+    let raft_api = raft.map(|raft| {
+        InterceptedService::new(
+            raft_server::RaftServer::from_arc(raft),
+            authenticator.clone(),
+        )
+    });
+    let router = |mut builder: transport::Server| {
+        builder
+            .add_optional_service(health.clone())
+            .add_optional_service(reflection.clone())
+            .add_optional_service(replication_api.clone())
+            .add_optional_service(raft_api.clone())
+            .add_service(api.clone())
+    };
+    let grpc = async {
+        if let Some(address) = address {
+            let mut builder = transport::Server::builder();
+            if let Some(tls) = &tls {
+                builder = builder.tls_config(tls_config(tls)?)?;
+            }
+            router(builder)
+                .serve_with_shutdown(address, stopped(stopping.clone()))
+                .await?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let grpc_unix = async {
+        if let (Some(unix), Some(listener)) = (&unix, unix_listener) {
+            info!("Listening on {}", unix.path.display());
+            let incoming = UnixListenerStream::new(listener);
+            let served = router(transport::Server::builder())
+                .serve_with_incoming_shutdown(incoming, stopped(stopping.clone()))
+                .await;
+            remove_socket(&unix.path);
+            served?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let gateway = async {
+        match gateway_address {
+            Some(address) => {
+                let stopped = stopped(stopping.clone());
+                gateway::serve(address, service.clone(), authenticator.clone(), stopped).await
+            }
+            None => Ok(()),
+        }
+    };
+    let resp = async {
+        match resp_address {
+            Some(address) => {
+                let stopped = stopped(stopping.clone());
+                let authenticator = authenticator.clone();
+                let stats = stats.clone();
+                resp::serve(address, service.clone(), stats, authenticator, stopped).await
+            }
+            None => Ok(()),
+        }
+    };
+    let memcached = async {
+        match memcached_address {
+            Some(address) => {
+                let stopped = stopped(stopping.clone());
+                let authenticator = authenticator.clone();
+                let (service, stats) = (service.clone(), stats.clone());
+                memcached::serve(
+                    address,
+                    service,
+                    stats,
+                    authenticator,
+                    memcached_token,
+                    stopped,
+                )
+                .await
+            }
+            None => Ok(()),
+        }
+    };
+
+    tokio::try_join!(grpc, grpc_unix, gateway, resp, memcached)?;
+    Ok(())
+}
+
+/// Parses the endpoint of an optional listener.
+fn listener_address(endpoint: Option<&String>) -> anyhow::Result<Option<SocketAddr>> {
+    use anyhow::Context as _;
+    endpoint
+        .map(|endpoint| endpoint.parse().context(endpoint.clone()))
+        .transpose()
+}
+
+/// Binds the Unix domain socket, replacing a stale one left by a previous run,
+/// and sets its file permissions.
+pub fn bind_unix(unix: &config::Unix) -> anyhow::Result<UnixListener> {
+    use anyhow::Context as _;
+    use std::os::unix::fs::{FileTypeExt as _, PermissionsExt as _};
+
+    let path = &unix.path;
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("'{}' exists and is not a socket", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Cannot remove stale socket '{}'", path.display()))?;
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Cannot listen on '{}'", path.display()))?;
+    if let Some(mode) = unix.mode()? {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Cannot set permissions of '{}'", path.display()))?;
+    }
+    Ok(listener)
+}
+
+/// Removes the socket file once the server stopped listening on it.
+pub fn remove_socket(path: &Path) {
+    if let Err(err) = std::fs::remove_file(path) {
+        error!("Cannot remove socket '{}': {}", path.display(), err);
+    }
+}
+
+/// Completes when the shutdown has been requested.
+async fn stopped(mut stopping: watch::Receiver<()>) {
+    let _ = stopping.changed().await;
+}
 
+/// Reports SERVING once the role is ready. Waits for SIGINT or SIGTERM, then
+/// reports NOT_SERVING for a while before the server stops accepting connections.
+async fn shutdown(reporter: health::Reporter, mut ready: watch::Receiver<bool>) {
+    let terminated = terminated();
+    tokio::pin!(terminated);
+    tokio::select! {
+        _ = &mut terminated => {}
+        _ = serve_when_ready(&reporter, &mut ready) => terminated.await,
+    }
+    info!("Shutting down");
+    reporter.not_serving();
+    tokio::time::sleep(Duration::from_secs(config::SHUTDOWN_DELAY)).await;
+}
+
+/// Reports SERVING once the role is ready; never if it cannot be.
+pub async fn serve_when_ready(reporter: &health::Reporter, ready: &mut watch::Receiver<bool>) {
+    until_ready(ready).await;
+    reporter.serving();
     info!("Ready");
-    let endpoint = cfg.server.endpoint.clone();
-    transport::Server::builder()
-        .add_service(astrobase_server::AstrobaseServer::new(service))
-        .serve(endpoint.parse().context(endpoint)?)
-        .await?;
+}
 
-    Ok(())
+/// Waits until the value turns true; forever if it never can.
+async fn until_ready(ready: &mut watch::Receiver<bool>) {
+    while !*ready.borrow_and_update() {
+        if ready.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Waits for SIGINT or SIGTERM.
+async fn terminated() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        Err(err) => {
+            error!("Cannot listen to SIGTERM: {}", err);
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+/// Loads the certificates for TLS (and mutual TLS if client CA is given).
+fn tls_config(tls: &config::Tls) -> anyhow::Result<transport::ServerTlsConfig> {
+    let cert = read_pem(&tls.cert)?;
+    let key = read_pem(&tls.key)?;
+    let mut tls_config =
+        transport::ServerTlsConfig::new().identity(transport::Identity::from_pem(cert, key));
+    if let Some(client_ca) = &tls.client_ca {
+        let ca = read_pem(client_ca)?;
+        tls_config = tls_config.client_ca_root(transport::Certificate::from_pem(ca));
+    }
+    Ok(tls_config)
+}
+
+/// Reads a PEM file.
+pub fn read_pem(filename: &Path) -> anyhow::Result<Vec<u8>> {
+    use anyhow::Context as _;
+    std::fs::read(filename).with_context(|| format!("Cannot read '{}'", filename.display()))
 }
 
 /// Launches additional task which dumps the statistics regularly.
-fn start_monitoring(stats: Arc<RwLock<Stats>>, interval: Duration) {
+/// The interval is re-read every time, so it follows config reloads.
+fn start_monitoring(stats: Arc<RwLock<Stats>>, cfg: SharedConfig) {
     tokio::spawn(async move {
         loop {
-            tokio::time::sleep(interval).await;
+            let interval = cfg.read().await.monitoring.interval;
+            tokio::time::sleep(Duration::from_secs(interval)).await;
             stats.read().await.dump();
         }
     });
 }
 
+/// Launches additional task which merges the old segments of the logs regularly.
+/// The interval and the retention are re-read every time, so they follow config reloads.
+fn start_merging<Db: Database>(service: Arc<Service<Db>>) {
+    tokio::spawn(async move {
+        loop {
+            let (interval, retention) = {
+                let cfg = service.cfg.read().await;
+                (cfg.database.merge_interval, cfg.database.retention)
+            };
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            match service.db.compact(Duration::from_secs(retention)).await {
+                Ok(0) => {}
+                Ok(removed) => info!("Merge removed {} old versions", removed),
+                Err(e) => error!("Merge failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Represents the part the server plays in replication.
+struct Role {
+    journal: Option<Arc<Journal>>,          // of a leader
+    leader: Option<String>,                 // of a follower
+    raft: Option<Arc<raft::Node<Outcome>>>, // of a cluster member
+    stopping: watch::Receiver<()>,
+}
+
 /// Represents the `gRPC` service.
 struct Service<Db: Database> {
-    db: Db,
+    db: Arc<Db>,
     stats: Arc<RwLock<Stats>>,
+    cfg: SharedConfig,
+    limiter: RateLimiter,
+    reloader: Arc<Reloader>,
+    role: Role,
+    // Writes hold it shared; a backup holds it exclusively, so it sees all namespaces at one point.
+    writes: RwLock<()>,
 }
 
 impl<Db: Database> Service<Db> {
-    fn new() -> Self {
-        Service {
-            db: Db::new(),
-            stats: Arc::new(RwLock::new(Stats::default())),
+    async fn new(cfg: SharedConfig, reloader: Arc<Reloader>, role: Role) -> anyhow::Result<Self> {
+        let database = cfg.read().await.database.clone();
+        let db = Db::open(&database);
+        let retention = Duration::from_secs(database.retention);
+        let removed = db.compact(retention).await?;
+        if removed > 0 {
+            info!("Compaction removed {} old versions", removed);
+        }
+        let mut stats = Stats::default();
+        for ns in db.list_namespaces().await? {
+            stats.create_namespace(&ns);
+            stats.records(&ns, count(&db, &ns).await?, 0);
         }
+
+        Ok(Service {
+            db: Arc::new(db),
+            stats: Arc::new(RwLock::new(stats)),
+            cfg,
+            limiter: RateLimiter::new(),
+            reloader,
+            role,
+            writes: RwLock::new(()),
+        })
+    }
+
+    /// Rejects writes to a follower, pointing the client to the leader.
+    #[allow(clippy::result_large_err)] // the status is the reply of the handlers
+    fn ensure_writable(&self) -> Result<(), Status> {
+        let leader = match &self.role.leader {
+            Some(leader) => leader,
+            None => return Ok(()),
+        };
+        Err(redirect(
+            Error::Follower(leader.clone()).to_string(),
+            leader,
+        ))
+    }
+
+    /// Runs a write through the cluster if the server is a member,
+    /// returns None otherwise.
+    async fn consensus(
+        &self,
+        command: impl FnOnce() -> Command,
+    ) -> Result<Option<Outcome>, Status> {
+        match &self.role.raft {
+            Some(raft) => raft
+                .propose(command())
+                .await
+                .map(Some)
+                .map_err(cluster_status),
+            None => Ok(None),
+        }
+    }
+
+    /// Makes a read of a cluster member see every write committed before it,
+    /// unless the client accepts a stale read.
+    async fn ensure_consistent<T>(&self, req: &Request<T>) -> Result<(), Status> {
+        let raft = match &self.role.raft {
+            Some(raft) => raft,
+            None => return Ok(()),
+        };
+        let consistency = match req.metadata().get(CONSISTENCY_METADATA) {
+            Some(value) => value
+                .to_str()
+                .map_err(|err| err.to_string())
+                .and_then(str::parse)
+                .map_err(Status::invalid_argument)?,
+            None => Consistency::default(),
+        };
+        match consistency {
+            Consistency::Linearizable => raft.read_barrier().await.map_err(cluster_status),
+            Consistency::Stale => Ok(()),
+        }
+    }
+
+    /// Runs a write, recording the change in the journal of a leader if it succeeds.
+    async fn journaled<T>(
+        &self,
+        write: impl Future<Output = database::Result<T>>,
+        change: impl FnOnce() -> Change,
+    ) -> database::Result<T> {
+        let _writing = self.writes.read().await;
+        let journal = match &self.role.journal {
+            Some(journal) => journal,
+            None => return write.await,
+        };
+        let mut log = journal.lock().await;
+        let r = write.await;
+        if r.is_ok() {
+            log.record(change());
+        }
+        r
+    }
+
+    /// Counts the records a write of this server added and removed;
+    /// the writes of a cluster are counted as they are applied.
+    async fn count(&self, ns: &str, added: u64, removed: u64) {
+        self.stats.write().await.records(ns, added, removed);
+    }
+
+    /// Checks the request against the current limits.
+    async fn admit(&self, key: &[u8], value: Option<&[u8]>) -> Result<(), Status> {
+        self.admit_all(&[(key, value)]).await
+    }
+
+    /// Checks the request carrying several records against the current limits.
+    async fn admit_all(&self, records: &[(&[u8], Option<&[u8]>)]) -> Result<(), Status> {
+        let limits = self.cfg.read().await.limits.clone();
+        if !self.limiter.admit(limits.requests_per_second) {
+            return Err(Status::resource_exhausted(Error::RateLimited.to_string()));
+        }
+        for (key, value) in records {
+            if key.len() > limits.max_key_len {
+                return Err(Status::invalid_argument(
+                    Error::KeyTooLong(key.len()).to_string(),
+                ));
+            }
+            if let Some(value) = value {
+                if value.len() > limits.max_value_len {
+                    return Err(Status::invalid_argument(
+                        Error::ValueTooLong(value.len()).to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the records of every namespace.
+    async fn records(&self) -> Result<Vec<(String, replication::Records)>, Status> {
+        let mut namespaces = Vec::new();
+        for ns in self.db.list_namespaces().await.map_err(internal)? {
+            let records = self.db.scan(&ns, b"", None, usize::MAX).await;
+            let records = records.map_err(internal)?;
+            namespaces.push((ns, records));
+        }
+        Ok(namespaces)
     }
 }
 
@@ -69,59 +568,662 @@ type CallResult = Result<Response<Output>, Status>;
 impl<Db: Database> astrobase_server::Astrobase for Service<Db> {
     /// Handles command "Get".
     async fn get(&self, req: Request<Key>) -> CallResult {
+        self.ensure_consistent(&req).await?;
+        let ns = namespace(&req.get_ref().namespace);
         let key = &req.get_ref().key;
-        let r = self.db.get(key).await;
-        let ok = r.is_ok();
-        self.stats.write().await.get(ok);
-        let info = if ok {
-            r.unwrap()
-        } else {
-            r.unwrap_err().to_string()
-        };
-        Ok(Response::new(Output { ok, info }))
+        if let Err(err) = auth::authorize(&req, ns, key, Access::Read) {
+            self.stats.write().await.deny(ns, Op::Get);
+            return Err(Status::permission_denied(err.to_string()));
+        }
+        if let Err(status) = self.admit(key, None).await {
+            self.stats.write().await.get(ns, false);
+            return Err(status);
+        }
+        let r = self.db.get(ns, key).await;
+        self.stats.write().await.get(ns, r.is_ok());
+        Ok(Response::new(output(r)))
+    }
+
+    /// Handles command "History" returning every version of a key.
+    async fn history(&self, req: Request<Key>) -> Result<Response<Versions>, Status> {
+        self.ensure_consistent(&req).await?;
+        let ns = namespace(&req.get_ref().namespace);
+        let key = &req.get_ref().key;
+        if let Err(err) = auth::authorize(&req, ns, key, Access::Read) {
+            self.stats.write().await.deny(ns, Op::Get);
+            return Err(Status::permission_denied(err.to_string()));
+        }
+        if let Err(status) = self.admit(key, None).await {
+            self.stats.write().await.get(ns, false);
+            return Err(status);
+        }
+        let r = self.db.history(ns, key).await;
+        self.stats.write().await.get(ns, r.is_ok());
+        Ok(Response::new(match r {
+            Ok(versions) => Versions::accepted(versions),
+            Err(err) => Versions::rejected(rejection(&err)),
+        }))
+    }
+
+    /// Handles command "GetAt" returning the value a key had at the moment.
+    async fn get_at(&self, req: Request<Moment>) -> CallResult {
+        self.ensure_consistent(&req).await?;
+        let ns = namespace(&req.get_ref().namespace);
+        let key = &req.get_ref().key;
+        if let Err(err) = auth::authorize(&req, ns, key, Access::Read) {
+            self.stats.write().await.deny(ns, Op::Get);
+            return Err(Status::permission_denied(err.to_string()));
+        }
+        if let Err(status) = self.admit(key, None).await {
+            self.stats.write().await.get(ns, false);
+            return Err(status);
+        }
+        let at = req
+            .get_ref()
+            .at
+            .clone()
+            .ok_or_else(|| Status::invalid_argument(Error::MomentMissing.to_string()))?;
+        let r = self.db.get_at(ns, key, at.into()).await;
+        self.stats.write().await.get(ns, r.is_ok());
+        Ok(Response::new(output(r)))
     }
 
     /// Handles command "Insert".
     async fn insert(&self, req: Request<Pair>) -> CallResult {
+        self.ensure_writable()?;
+        let ns = namespace(&req.get_ref().namespace);
         let key = &req.get_ref().key;
         let value = &req.get_ref().value;
-        let r = self.db.insert(key, value).await;
-        let ok = r.is_ok();
-        self.stats.write().await.insert(ok);
-        let info = if ok {
-            r.unwrap()
-        } else {
-            r.unwrap_err().to_string()
+        if let Err(err) = auth::authorize(&req, ns, key, Access::Write) {
+            self.stats.write().await.deny(ns, Op::Insert);
+            return Err(Status::permission_denied(err.to_string()));
+        }
+        if let Err(status) = self.admit(key, Some(value)).await {
+            self.stats.write().await.insert(ns, false);
+            return Err(status);
+        }
+        let r = match self
+            .consensus(|| Command::Insert(pair(ns, key, value)))
+            .await?
+        {
+            Some(outcome) => outcome.output(),
+            None => {
+                let change = || Change::Put(pair(ns, key, value));
+                let r = self.journaled(self.db.insert(ns, key, value), change).await;
+                if r.is_ok() {
+                    self.count(ns, 1, 0).await;
+                }
+                r
+            }
         };
-        Ok(Response::new(Output { ok, info }))
+        self.stats.write().await.insert(ns, r.is_ok());
+        Ok(Response::new(output(r)))
     }
 
     /// Handles command "Delete".
     async fn delete(&self, req: Request<Key>) -> CallResult {
+        self.ensure_writable()?;
+        let ns = namespace(&req.get_ref().namespace);
         let key = &req.get_ref().key;
-        let r = self.db.delete(key).await;
-        let ok = r.is_ok();
-        self.stats.write().await.delete(ok);
-        let info = if ok {
-            r.unwrap()
-        } else {
-            r.unwrap_err().to_string()
+        if let Err(err) = auth::authorize(&req, ns, key, Access::Write) {
+            self.stats.write().await.deny(ns, Op::Delete);
+            return Err(Status::permission_denied(err.to_string()));
+        }
+        if let Err(status) = self.admit(key, None).await {
+            self.stats.write().await.delete(ns, false);
+            return Err(status);
+        }
+        let record = || Key {
+            key: key.clone(),
+            namespace: ns.into(),
         };
-        Ok(Response::new(Output { ok, info }))
+        let r = match self.consensus(|| Command::Delete(record())).await? {
+            Some(outcome) => outcome.output(),
+            None => {
+                let change = || Change::Delete(record());
+                let r = self.journaled(self.db.delete(ns, key), change).await;
+                if r.is_ok() {
+                    self.count(ns, 0, 1).await;
+                }
+                r
+            }
+        };
+        self.stats.write().await.delete(ns, r.is_ok());
+        Ok(Response::new(output(r)))
     }
 
     /// Handles command "Update".
     async fn update(&self, req: Request<Pair>) -> CallResult {
+        self.ensure_writable()?;
+        let ns = namespace(&req.get_ref().namespace);
         let key = &req.get_ref().key;
         let value = &req.get_ref().value;
-        let r = self.db.update(key, value).await;
-        let ok = r.is_ok();
-        self.stats.write().await.update(ok);
-        let info = if ok {
-            r.unwrap()
-        } else {
-            r.unwrap_err().to_string()
+        if let Err(err) = auth::authorize(&req, ns, key, Access::Write) {
+            self.stats.write().await.deny(ns, Op::Update);
+            return Err(Status::permission_denied(err.to_string()));
+        }
+        if let Err(status) = self.admit(key, Some(value)).await {
+            self.stats.write().await.update(ns, false);
+            return Err(status);
+        }
+        let r = match self
+            .consensus(|| Command::Update(pair(ns, key, value)))
+            .await?
+        {
+            Some(outcome) => outcome.output(),
+            None => {
+                let change = || Change::Put(pair(ns, key, value));
+                self.journaled(self.db.update(ns, key, value), change).await
+            }
+        };
+        self.stats.write().await.update(ns, r.is_ok());
+        Ok(Response::new(output(r)))
+    }
+
+    /// Handles admin command "Reload".
+    async fn reload(&self, req: Request<Empty>) -> CallResult {
+        auth::authorize_admin(&req).map_err(|err| Status::permission_denied(err.to_string()))?;
+        let r = self.reloader.reload().await;
+        let output = match r {
+            Ok(()) => Output::accepted(Vec::new()),
+            Err(err) => {
+                error!("Config reload rejected: {:#}", err);
+                Output::rejected(Rejection {
+                    failure: Failure::Invalid,
+                    info: format!("{:#}", err),
+                })
+            }
+        };
+        Ok(Response::new(output))
+    }
+
+    /// Handles admin command "CreateNamespace".
+    async fn create_namespace(&self, req: Request<Namespace>) -> CallResult {
+        auth::authorize_admin(&req).map_err(|err| Status::permission_denied(err.to_string()))?;
+        self.ensure_writable()?;
+        let ns = &req.get_ref().name;
+        let r = match self
+            .consensus(|| Command::CreateNamespace(req.get_ref().clone()))
+            .await?
+        {
+            Some(outcome) => outcome.output().map(|_| ()),
+            None => {
+                let change = || Change::CreateNamespace(req.get_ref().clone());
+                self.journaled(self.db.create_namespace(ns), change).await
+            }
+        };
+        if r.is_ok() {
+            self.stats.write().await.create_namespace(ns);
+        }
+        Ok(Response::new(output(r.map(|()| Vec::new()))))
+    }
+
+    /// Handles admin command "DropNamespace".
+    async fn drop_namespace(&self, req: Request<Namespace>) -> CallResult {
+        auth::authorize_admin(&req).map_err(|err| Status::permission_denied(err.to_string()))?;
+        self.ensure_writable()?;
+        let ns = &req.get_ref().name;
+        let r = match self
+            .consensus(|| Command::DropNamespace(req.get_ref().clone()))
+            .await?
+        {
+            Some(outcome) => outcome.output().map(|_| ()),
+            None => {
+                let change = || Change::DropNamespace(req.get_ref().clone());
+                self.journaled(self.db.drop_namespace(ns), change).await
+            }
+        };
+        if r.is_ok() {
+            self.stats.write().await.drop_namespace(ns);
+        }
+        Ok(Response::new(output(r.map(|()| Vec::new()))))
+    }
+
+    /// Handles admin command "ListNamespaces".
+    async fn list_namespaces(&self, req: Request<Empty>) -> Result<Response<Namespaces>, Status> {
+        auth::authorize_admin(&req).map_err(|err| Status::permission_denied(err.to_string()))?;
+        let names = self
+            .db
+            .list_namespaces()
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?;
+        Ok(Response::new(Namespaces { names }))
+    }
+
+    type BackupStream = ReceiverStream<Result<Chunk, Status>>;
+
+    /// Handles admin command "Backup" streaming the archive of all namespaces.
+    /// The writes wait only while the records are read; the archive is encoded
+    /// and sent piece by piece after that.
+    async fn backup(&self, req: Request<Empty>) -> Result<Response<Self::BackupStream>, Status> {
+        auth::authorize_admin(&req).map_err(|err| Status::permission_denied(err.to_string()))?;
+        let writes = self.writes.write().await;
+        let namespaces = self.records().await?;
+        drop(writes);
+        let (tx, rx) = mpsc::channel(4);
+        tokio::task::spawn_blocking(move || {
+            let mut size = 0;
+            let sent = backup::encode(namespaces, |piece| -> Result<(), ()> {
+                size += piece.len();
+                for data in piece.chunks(backup::CHUNK_SIZE) {
+                    let data = data.to_vec();
+                    tx.blocking_send(Ok(Chunk { data })).map_err(|_| ())?;
+                }
+                Ok(())
+            });
+            match sent {
+                Ok(()) => info!("Sent a backup of {} bytes", size),
+                Err(_) => warn!("The client left before the backup was sent"),
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type ScanStream = ReceiverStream<Result<Pair, Status>>;
+
+    /// Handles command "Scan" streaming the records with keys starting with the prefix.
+    async fn scan(&self, req: Request<Range>) -> Result<Response<Self::ScanStream>, Status> {
+        self.ensure_consistent(&req).await?;
+        let ns = namespace(&req.get_ref().namespace);
+        let prefix = &req.get_ref().prefix;
+        if let Err(err) = auth::authorize(&req, ns, prefix, Access::Read) {
+            self.stats.write().await.deny(ns, Op::Get);
+            return Err(Status::permission_denied(err.to_string()));
+        }
+        self.admit(prefix, None).await?;
+        let after = Some(req.get_ref().after.as_slice()).filter(|after| !after.is_empty());
+        let records = self.db.scan(ns, prefix, after, SCAN_CHUNK).await;
+        let records = records.map_err(|err| match err {
+            database::Error::NamespaceMissing(_) => Status::not_found(err.to_string()),
+            _ => Status::internal(err.to_string()),
+        })?;
+
+        // The next chunks are read as the client receives the previous ones.
+        let (tx, rx) = mpsc::channel(SCAN_CHUNK);
+        let (db, ns, prefix) = (self.db.clone(), ns.to_owned(), prefix.clone());
+        tokio::spawn(async move {
+            let mut records = records;
+            while let Some((last, _)) = records.last() {
+                let after = last.clone();
+                for (key, value) in records {
+                    let pair = Pair {
+                        key,
+                        value,
+                        namespace: ns.clone(),
+                    };
+                    if tx.send(Ok(pair)).await.is_err() {
+                        return;
+                    }
+                }
+                records = match db.scan(&ns, &prefix, Some(&after), SCAN_CHUNK).await {
+                    Ok(records) => records,
+                    Err(err) => {
+                        let _ = tx.send(Err(Status::internal(err.to_string()))).await;
+                        return;
+                    }
+                };
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// Handles command "WriteBatch".
+    async fn write_batch(&self, req: Request<Batch>) -> Result<Response<Written>, Status> {
+        self.ensure_writable()?;
+        let batch = req.get_ref();
+        let ns = namespace(&batch.namespace);
+        let conflict = database::Conflict::from_proto(batch.conflict).ok_or_else(|| {
+            Status::invalid_argument(Error::ConflictInvalid(batch.conflict).to_string())
+        })?;
+        for pair in &batch.pairs {
+            if let Err(err) = auth::authorize(&req, ns, &pair.key, Access::Write) {
+                self.stats.write().await.deny(ns, Op::Insert);
+                return Err(Status::permission_denied(err.to_string()));
+            }
+        }
+        let records: Vec<(&[u8], Option<&[u8]>)> = batch
+            .pairs
+            .iter()
+            .map(|pair| (pair.key.as_slice(), Some(pair.value.as_slice())))
+            .collect();
+        if let Err(status) = self.admit_all(&records).await {
+            self.stats.write().await.insert(ns, false);
+            return Err(status);
+        }
+
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = batch
+            .pairs
+            .iter()
+            .map(|pair| (pair.key.clone(), pair.value.clone()))
+            .collect();
+        let normalized = || Batch {
+            namespace: ns.into(),
+            ..batch.clone()
+        };
+        let r = match self.consensus(|| Command::Batch(normalized())).await? {
+            Some(outcome) => outcome.written(),
+            None => {
+                let change = || Change::Batch(normalized());
+                let r = self
+                    .journaled(self.db.write_batch(ns, &pairs, conflict), change)
+                    .await;
+                if let Ok(written) = &r {
+                    self.count(ns, written.inserted, 0).await;
+                }
+                r
+            }
         };
-        Ok(Response::new(Output { ok, info }))
+        let written = match r {
+            Ok(written) => {
+                let mut stats = self.stats.write().await;
+                stats.write_batch(ns, written.inserted, written.updated);
+                Written::accepted(written)
+            }
+            Err(err) => {
+                self.stats.write().await.insert(ns, false);
+                Written::rejected(rejection(&err))
+            }
+        };
+        Ok(Response::new(written))
     }
 }
+
+#[tonic::async_trait]
+impl<Db: Database> replication_server::Replication for Service<Db> {
+    type FollowStream = replication::Feed;
+
+    /// Handles admin command "Follow": streams the changes after the position
+    /// or a snapshot if the journal does not keep them.
+    async fn follow(&self, req: Request<Position>) -> Result<Response<Self::FollowStream>, Status> {
+        auth::authorize_admin(&req).map_err(|err| Status::permission_denied(err.to_string()))?;
+        let journal = match &self.role.journal {
+            Some(journal) => journal.clone(),
+            None => return Err(Status::unimplemented(Error::NotLeader.to_string())),
+        };
+        let position = req.get_ref();
+        let log = journal.lock().await;
+        let backlog = (position.epoch == journal.epoch())
+            .then(|| log.since(position.offset))
+            .flatten();
+        let offset = log.offset();
+        drop(log);
+        if backlog.is_none() {
+            info!("Sending a snapshot at offset {} to a follower", offset);
+        }
+        let db = self.db.clone();
+        let stopping = self.role.stopping.clone();
+        Ok(Response::new(replication::feed(
+            journal, db, backlog, offset, stopping,
+        )))
+    }
+}
+
+#[tonic::async_trait]
+impl<Db: Database> Replica for Service<Db> {
+    /// Applies a change of the leader; the statistics count only the requests
+    /// of the clients, so only the namespaces and the records are updated.
+    async fn apply(&self, change: Change) -> anyhow::Result<()> {
+        use database::Error::{
+            NamespaceAlreadyExists, NamespaceMissing, RecordAlreadyExists, RecordAlreadyMissing,
+        };
+        let _writing = self.writes.read().await;
+        match change {
+            Change::Reset(_) => {
+                for ns in self.db.list_namespaces().await? {
+                    self.stats.write().await.drop_namespace(&ns);
+                }
+                self.db.clear().await?;
+                self.stats
+                    .write()
+                    .await
+                    .create_namespace(database::DEFAULT_NAMESPACE);
+            }
+            Change::CreateNamespace(ns) => {
+                match self.db.create_namespace(&ns.name).await {
+                    Ok(()) | Err(NamespaceAlreadyExists(_)) => {}
+                    Err(err) => return Err(err.into()),
+                }
+                self.stats.write().await.create_namespace(&ns.name);
+            }
+            Change::DropNamespace(ns) => {
+                match self.db.drop_namespace(&ns.name).await {
+                    Ok(()) | Err(NamespaceMissing(_)) => {}
+                    Err(err) => return Err(err.into()),
+                }
+                self.stats.write().await.drop_namespace(&ns.name);
+            }
+            Change::Put(pair) => {
+                let pairs = [(pair.key, pair.value)];
+                let ns = namespace(&pair.namespace);
+                let overwrite = database::Conflict::Overwrite;
+                let written = self.db.write_batch(ns, &pairs, overwrite).await?;
+                self.count(ns, written.inserted, 0).await;
+            }
+            Change::Delete(key) => {
+                let ns = namespace(&key.namespace);
+                match self.db.delete(ns, &key.key).await {
+                    Ok(_) => self.count(ns, 0, 1).await,
+                    Err(RecordAlreadyMissing(_)) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Change::Batch(batch) => {
+                let conflict = database::Conflict::from_proto(batch.conflict)
+                    .ok_or(Error::ConflictInvalid(batch.conflict))?;
+                let pairs: Vec<(Vec<u8>, Vec<u8>)> = batch
+                    .pairs
+                    .into_iter()
+                    .map(|pair| (pair.key, pair.value))
+                    .collect();
+                let ns = namespace(&batch.namespace);
+                // An insert-only batch applied already fails as a whole.
+                match self.db.write_batch(ns, &pairs, conflict).await {
+                    Ok(written) => self.count(ns, written.inserted, 0).await,
+                    Err(RecordAlreadyExists(_)) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Change::Synced(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Represents the result of a command applied by the cluster.
+enum Outcome {
+    Done,
+    Output(database::Result<Vec<u8>>),
+    Written(database::Result<database::Written>),
+}
+
+impl Outcome {
+    fn output(self) -> database::Result<Vec<u8>> {
+        match self {
+            Outcome::Done => Ok(Vec::new()),
+            Outcome::Output(r) => r,
+            Outcome::Written(r) => r.map(|_| Vec::new()),
+        }
+    }
+
+    fn written(self) -> database::Result<database::Written> {
+        match self {
+            Outcome::Done => Ok(database::Written::default()),
+            Outcome::Output(r) => r.map(|_| database::Written::default()),
+            Outcome::Written(r) => r,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<Db: Database> StateMachine for Service<Db> {
+    type Outcome = Outcome;
+
+    /// Applies a committed command as the handlers would; the statistics count
+    /// only the requests of the clients, except for the namespaces and the
+    /// records, so every member knows how many records it has.
+    async fn apply(&self, command: astrobase_api::proto::Command) -> Outcome {
+        let command = match command.op {
+            Some(command) => command,
+            None => return Outcome::Done,
+        };
+        let _writing = self.writes.read().await;
+        match command {
+            Command::Noop(_) => Outcome::Done,
+            Command::Insert(pair) => {
+                let ns = namespace(&pair.namespace);
+                let r = self.db.insert(ns, &pair.key, &pair.value).await;
+                if r.is_ok() {
+                    self.count(ns, 1, 0).await;
+                }
+                Outcome::Output(r)
+            }
+            Command::Update(pair) => {
+                let ns = namespace(&pair.namespace);
+                Outcome::Output(self.db.update(ns, &pair.key, &pair.value).await)
+            }
+            Command::Delete(key) => {
+                let ns = namespace(&key.namespace);
+                let r = self.db.delete(ns, &key.key).await;
+                if r.is_ok() {
+                    self.count(ns, 0, 1).await;
+                }
+                Outcome::Output(r)
+            }
+            Command::Batch(batch) => {
+                // The leader checks the conflict mode before proposing the batch.
+                let conflict = match database::Conflict::from_proto(batch.conflict) {
+                    Some(conflict) => conflict,
+                    None => return Outcome::Done,
+                };
+                let pairs: Vec<(Vec<u8>, Vec<u8>)> = batch
+                    .pairs
+                    .into_iter()
+                    .map(|pair| (pair.key, pair.value))
+                    .collect();
+                let ns = namespace(&batch.namespace);
+                let r = self.db.write_batch(ns, &pairs, conflict).await;
+                if let Ok(written) = &r {
+                    self.count(ns, written.inserted, 0).await;
+                }
+                Outcome::Written(r)
+            }
+            Command::CreateNamespace(ns) => {
+                let r = self.db.create_namespace(&ns.name).await;
+                if r.is_ok() {
+                    self.stats.write().await.create_namespace(&ns.name);
+                }
+                Outcome::Output(r.map(|()| Vec::new()))
+            }
+            Command::DropNamespace(ns) => {
+                let r = self.db.drop_namespace(&ns.name).await;
+                if r.is_ok() {
+                    self.stats.write().await.drop_namespace(&ns.name);
+                }
+                Outcome::Output(r.map(|()| Vec::new()))
+            }
+        }
+    }
+}
+
+/// Counts the records of a namespace, reading them in chunks.
+async fn count<Db: Database>(db: &Db, ns: &str) -> database::Result<u64> {
+    let mut count = 0;
+    let mut after = None;
+    loop {
+        let mut chunk = db.scan(ns, b"", after.as_deref(), SCAN_CHUNK).await?;
+        count += chunk.len() as u64;
+        if chunk.len() < SCAN_CHUNK {
+            return Ok(count);
+        }
+        after = chunk.pop().map(|(key, _)| key);
+    }
+}
+
+/// Rejects a request a follower cannot serve, pointing the client to the leader.
+fn redirect(message: String, leader: &str) -> Status {
+    let mut status = Status::failed_precondition(message);
+    if let Ok(value) = leader.parse() {
+        status.metadata_mut().insert(LEADER_METADATA, value);
+    }
+    status
+}
+
+/// Converts a failure of the cluster to the reply.
+fn cluster_status(err: raft::Error) -> Status {
+    match &err {
+        raft::Error::NotLeader(leader) => redirect(err.to_string(), leader),
+        raft::Error::NoLeader | raft::Error::LeadershipLost => Status::unavailable(err.to_string()),
+        raft::Error::Io(_) => Status::internal(err.to_string()),
+    }
+}
+
+/// Constructs the record of a change.
+fn pair(ns: &str, key: &[u8], value: &[u8]) -> Pair {
+    Pair {
+        key: key.into(),
+        value: value.into(),
+        namespace: ns.into(),
+    }
+}
+
+/// Converts a database error which is not the client's fault.
+fn internal(err: database::Error) -> Status {
+    Status::internal(err.to_string())
+}
+
+/// Maps the empty namespace of a request to the default one.
+fn namespace(name: &str) -> &str {
+    if name.is_empty() {
+        database::DEFAULT_NAMESPACE
+    } else {
+        name
+    }
+}
+
+/// Converts the result of a database operation to the reply.
+fn output(r: database::Result<Vec<u8>>) -> Output {
+    match r {
+        Ok(value) => Output::accepted(value),
+        Err(err) => Output::rejected(rejection(&err)),
+    }
+}
+
+/// Classifies a database error for the client.
+fn rejection(err: &database::Error) -> Rejection {
+    use database::Error;
+    let failure = match err {
+        Error::RecordMissing(_)
+        | Error::RecordAlreadyMissing(_)
+        | Error::NamespaceMissing(_)
+        | Error::FileMissing(_) => Failure::NotFound,
+        Error::RecordAlreadyExists(_) | Error::NamespaceAlreadyExists(_) => Failure::AlreadyExists,
+        Error::RecordAlreadyExistsIdentical(_) => Failure::Identical,
+        Error::NamespaceInvalid(_) | Error::NamespacePermanent(_) | Error::HistoryUnsupported => {
+            Failure::Invalid
+        }
+        _ => Failure::Other,
+    };
+    Rejection {
+        failure,
+        info: err.to_string(),
+    }
+}
+
+/// Represents request validation errors.
+#[derive(thiserror::Error, Debug)]
+enum Error {
+    #[error("Key is too long: {0}")]
+    KeyTooLong(usize),
+    #[error("Value is too long: {0}")]
+    ValueTooLong(usize),
+    #[error("Rate limit exceeded")]
+    RateLimited,
+    #[error("Invalid conflict mode: {0}")]
+    ConflictInvalid(i32),
+    #[error("Read-only follower, write to the leader at {0}")]
+    Follower(String),
+    #[error("Not a replication leader")]
+    NotLeader,
+    #[error("The moment to read at is missing")]
+    MomentMissing,
+}
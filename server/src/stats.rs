@@ -1,61 +1,206 @@
 //! astrobase-server database statistics.
 
-/// Represents the statistics.
+use std::collections::BTreeMap;
+
+/// Represents the database operations.
+#[derive(Clone, Copy)]
+pub enum Op {
+    Get,
+    Insert,
+    Delete,
+    Update,
+}
+
+/// Represents the counters of a namespace.
 #[derive(Default)]
-pub struct Stats {
+struct Counters {
     number_of_records: usize,
     get_ok_fail: (usize, usize),
     insert_ok_fail: (usize, usize),
     delete_ok_fail: (usize, usize),
     update_ok_fail: (usize, usize),
+    denied: (usize, usize, usize, usize),
+}
+
+impl Counters {
+    /// Adds counters of another namespace.
+    fn add(&mut self, other: &Counters) {
+        fn add2(a: &mut (usize, usize), b: (usize, usize)) {
+            a.0 += b.0;
+            a.1 += b.1;
+        }
+
+        self.number_of_records += other.number_of_records;
+        add2(&mut self.get_ok_fail, other.get_ok_fail);
+        add2(&mut self.insert_ok_fail, other.insert_ok_fail);
+        add2(&mut self.delete_ok_fail, other.delete_ok_fail);
+        add2(&mut self.update_ok_fail, other.update_ok_fail);
+        self.denied.0 += other.denied.0;
+        self.denied.1 += other.denied.1;
+        self.denied.2 += other.denied.2;
+        self.denied.3 += other.denied.3;
+    }
+
+    /// Returns the counters as named values.
+    fn fields(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("records", self.number_of_records),
+            ("get_ok", self.get_ok_fail.0),
+            ("get_fail", self.get_ok_fail.1),
+            ("insert_ok", self.insert_ok_fail.0),
+            ("insert_fail", self.insert_ok_fail.1),
+            ("delete_ok", self.delete_ok_fail.0),
+            ("delete_fail", self.delete_ok_fail.1),
+            ("update_ok", self.update_ok_fail.0),
+            ("update_fail", self.update_ok_fail.1),
+            ("denied_get", self.denied.0),
+            ("denied_insert", self.denied.1),
+            ("denied_delete", self.denied.2),
+            ("denied_update", self.denied.3),
+        ]
+    }
+
+    /// Formats the counters as one line.
+    fn line(&self) -> String {
+        format!("NR:{}, GET(ok/fail):{:?}, INSERT(ok/fail):{:?}, DELETE(ok/fail):{:?}, UPDATE(ok/fail):{:?}, DENIED(get/insert/delete/update):{:?}",
+            self.number_of_records,
+            self.get_ok_fail,
+            self.insert_ok_fail,
+            self.delete_ok_fail,
+            self.update_ok_fail,
+            self.denied)
+    }
+}
+
+/// Represents the counters as named values for the other protocols:
+/// the totals and the counters of every namespace.
+pub struct Report {
+    pub total: Vec<(&'static str, usize)>,
+    pub namespaces: Vec<(String, Vec<(&'static str, usize)>)>,
+}
+
+/// Represents the statistics broken down by namespace.
+/// Requests to unknown namespaces are counted in the totals only.
+#[derive(Default)]
+pub struct Stats {
+    namespaces: BTreeMap<String, Counters>,
+    unknown: Counters,
 }
 
 impl Stats {
+    /// Returns the counters of a namespace.
+    fn counters(&mut self, ns: &str) -> &mut Counters {
+        match self.namespaces.get_mut(ns) {
+            Some(counters) => counters,
+            None => &mut self.unknown,
+        }
+    }
+
+    /// Starts counting operations of an existing namespace.
+    pub fn create_namespace(&mut self, ns: &str) {
+        self.namespaces.entry(ns.into()).or_default();
+    }
+
     /// Updates the GET stats.
-    pub fn get(&mut self, ok: bool) {
+    pub fn get(&mut self, ns: &str, ok: bool) {
+        let counters = self.counters(ns);
         if ok {
-            self.get_ok_fail.0 += 1;
+            counters.get_ok_fail.0 += 1;
         } else {
-            self.get_ok_fail.1 += 1;
+            counters.get_ok_fail.1 += 1;
         }
     }
 
-    /// Updates the INSERT stats (may increment number of records).
-    pub fn insert(&mut self, ok: bool) {
+    /// Counts the records a write added to and removed from the database.
+    pub fn records(&mut self, ns: &str, added: u64, removed: u64) {
+        let counters = self.counters(ns);
+        counters.number_of_records =
+            (counters.number_of_records + added as usize).saturating_sub(removed as usize);
+    }
+
+    /// Updates the INSERT stats.
+    pub fn insert(&mut self, ns: &str, ok: bool) {
+        let counters = self.counters(ns);
         if ok {
-            self.number_of_records += 1;
-            self.insert_ok_fail.0 += 1;
+            counters.insert_ok_fail.0 += 1;
         } else {
-            self.insert_ok_fail.1 += 1;
+            counters.insert_ok_fail.1 += 1;
         }
     }
 
-    /// Updates the DELETE stats (may decrement number of records).
-    pub fn delete(&mut self, ok: bool) {
+    /// Updates the DELETE stats.
+    pub fn delete(&mut self, ns: &str, ok: bool) {
+        let counters = self.counters(ns);
         if ok {
-            self.number_of_records -= 1;
-            self.delete_ok_fail.0 += 1;
+            counters.delete_ok_fail.0 += 1;
         } else {
-            self.delete_ok_fail.1 += 1;
+            counters.delete_ok_fail.1 += 1;
         }
     }
 
     /// Updates the UPDATE stats.
-    pub fn update(&mut self, ok: bool) {
+    pub fn update(&mut self, ns: &str, ok: bool) {
+        let counters = self.counters(ns);
         if ok {
-            self.update_ok_fail.0 += 1;
+            counters.update_ok_fail.0 += 1;
         } else {
-            self.update_ok_fail.1 += 1;
+            counters.update_ok_fail.1 += 1;
+        }
+    }
+
+    /// Updates the INSERT and UPDATE stats after a batch write.
+    pub fn write_batch(&mut self, ns: &str, inserted: u64, updated: u64) {
+        let counters = self.counters(ns);
+        counters.insert_ok_fail.0 += inserted as usize;
+        counters.update_ok_fail.0 += updated as usize;
+    }
+
+    /// Updates the stats of operations denied by access control.
+    pub fn deny(&mut self, ns: &str, op: Op) {
+        let denied = &mut self.counters(ns).denied;
+        match op {
+            Op::Get => denied.0 += 1,
+            Op::Insert => denied.1 += 1,
+            Op::Delete => denied.2 += 1,
+            Op::Update => denied.3 += 1,
+        }
+    }
+
+    /// Forgets the stats of a dropped namespace.
+    pub fn drop_namespace(&mut self, ns: &str) {
+        self.namespaces.remove(ns);
+    }
+
+    /// Sums the counters of all namespaces.
+    fn total(&self) -> Counters {
+        let mut total = Counters::default();
+        for counters in self.namespaces.values() {
+            total.add(counters);
         }
+        total.add(&self.unknown);
+        total
     }
 
-    /// Dumps the data to stderr.
+    /// Dumps the data to stderr: a line per namespace if there are several,
+    /// then the totals.
     pub fn dump(&self) {
-        eprintln!("NR:{}, GET(ok/fail):{:?}, INSERT(ok/fail):{:?}, DELETE(ok/fail):{:?}, UPDATE(ok/fail):{:?}",
-            self.number_of_records,
-            self.get_ok_fail,
-            self.insert_ok_fail,
-            self.delete_ok_fail,
-            self.update_ok_fail);
+        if self.namespaces.len() > 1 {
+            for (ns, counters) in &self.namespaces {
+                eprintln!("[{}] {}", ns, counters.line());
+            }
+        }
+        eprintln!("{}", self.total().line());
+    }
+
+    /// Returns the current counters.
+    pub fn report(&self) -> Report {
+        Report {
+            total: self.total().fields(),
+            namespaces: self
+                .namespaces
+                .iter()
+                .map(|(ns, counters)| (ns.clone(), counters.fields()))
+                .collect(),
+        }
     }
 }
@@ -0,0 +1,68 @@
+//! Backup archive unit tests.
+
+use crate::backup::{archive, read, restore, Error};
+use crate::config;
+use crate::database::{Database, InMemory, DEFAULT_NAMESPACE as NS};
+
+fn records(keys: &[&str]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    keys.iter()
+        .map(|key| {
+            (
+                key.as_bytes().to_vec(),
+                format!("value of {}", key).into_bytes(),
+            )
+        })
+        .collect()
+}
+
+fn sample() -> Vec<u8> {
+    archive(vec![
+        (NS.into(), records(&["a", "b"])),
+        ("empty".into(), Vec::new()),
+        ("ns".into(), records(&["c"])),
+    ])
+}
+
+#[tokio::test]
+async fn backup_round_trip() {
+    let batches = read(&sample()).unwrap();
+    let namespaces: Vec<&str> = batches.iter().map(|b| b.namespace.as_str()).collect();
+    assert_eq!(namespaces, [NS, "empty", "ns"]);
+
+    let db = InMemory::open(&config::Database::default());
+    assert_eq!(restore(&db, batches.clone()).await.unwrap(), 3);
+    let mut namespaces = db.list_namespaces().await.unwrap();
+    namespaces.sort();
+    assert_eq!(namespaces, ["default", "empty", "ns"]);
+    assert_eq!(
+        db.scan(NS, b"", None, 10).await.unwrap(),
+        records(&["a", "b"])
+    );
+    assert_eq!(db.scan("ns", b"", None, 10).await.unwrap(), records(&["c"]));
+
+    let err = restore(&db, batches).await.unwrap_err();
+    assert!(
+        matches!(err.downcast_ref(), Some(Error::NotEmpty(_))),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn backup_detects_corruption() {
+    let archive = sample();
+    let value = archive
+        .windows(10)
+        .position(|window| window == b"value of b")
+        .unwrap();
+    let mut flipped = archive.clone();
+    flipped[value] = b'V';
+    assert!(matches!(read(&flipped), Err(Error::Checksum { .. })));
+
+    assert!(matches!(read(b"BACKUP\n"), Err(Error::Magic)));
+    let cut = &archive[..archive.len() - 1];
+    assert!(matches!(read(cut), Err(Error::Corrupted(_))));
+    let mut trailing = archive.clone();
+    trailing.push(0);
+    assert!(matches!(read(&trailing), Err(Error::Trailing(offset)) if offset == archive.len()));
+}
@@ -0,0 +1,42 @@
+//! Config validation unit tests.
+
+use crate::config::{self, Error};
+
+use std::path::Path;
+
+#[test]
+fn config_rejects_duplicate_tokens() {
+    let filename = Path::new("/tmp/astrobase-config-auth.json");
+    let user = |name: &str, token: &str| {
+        format!(
+            r#"{{"name": "{}", "token": "{}", "grants": [{{"prefix": "", "access": "read"}}]}}"#,
+            name, token
+        )
+    };
+    let text = |users: &[String]| {
+        format!(
+            r#"{{
+                "environment": "test",
+                "server": {{"endpoint": "[::1]:50051"}},
+                "monitoring": {{"interval": 60}},
+                "logging": {{}},
+                "auth": {{"users": [{}]}}
+            }}"#,
+            users.join(", ")
+        )
+    };
+
+    std::fs::write(filename, text(&[user("alice", "a"), user("bob", "b")])).unwrap();
+    let distinct = config::Astrobase::load(filename);
+    std::fs::write(filename, text(&[user("alice", "a"), user("bob", "a")])).unwrap();
+    let duplicate = config::Astrobase::load(filename);
+    std::fs::remove_file(filename).unwrap();
+
+    assert!(distinct.is_ok());
+    match duplicate {
+        Err(Error::Auth(reason)) => {
+            assert_eq!(reason, "users 'alice' and 'bob' have the same token")
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
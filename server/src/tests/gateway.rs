@@ -0,0 +1,127 @@
+//! HTTP/JSON gateway unit tests.
+
+use crate::gateway::{reply, status, Params};
+
+use astrobase_api::proto::Output;
+use astrobase_api::{Encoding, Failure, Rejection, LEADER_METADATA};
+use axum::body::HttpBody as _;
+use axum::http::StatusCode;
+use axum::response::IntoResponse as _;
+use tonic::Status;
+
+#[test]
+fn gateway_maps_rejections() {
+    let cases = [
+        (Failure::NotFound, StatusCode::NOT_FOUND, "not_found"),
+        (
+            Failure::AlreadyExists,
+            StatusCode::CONFLICT,
+            "already_exists",
+        ),
+        (Failure::Identical, StatusCode::CONFLICT, "identical"),
+        (Failure::Invalid, StatusCode::BAD_REQUEST, "invalid"),
+        (Failure::Other, StatusCode::INTERNAL_SERVER_ERROR, "other"),
+    ];
+    for (failure, code, kind) in cases {
+        let rejection = Rejection {
+            failure,
+            info: "reason".into(),
+        };
+        let err = reply(Ok(tonic::Response::new(Output::rejected(rejection)))).unwrap_err();
+        assert_eq!(
+            (err.status, err.kind, err.message.as_str()),
+            (code, kind, "reason")
+        );
+    }
+    let value = reply(Ok(tonic::Response::new(Output::accepted(b"v".to_vec()))));
+    assert_eq!(value.ok(), Some(b"v".to_vec()));
+}
+
+#[test]
+fn gateway_maps_statuses() {
+    let cases = [
+        (
+            Status::unauthenticated("a"),
+            StatusCode::UNAUTHORIZED,
+            "unauthenticated",
+        ),
+        (
+            Status::permission_denied("a"),
+            StatusCode::FORBIDDEN,
+            "permission_denied",
+        ),
+        (
+            Status::invalid_argument("a"),
+            StatusCode::BAD_REQUEST,
+            "invalid",
+        ),
+        (
+            Status::resource_exhausted("a"),
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+        ),
+        (Status::not_found("a"), StatusCode::NOT_FOUND, "not_found"),
+        (
+            Status::failed_precondition("a"),
+            StatusCode::MISDIRECTED_REQUEST,
+            "read_only",
+        ),
+        (
+            Status::internal("a"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "other",
+        ),
+        (
+            Status::unavailable("a"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "other",
+        ),
+    ];
+    for (s, code, kind) in cases {
+        let err = status(&s);
+        assert_eq!(
+            (err.status, err.kind, err.message.as_str()),
+            (code, kind, "a")
+        );
+        let err = reply(Err(s)).unwrap_err();
+        assert_eq!((err.status, err.kind), (code, kind));
+    }
+}
+
+#[tokio::test]
+async fn gateway_points_to_the_leader() {
+    let mut s = Status::failed_precondition("follower");
+    let leader = "http://[::1]:50051".parse().unwrap();
+    s.metadata_mut().insert(LEADER_METADATA, leader);
+    let err = status(&s);
+    assert_eq!(err.leader.as_deref(), Some("http://[::1]:50051"));
+
+    let mut response = err.into_response();
+    assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+    assert_eq!(response.headers()[LEADER_METADATA], "http://[::1]:50051");
+    let body = response.body_mut().data().await.unwrap().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["leader"], "http://[::1]:50051");
+    assert_eq!(body["error"]["kind"], "read_only");
+    let body = status(&Status::not_found("a")).into_response();
+    assert!(!body.headers().contains_key(LEADER_METADATA));
+}
+
+#[test]
+fn gateway_parses_params() {
+    let params = Params::parse(Some("namespace=a%20b&encoding=hex"))
+        .ok()
+        .unwrap();
+    assert_eq!(params.namespace, "a b");
+    assert!(matches!(params.encoding, Encoding::Hex));
+
+    for query in ["color=red", "encoding=morse", "namespace=%FF"] {
+        let err = Params::parse(Some(query)).err().unwrap();
+        assert_eq!(
+            (err.status, err.kind),
+            (StatusCode::BAD_REQUEST, "invalid"),
+            "{}",
+            query
+        );
+    }
+}
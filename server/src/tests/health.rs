@@ -0,0 +1,63 @@
+//! Health checking unit tests.
+
+use crate::health::{Health, Reporter};
+use crate::server::serve_when_ready;
+
+use astrobase_api::health::health_check_response::ServingStatus;
+use astrobase_api::health::health_server::Health as _;
+use astrobase_api::health::{HealthCheckRequest, HealthCheckResponse};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_stream::{Stream, StreamExt as _};
+use tonic::{Code, Request, Status};
+
+async fn check(health: &Health, service: &str) -> Result<i32, Code> {
+    let req = Request::new(HealthCheckRequest {
+        service: service.into(),
+    });
+    match health.check(req).await {
+        Ok(reply) => Ok(reply.get_ref().status),
+        Err(status) => Err(status.code()),
+    }
+}
+
+async fn watched<S>(changes: &mut S) -> i32
+where
+    S: Stream<Item = Result<HealthCheckResponse, Status>> + Unpin,
+{
+    changes.next().await.unwrap().unwrap().status
+}
+
+#[tokio::test]
+async fn health_serving_once_ready() {
+    let (reporter, health) = Reporter::new();
+    let req = Request::new(HealthCheckRequest {
+        service: "api.Astrobase".into(),
+    });
+    let mut changes = health.watch(req).await.unwrap().into_inner();
+    let (ready, mut readiness) = watch::channel(false);
+    let serving = tokio::spawn(async move {
+        serve_when_ready(&reporter, &mut readiness).await;
+        reporter
+    });
+
+    let not_serving = Ok(ServingStatus::NotServing as i32);
+    assert_eq!(check(&health, "").await, not_serving);
+    assert_eq!(
+        watched(&mut changes).await,
+        ServingStatus::NotServing as i32
+    );
+    ready.send_replace(false);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(check(&health, "api.Astrobase").await, not_serving);
+    ready.send_replace(true);
+    let reporter = serving.await.unwrap();
+    assert_eq!(check(&health, "").await, Ok(ServingStatus::Serving as i32));
+    assert_eq!(check(&health, "api.Other").await, Err(Code::NotFound));
+    assert_eq!(watched(&mut changes).await, ServingStatus::Serving as i32);
+    reporter.not_serving();
+    assert_eq!(
+        watched(&mut changes).await,
+        ServingStatus::NotServing as i32
+    );
+}
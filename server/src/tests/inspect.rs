@@ -0,0 +1,84 @@
+//! Inspection unit tests.
+
+use crate::database::Line;
+use crate::inspect::inspect;
+
+use std::path::Path;
+
+#[test]
+fn inspect_counts_lines() {
+    let filename = Path::new("/tmp/astrobase-inspect.db");
+    std::fs::write(filename, b"a\t1\t1\t5\nb\t2\t2\t6\na\t\0\t3\t7\nbroken\n").unwrap();
+    let report = inspect(filename, Some(b"a")).unwrap();
+    std::fs::remove_file(filename).unwrap();
+
+    assert_eq!(report.segments.len(), 1);
+    assert_eq!(report.records, 3);
+    assert_eq!(report.tombstones, 1);
+    assert_eq!(report.live(), 1);
+    assert_eq!(report.dead(), 2);
+    assert_eq!(report.invalid, [(0, 24, b"broken".to_vec())]);
+    assert!(report.terminated);
+    assert_eq!(report.history.len(), 2);
+}
+
+#[test]
+fn inspect_skips_repeated_lines() {
+    let filename = Path::new("/tmp/astrobase-inspect-stale.db");
+    let stale = Path::new("/tmp/astrobase-inspect-stale.db.1");
+    let active = Path::new("/tmp/astrobase-inspect-stale.db.2");
+    // The merged segment 0, the segment 1 a merge left behind and the active one.
+    std::fs::write(filename, b"a\t1\t1\t5\nb\t2\t2\t6\n").unwrap();
+    std::fs::write(stale, b"b\t2\t2\t6\n").unwrap();
+    std::fs::write(active, b"b\t\0\t3\t7\nc\t3\t4\t8\n").unwrap();
+    let report = inspect(filename, Some(b"b")).unwrap();
+    for file in &[filename, stale, active] {
+        std::fs::remove_file(file).unwrap();
+    }
+
+    assert_eq!(report.segments.len(), 3);
+    assert_eq!(report.records, 4);
+    assert_eq!(report.repeated, 1);
+    assert_eq!(report.tombstones, 1);
+    assert_eq!(report.live(), 2);
+    let offsets: Vec<(usize, u64)> = report
+        .history
+        .iter()
+        .map(|(segment, offset, _)| (*segment, *offset))
+        .collect();
+    assert_eq!(offsets, [(0, 8), (2, 0)]);
+}
+
+#[test]
+fn inspect_unescapes_current_format() {
+    let filename = Path::new("/tmp/astrobase-inspect-escaped.db");
+    let mut text = b"astrobase-log 2\n".to_vec();
+    text.extend_from_slice(b"k\\tx\tv\\n1\t1\t5\nk\\tx\t\0\t2\t6\nj\tw\t3\t7");
+    std::fs::write(filename, text).unwrap();
+    let report = inspect(filename, Some(b"k\tx")).unwrap();
+    std::fs::remove_file(filename).unwrap();
+
+    assert_eq!(report.records, 3);
+    assert_eq!(report.tombstones, 1);
+    assert_eq!(report.keys.get(&b"k\tx"[..]), Some(&None));
+    assert_eq!(report.keys.get(&b"j"[..]), Some(&Some(b"w".to_vec())));
+    assert_eq!(report.live(), 1);
+    assert!((report.duplicate_ratio() - 1.0 / 3.0).abs() < 1e-9);
+    assert!(!report.terminated);
+    assert!(report.invalid.is_empty());
+    let offsets: Vec<u64> = report
+        .history
+        .iter()
+        .map(|(_, offset, _)| *offset)
+        .collect();
+    assert_eq!(offsets, [16, 30]);
+    match &report.history[0].2 {
+        Line::Record(_, version) => assert_eq!(version.value.as_deref(), Some(&b"v\n1"[..])),
+        line => panic!("unexpected line: {:?}", line),
+    }
+}
+
+#[test]
+fn inspect_fails_without_file() {
+    assert!(inspect(Path::new("/tmp/astrobase-inspect-missing.db"), None).is_err());
+}
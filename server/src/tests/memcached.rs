@@ -0,0 +1,60 @@
+//! Memcached protocol parser unit tests.
+
+use crate::memcached::{read_data, skip_data, words, Error, StoreLine};
+
+#[test]
+fn memcached_splits_words() {
+    assert_eq!(words(b"  get a  b "), [&b"get"[..], b"a", b"b"]);
+    assert!(words(b"   ").is_empty());
+}
+
+#[test]
+fn memcached_parses_storage_lines() {
+    let line = StoreLine::parse(&words(b"k 5 0 3 noreply")).unwrap();
+    assert_eq!((line.key, line.len, line.noreply), (&b"k"[..], 3, true));
+    let line = StoreLine::parse(&words(b"k 0 -1 16777217")).unwrap();
+    assert_eq!((line.len, line.noreply), (16 * 1024 * 1024 + 1, false));
+
+    // The data block of a malformed line is skipped if its length is known.
+    for (line, len) in [
+        ("k 0 0", None),
+        ("k 0 0 -3", None),
+        ("k 0 0 x", None),
+        ("k 0 0 3 noreply extra", Some(3)),
+        ("k x 0 3", Some(3)),
+        ("k 0 x 3", Some(3)),
+    ] {
+        assert_eq!(
+            StoreLine::parse(&words(line.as_bytes())).err(),
+            Some(len),
+            "{}",
+            line
+        );
+    }
+}
+
+#[tokio::test]
+async fn memcached_skips_data_blocks() {
+    let mut input = &b"delete k\r\nget k\r\n"[..];
+    skip_data(&mut input, 8).await.unwrap();
+    assert_eq!(input, b"get k\r\n");
+    let mut input = &b"abc"[..];
+    skip_data(&mut input, 8).await.unwrap();
+    assert!(input.is_empty());
+}
+
+#[tokio::test]
+async fn memcached_reads_data_blocks() {
+    let mut input = &b"abc\r\nget k\r\n"[..];
+    assert_eq!(read_data(&mut input, 3).await.unwrap(), b"abc");
+    assert_eq!(input, b"get k\r\n");
+
+    assert!(matches!(
+        read_data(&mut &b"abcd\r\n"[..], 3).await,
+        Err(Error::DataChunk)
+    ));
+    match read_data(&mut &b"ab\r\n"[..], 3).await {
+        Err(Error::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
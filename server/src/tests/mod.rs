@@ -0,0 +1,15 @@
+//! astrobase-server unit tests of the modules outside the database.
+
+mod backup;
+mod config;
+mod gateway;
+mod health;
+mod inspect;
+mod memcached;
+mod protocol;
+mod raft;
+mod reload;
+mod replication;
+mod resp;
+mod stats;
+mod unix;
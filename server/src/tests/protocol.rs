@@ -0,0 +1,39 @@
+//! Line protocol helper unit tests.
+
+use crate::protocol::{parse, read_line, Error};
+
+#[tokio::test]
+async fn protocol_reads_lines() {
+    let mut input = &b"a b\r\nc\n\r\nlast"[..];
+    assert_eq!(
+        read_line(&mut input, 8).await.unwrap(),
+        Some(b"a b".to_vec())
+    );
+    assert_eq!(read_line(&mut input, 8).await.unwrap(), Some(b"c".to_vec()));
+    assert_eq!(read_line(&mut input, 8).await.unwrap(), Some(Vec::new()));
+    // A line cut short by the end of the stream is dropped with it.
+    assert_eq!(read_line(&mut input, 8).await.unwrap(), None);
+    assert_eq!(read_line(&mut input, 8).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn protocol_limits_lines() {
+    let mut input = &b"12345678\r\n"[..];
+    assert!(matches!(
+        read_line(&mut input, 8).await,
+        Err(Error::TooLong)
+    ));
+    let mut input = &b"123456\r\n"[..];
+    assert_eq!(
+        read_line(&mut input, 8).await.unwrap(),
+        Some(b"123456".to_vec())
+    );
+}
+
+#[test]
+fn protocol_parses_numbers() {
+    assert_eq!(parse::<usize>(b"42"), Some(42));
+    assert_eq!(parse::<i64>(b"-1"), Some(-1));
+    assert_eq!(parse::<usize>(b"-1"), None);
+    assert_eq!(parse::<u32>(b"\xff"), None);
+}
@@ -0,0 +1,234 @@
+//! Raft consensus unit tests.
+
+use crate::config::{Cluster, Member};
+use crate::raft::{Error, Node, StateMachine};
+
+use astrobase_api::proto::raft_server::{Raft, RaftServer};
+use astrobase_api::proto::{command, AppendRequest, Command, Empty, Entry, Key, VoteRequest};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::Request;
+
+/// Records the commands applied to it, answers how many there are.
+#[derive(Default)]
+struct Machine(Mutex<Vec<command::Op>>);
+
+#[tonic::async_trait]
+impl StateMachine for Machine {
+    type Outcome = usize;
+
+    async fn apply(&self, command: Command) -> usize {
+        let mut ops = self.0.lock().unwrap();
+        ops.push(command.op.unwrap());
+        ops.len()
+    }
+}
+
+impl Machine {
+    /// Returns the keys of the deletes applied, skipping the empty entries of new leaders.
+    fn deleted(&self) -> Vec<Vec<u8>> {
+        let ops = self.0.lock().unwrap();
+        ops.iter()
+            .filter_map(|op| match op {
+                command::Op::Delete(key) => Some(key.key.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn cluster(node: &str, endpoints: &[String], election_timeout: u64) -> Cluster {
+    let members = endpoints
+        .iter()
+        .zip(["a", "b", "c"])
+        .map(|(endpoint, id)| Member {
+            id: id.into(),
+            endpoint: endpoint.clone(),
+        })
+        .collect();
+    Cluster {
+        node: node.into(),
+        members,
+        token: None,
+        election_timeout,
+        heartbeat: election_timeout / 5,
+    }
+}
+
+/// Returns the database path of a member, removing the Raft files left behind.
+fn db(name: &str) -> PathBuf {
+    let db = PathBuf::from(format!("/tmp/astrobase-raft-{}.db", name));
+    remove(&db);
+    db
+}
+
+fn remove(db: &Path) {
+    for extension in ["raft", "raftlog"] {
+        let _ = std::fs::remove_file(db.with_extension(extension));
+    }
+}
+
+fn delete(term: u64, index: u64, key: &[u8]) -> Entry {
+    Entry {
+        term,
+        index,
+        command: Some(Command {
+            op: Some(command::Op::Delete(Key {
+                key: key.to_vec(),
+                namespace: String::new(),
+            })),
+        }),
+    }
+}
+
+async fn vote(node: &Node<usize>, term: u64, candidate: &str, last: (u64, u64)) -> (u64, bool) {
+    let req = VoteRequest {
+        term,
+        candidate: candidate.into(),
+        last_log_index: last.0,
+        last_log_term: last.1,
+    };
+    let reply = node.request_vote(Request::new(req)).await.unwrap();
+    (reply.get_ref().term, reply.get_ref().granted)
+}
+
+async fn append(node: &Node<usize>, req: AppendRequest) -> (u64, bool, u64) {
+    let reply = node.append_entries(Request::new(req)).await.unwrap();
+    let reply = reply.into_inner();
+    (reply.term, reply.success, reply.match_index)
+}
+
+#[tokio::test]
+async fn raft_follower_votes_and_appends() {
+    let endpoints: Vec<String> = (1..=3).map(|i| format!("http://[::1]:{}", i)).collect();
+    let path = db("follower");
+    let node = Node::new(&cluster("a", &endpoints, 60_000), &path, false).unwrap();
+    let machine = Arc::new(Machine::default());
+    let (stop, stopping) = watch::channel(());
+    node.start(machine.clone(), stopping);
+
+    // One vote per term, for a candidate whose log is not behind.
+    assert_eq!(vote(&node, 1, "b", (0, 0)).await, (1, true));
+    assert_eq!(vote(&node, 1, "c", (0, 0)).await, (1, false));
+    assert_eq!(vote(&node, 1, "b", (0, 0)).await, (1, true));
+
+    let req = AppendRequest {
+        term: 1,
+        leader: "b".into(),
+        prev_log_index: 0,
+        prev_log_term: 0,
+        entries: vec![delete(1, 1, b"x"), delete(1, 2, b"y")],
+        commit: 1,
+    };
+    assert_eq!(append(&node, req).await, (1, true, 2));
+    assert_eq!(vote(&node, 2, "c", (1, 1)).await, (2, false));
+
+    // A gap is refused with a hint where the log ends.
+    let gap = AppendRequest {
+        term: 2,
+        leader: "c".into(),
+        prev_log_index: 5,
+        prev_log_term: 2,
+        entries: vec![delete(2, 6, b"z")],
+        commit: 6,
+    };
+    assert_eq!(append(&node, gap).await, (2, false, 2));
+    // The entry of the new leader replaces the uncommitted one.
+    let conflict = AppendRequest {
+        term: 2,
+        leader: "c".into(),
+        prev_log_index: 1,
+        prev_log_term: 1,
+        entries: vec![delete(2, 2, b"z")],
+        commit: 2,
+    };
+    assert_eq!(append(&node, conflict).await, (2, true, 2));
+    let stale = AppendRequest {
+        term: 1,
+        leader: "b".into(),
+        prev_log_index: 2,
+        prev_log_term: 1,
+        entries: vec![],
+        commit: 2,
+    };
+    assert_eq!(append(&node, stale).await, (2, false, 2));
+
+    match node.propose(command::Op::Noop(Empty {})).await {
+        Err(Error::NotLeader(leader)) => assert_eq!(leader, endpoints[2]),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    tokio::time::timeout(Duration::from_secs(5), node.ready())
+        .await
+        .unwrap();
+    drop(stop);
+    remove(&path);
+    assert_eq!(machine.deleted(), [b"x".to_vec(), b"z".to_vec()]);
+}
+
+#[tokio::test]
+async fn raft_elects_a_leader_and_commits() {
+    let mut listeners = Vec::new();
+    let mut endpoints = Vec::new();
+    for _ in 0..3 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        endpoints.push(format!("http://{}", listener.local_addr().unwrap()));
+        listeners.push(listener);
+    }
+    let (stop, stopping) = watch::channel(());
+    let mut members = Vec::new();
+    for (listener, id) in listeners.into_iter().zip(["a", "b", "c"]) {
+        let path = db(&format!("cluster-{}", id));
+        let node = Node::new(&cluster(id, &endpoints, 300), &path, false).unwrap();
+        let machine = Arc::new(Machine::default());
+        node.start(machine.clone(), stopping.clone());
+        let mut stopped = stopping.clone();
+        let server = Server::builder()
+            .add_service(RaftServer::from_arc(node.clone()))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+                let _ = stopped.changed().await;
+            });
+        tokio::spawn(server);
+        members.push((node, machine, path));
+    }
+
+    let wait = Duration::from_secs(10);
+    for (node, _, _) in &members {
+        tokio::time::timeout(wait, node.ready()).await.unwrap();
+    }
+    let write = command::Op::Delete(Key {
+        key: b"k".to_vec(),
+        namespace: String::new(),
+    });
+    let mut leaders = 0;
+    for (node, _, _) in &members {
+        match node.propose(write.clone()).await {
+            Ok(_) => {
+                leaders += 1;
+                node.read_barrier().await.unwrap();
+            }
+            Err(Error::NotLeader(leader)) => assert!(endpoints.contains(&leader)),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+    assert_eq!(leaders, 1);
+
+    // The followers apply the write once the leader tells them it is committed.
+    let applied = async {
+        while !members
+            .iter()
+            .all(|(_, machine, _)| machine.deleted() == [b"k".to_vec()])
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    };
+    tokio::time::timeout(wait, applied).await.unwrap();
+    drop(stop);
+    for (_, _, path) in &members {
+        remove(path);
+    }
+}
@@ -0,0 +1,35 @@
+//! Config reload unit tests.
+
+use crate::config;
+use crate::logger::Handle;
+use crate::reload::Reloader;
+
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing_subscriber::filter::LevelFilter;
+
+const CONFIG: &str = r#"{
+    "environment": "test",
+    "server": {"endpoint": "[::1]:50051"},
+    "monitoring": {"interval": 60},
+    "logging": LOGGING
+}"#;
+
+#[tokio::test]
+async fn reload_resets_removed_log_level() {
+    let filename = Path::new("/tmp/astrobase-reload.json");
+    std::fs::write(filename, CONFIG.replace("LOGGING", r#"{"level": "debug"}"#)).unwrap();
+    let cfg = config::Astrobase::load(filename).unwrap();
+    let (_layer, logger) = Handle::new(LevelFilter::WARN);
+    logger.set_level(cfg.logging.level.as_deref()).unwrap();
+    assert_eq!(logger.level(), Some(LevelFilter::DEBUG));
+
+    let reloader = Reloader::new(filename.into(), Arc::new(RwLock::new(cfg)), logger.clone());
+    std::fs::write(filename, CONFIG.replace("LOGGING", "{}")).unwrap();
+    let reloaded = reloader.reload().await;
+    std::fs::remove_file(filename).unwrap();
+
+    reloaded.unwrap();
+    assert_eq!(logger.level(), Some(LevelFilter::WARN));
+}
@@ -0,0 +1,112 @@
+//! Replication journal and tracker unit tests.
+
+use crate::config;
+use crate::database::{Database, InMemory};
+use crate::replication::{snapshot, Journal, Tracker};
+
+use astrobase_api::proto::change::Op;
+use astrobase_api::proto::{Batch, Change, Conflict, Key, Position};
+use std::path::Path;
+use tokio::sync::mpsc;
+
+fn delete(key: &[u8]) -> Op {
+    Op::Delete(Key {
+        key: key.to_vec(),
+        namespace: String::new(),
+    })
+}
+
+fn offsets(changes: Option<Vec<Change>>) -> Option<Vec<u64>> {
+    changes.map(|changes| changes.iter().map(|change| change.offset).collect())
+}
+
+#[tokio::test]
+async fn journal_keeps_recent_changes() {
+    let journal = Journal::new(2);
+    let mut log = journal.lock().await;
+    assert_eq!(offsets(log.since(0)), Some(vec![]));
+    for key in [b"a", b"b", b"c"] {
+        log.record(delete(key));
+    }
+
+    assert_eq!(log.offset(), 3);
+    assert_eq!(offsets(log.since(0)), None);
+    assert_eq!(offsets(log.since(1)), Some(vec![2, 3]));
+    assert_eq!(offsets(log.since(3)), Some(vec![]));
+    assert_eq!(offsets(log.since(4)), None);
+    let changes = log.since(2).unwrap();
+    assert_eq!(changes[0].epoch, journal.epoch());
+    assert_eq!(changes[0].op, Some(delete(b"c")));
+}
+
+#[tokio::test]
+async fn journal_snapshot_resets_and_syncs() {
+    let db = InMemory::open(&config::Database::default());
+    db.create_namespace("ns").await.unwrap();
+    for ns in ["default", "ns"] {
+        db.insert(ns, b"k", b"v").await.unwrap();
+    }
+    // Written while the records are read, so it is sent after them.
+    let journal = Journal::new(8);
+    journal.lock().await.record(Op::Batch(Batch {
+        namespace: "ns".into(),
+        conflict: Conflict::InsertOnly as i32,
+        pairs: Vec::new(),
+    }));
+    let (sender, mut receiver) = mpsc::channel(16);
+    let synced = snapshot(&db, &journal, 0, &sender).await.unwrap();
+    drop(sender);
+    let mut changes = Vec::new();
+    while let Some(change) = receiver.recv().await {
+        changes.push(change.unwrap());
+    }
+
+    let ops: Vec<&str> = changes
+        .iter()
+        .map(|change| match change.op.as_ref().unwrap() {
+            Op::Reset(_) => "reset",
+            Op::CreateNamespace(_) => "create",
+            Op::Batch(batch) if batch.pairs.is_empty() => "replayed",
+            Op::Batch(batch) if batch.namespace == "ns" => "batch ns",
+            Op::Batch(_) => "batch",
+            Op::Synced(_) => "synced",
+            _ => "other",
+        })
+        .collect();
+    assert_eq!(
+        ops,
+        ["reset", "batch", "create", "batch ns", "replayed", "synced"]
+    );
+    assert!(changes.iter().all(|change| change.epoch == journal.epoch()));
+    let offsets: Vec<u64> = changes.iter().map(|change| change.offset).collect();
+    assert_eq!(offsets, [0, 0, 0, 0, 0, 1]);
+    assert_eq!(synced, 1);
+    match changes[4].op.as_ref().unwrap() {
+        Op::Batch(batch) => assert_eq!(batch.conflict, Conflict::Overwrite as i32),
+        op => panic!("unexpected change: {:?}", op),
+    }
+}
+
+#[test]
+fn tracker_keeps_position_in_file() {
+    let file = Path::new("/tmp/astrobase-tracker.position");
+    let _ = std::fs::remove_file(file);
+    let mut tracker = Tracker::new(Some(file.into()));
+    assert_eq!(tracker.position, Position::default());
+
+    let position = Position {
+        epoch: 5,
+        offset: 9,
+    };
+    tracker.set(position.clone()).unwrap();
+    let reloaded = Tracker::new(Some(file.into())).position;
+    std::fs::write(file, "5 x\n").unwrap();
+    let broken = Tracker::new(Some(file.into())).position;
+    std::fs::remove_file(file).unwrap();
+
+    assert_eq!(reloaded, position);
+    assert_eq!(broken, Position::default());
+    let mut memory = Tracker::new(None);
+    memory.set(position.clone()).unwrap();
+    assert_eq!(memory.position, position);
+}
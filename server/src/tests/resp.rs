@@ -0,0 +1,83 @@
+//! Redis protocol parser unit tests.
+
+use crate::resp::{matches, read_command, Error};
+
+use astrobase_api::MAX_VALUE_LEN;
+
+async fn read(mut input: &[u8]) -> Result<Vec<Vec<Vec<u8>>>, Error> {
+    let mut commands = Vec::new();
+    while let Some(args) = read_command(&mut input).await? {
+        commands.push(args);
+    }
+    Ok(commands)
+}
+
+fn protocol_error(result: Result<Vec<Vec<Vec<u8>>>, Error>) -> &'static str {
+    match result {
+        Err(Error::Protocol(message)) => message,
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(commands) => panic!("unexpected commands: {:?}", commands),
+    }
+}
+
+#[tokio::test]
+async fn resp_reads_arrays_and_inline_commands() {
+    let input = b"*2\r\n$3\r\nGET\r\n$4\r\nk\r\ny\r\nPING  hello\r\n\r\nQUIT\n";
+    let commands = read(input).await.unwrap();
+    assert_eq!(
+        commands,
+        [
+            vec![b"GET".to_vec(), b"k\r\ny".to_vec()],
+            vec![b"PING".to_vec(), b"hello".to_vec()],
+            vec![],
+            vec![b"QUIT".to_vec()],
+        ]
+    );
+}
+
+#[tokio::test]
+async fn resp_rejects_truncated_frames() {
+    let err = read(b"*2\r\n$3\r\nGET\r\n").await;
+    assert_eq!(protocol_error(err), "unexpected end of stream");
+    match read(b"*1\r\n$5\r\nGET\r\n").await {
+        Err(Error::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    assert_eq!(
+        protocol_error(read(b"*1\r\n$3\r\nGETX\r\n").await),
+        "expected CRLF"
+    );
+    assert_eq!(protocol_error(read(b"*1\r\n:3\r\n").await), "expected '$'");
+    assert_eq!(protocol_error(read(b"*x\r\n").await), "invalid length");
+}
+
+#[tokio::test]
+async fn resp_rejects_oversized_frames() {
+    let bulk = MAX_VALUE_LEN + 1024;
+    let err = read(format!("*1\r\n${}\r\n", bulk + 1).as_bytes()).await;
+    assert_eq!(protocol_error(err), "too big request");
+    assert_eq!(protocol_error(read(b"*1025\r\n").await), "too big request");
+
+    // Every argument may be as big as a value, all of them together may not.
+    let mut frame = format!("*3\r\n${}\r\n", bulk).into_bytes();
+    frame.extend(std::iter::repeat(b'v').take(bulk));
+    frame.extend_from_slice(format!("\r\n${}\r\n", bulk).as_bytes());
+    assert_eq!(protocol_error(read(&frame).await), "too big request");
+    let inline = vec![b'a'; 64 * 1024 + 1];
+    assert_eq!(
+        protocol_error(read(&inline).await),
+        "too big inline request"
+    );
+}
+
+#[test]
+fn resp_matches_glob_patterns() {
+    assert!(matches(b"*", b""));
+    assert!(matches(b"red*", b"reddish"));
+    assert!(matches(b"r?d", b"rad"));
+    assert!(matches(b"*a*b", b"xaxxab"));
+    assert!(matches(b"a\\*", b"a*"));
+    assert!(!matches(b"a\\*", b"ab"));
+    assert!(!matches(b"red*", b"blue"));
+    assert!(!matches(b"r?d", b"rd"));
+}
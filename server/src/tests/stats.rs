@@ -0,0 +1,29 @@
+//! Statistics unit tests.
+
+use crate::stats::Stats;
+
+fn records(stats: &Stats) -> usize {
+    let report = stats.report();
+    let (_, records) = report
+        .total
+        .iter()
+        .find(|(name, _)| *name == "records")
+        .unwrap();
+    *records
+}
+
+#[test]
+fn stats_count_records_apart_from_requests() {
+    let mut stats = Stats::default();
+    stats.create_namespace("default");
+    stats.records("default", 3, 0);
+    stats.insert("default", true);
+    stats.delete("default", true);
+    assert_eq!(records(&stats), 3);
+
+    stats.records("default", 0, 1);
+    assert_eq!(records(&stats), 2);
+    // A server which missed some writes never wraps around.
+    stats.records("default", 0, 5);
+    assert_eq!(records(&stats), 0);
+}
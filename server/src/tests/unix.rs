@@ -0,0 +1,36 @@
+//! Unix domain socket listener unit tests.
+
+use crate::config;
+use crate::server::{bind_unix, remove_socket};
+
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::Path;
+
+#[tokio::test]
+async fn unix_socket_file_lifecycle() {
+    let path = Path::new("/tmp/astrobase-unit.sock");
+    let _ = std::fs::remove_file(path);
+    let unix = config::Unix {
+        path: path.into(),
+        mode: Some("600".into()),
+    };
+
+    // The socket of a run which did not stop cleanly is replaced.
+    drop(std::os::unix::net::UnixListener::bind(path).unwrap());
+    let listener = bind_unix(&unix).unwrap();
+    let mode = std::fs::metadata(path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o600);
+    let connected = tokio::net::UnixStream::connect(path).await;
+    assert!(connected.is_ok());
+    drop(listener);
+    remove_socket(path);
+    assert!(!path.exists());
+
+    // Anything else at the path is left alone.
+    std::fs::write(path, b"data").unwrap();
+    let err = bind_unix(&unix).unwrap_err();
+    let kept = std::fs::read(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert!(err.to_string().contains("is not a socket"), "{}", err);
+    assert_eq!(kept, b"data");
+}